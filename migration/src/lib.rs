@@ -4,6 +4,19 @@ mod m20220101_000001_create_table_account;
 mod m20230916_170023_create_table_printfile;
 mod m20231007_204738_alter_printfile_add_size;
 mod m20231124_134301_create_table_agent;
+mod m20261018_093000_create_table_command_policy;
+mod m20261018_110000_create_table_printer;
+mod m20261018_120000_create_table_print_job;
+mod m20261018_130000_create_table_print_queue;
+mod m20261018_130100_alter_printer_add_bed_cleared;
+mod m20261018_140000_alter_agent_hash_token;
+mod m20261018_150000_alter_agent_add_handshake;
+mod m20261018_160000_create_table_printfile_metadata;
+mod m20261018_170000_create_table_printfile_thumbnail;
+mod m20261018_180000_create_table_file_type_policy;
+mod m20261018_190000_alter_printfile_metadata_add_mesh;
+mod m20261018_200000_create_table_storage_deletion;
+mod m20261018_210000_alter_printfile_size_big_integer;
 
 pub struct Migrator;
 
@@ -15,6 +28,19 @@ impl MigratorTrait for Migrator {
             Box::new(m20230916_170023_create_table_printfile::Migration),
             Box::new(m20231007_204738_alter_printfile_add_size::Migration),
             Box::new(m20231124_134301_create_table_agent::Migration),
+            Box::new(m20261018_093000_create_table_command_policy::Migration),
+            Box::new(m20261018_110000_create_table_printer::Migration),
+            Box::new(m20261018_120000_create_table_print_job::Migration),
+            Box::new(m20261018_130000_create_table_print_queue::Migration),
+            Box::new(m20261018_130100_alter_printer_add_bed_cleared::Migration),
+            Box::new(m20261018_140000_alter_agent_hash_token::Migration),
+            Box::new(m20261018_150000_alter_agent_add_handshake::Migration),
+            Box::new(m20261018_160000_create_table_printfile_metadata::Migration),
+            Box::new(m20261018_170000_create_table_printfile_thumbnail::Migration),
+            Box::new(m20261018_180000_create_table_file_type_policy::Migration),
+            Box::new(m20261018_190000_alter_printfile_metadata_add_mesh::Migration),
+            Box::new(m20261018_200000_create_table_storage_deletion::Migration),
+            Box::new(m20261018_210000_alter_printfile_size_big_integer::Migration),
         ]
     }
 }
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(CommandPolicy::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(CommandPolicy::UserUuid).string().not_null().primary_key())
+                    .col(ColumnDef::new(CommandPolicy::Mode).string().not_null())
+                    .col(ColumnDef::new(CommandPolicy::Commands).text().not_null())
+                    .col(ColumnDef::new(CommandPolicy::UpdatedAt).string().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(CommandPolicy::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum CommandPolicy {
+    Table,
+    UserUuid,
+    Mode,
+    Commands,
+    UpdatedAt,
+}
@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Printer::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(Printer::Uuid).string().not_null().primary_key())
+                    .col(ColumnDef::new(Printer::UserUuid).string().not_null())
+                    .col(ColumnDef::new(Printer::AgentUuid).string().not_null())
+                    .col(ColumnDef::new(Printer::Identifier).string().not_null())
+                    .col(ColumnDef::new(Printer::AdapterIdentifier).string().not_null())
+                    .col(ColumnDef::new(Printer::AdapterInterface).string().not_null())
+                    .col(ColumnDef::new(Printer::CreatedAt).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_printer_agent_uuid")
+                    .table(Printer::Table)
+                    .col(Printer::AgentUuid)
+                    .to_owned(),
+            )
+            .await?;
+
+        // an agent has one printer per adapter, concurrent discoveries can't store it twice
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_printer_agent_adapter")
+                    .table(Printer::Table)
+                    .col(Printer::AgentUuid)
+                    .col(Printer::AdapterIdentifier)
+                    .col(Printer::AdapterInterface)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Printer::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Printer {
+    Table,
+    Uuid,
+    UserUuid,
+    AgentUuid,
+    Identifier,
+    AdapterIdentifier,
+    AdapterInterface,
+    CreatedAt,
+}
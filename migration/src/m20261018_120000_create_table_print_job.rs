@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PrintJob::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(PrintJob::Uuid).string().not_null().primary_key())
+                    .col(ColumnDef::new(PrintJob::UserUuid).string().not_null())
+                    .col(ColumnDef::new(PrintJob::AgentUuid).string().not_null())
+                    .col(ColumnDef::new(PrintJob::PrinterUuid).string().not_null())
+                    .col(ColumnDef::new(PrintJob::PrintFileUuid).string().not_null())
+                    .col(ColumnDef::new(PrintJob::State).string().not_null())
+                    .col(ColumnDef::new(PrintJob::Progress).double().not_null().default(0.0))
+                    .col(ColumnDef::new(PrintJob::CreatedAt).string().not_null())
+                    .col(ColumnDef::new(PrintJob::StartedAt).string().null())
+                    .col(ColumnDef::new(PrintJob::FinishedAt).string().null())
+                    .col(ColumnDef::new(PrintJob::UpdatedAt).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_print_job_printer_uuid")
+                    .table(PrintJob::Table)
+                    .col(PrintJob::PrinterUuid)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PrintJob::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PrintJob {
+    Table,
+    Uuid,
+    UserUuid,
+    AgentUuid,
+    PrinterUuid,
+    PrintFileUuid,
+    State,
+    Progress,
+    CreatedAt,
+    StartedAt,
+    FinishedAt,
+    UpdatedAt,
+}
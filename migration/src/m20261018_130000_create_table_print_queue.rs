@@ -0,0 +1,51 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PrintQueue::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(PrintQueue::Uuid).string().not_null().primary_key())
+                    .col(ColumnDef::new(PrintQueue::UserUuid).string().not_null())
+                    .col(ColumnDef::new(PrintQueue::PrintFileUuid).string().not_null())
+                    .col(ColumnDef::new(PrintQueue::PrinterUuids).text().not_null())
+                    .col(ColumnDef::new(PrintQueue::Position).integer().not_null())
+                    .col(ColumnDef::new(PrintQueue::CreatedAt).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_print_queue_user_uuid")
+                    .table(PrintQueue::Table)
+                    .col(PrintQueue::UserUuid)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PrintQueue::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PrintQueue {
+    Table,
+    Uuid,
+    UserUuid,
+    PrintFileUuid,
+    PrinterUuids,
+    Position,
+    CreatedAt,
+}
@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // agents keep their token, only its SHA-256 hash is stored from now on
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Agent::Table)
+                    .value(Agent::Token, Expr::cust("SHA2(`token`, 256)"))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_agent_token")
+                    .table(Agent::Table)
+                    .col(Agent::Token)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // hashed tokens can't be restored, agents need a rotated token after rolling back
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_agent_token")
+                    .table(Agent::Table)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Agent {
+    Table,
+    Token,
+}
@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Agent::Table)
+                    .add_column(ColumnDef::new(Agent::Version).string().null())
+                    .add_column(ColumnDef::new(Agent::ProtocolVersion).integer().null())
+                    .add_column(ColumnDef::new(Agent::Os).string().null())
+                    .add_column(ColumnDef::new(Agent::Arch).string().null())
+                    .add_column(ColumnDef::new(Agent::Capabilities).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Agent::Table)
+                    .drop_column(Agent::Version)
+                    .drop_column(Agent::ProtocolVersion)
+                    .drop_column(Agent::Os)
+                    .drop_column(Agent::Arch)
+                    .drop_column(Agent::Capabilities)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Agent {
+    Table,
+    Version,
+    ProtocolVersion,
+    Os,
+    Arch,
+    Capabilities,
+}
@@ -0,0 +1,66 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PrintFileMetadata::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PrintFileMetadata::PrintFileUuid)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PrintFileMetadata::Slicer).string().null())
+                    .col(ColumnDef::new(PrintFileMetadata::SlicerVersion).string().null())
+                    .col(ColumnDef::new(PrintFileMetadata::EstimatedTime).big_integer().null())
+                    .col(ColumnDef::new(PrintFileMetadata::FilamentLength).string().not_null())
+                    .col(ColumnDef::new(PrintFileMetadata::FilamentWeight).string().not_null())
+                    .col(ColumnDef::new(PrintFileMetadata::LayerHeight).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::LayerCount).integer().null())
+                    .col(ColumnDef::new(PrintFileMetadata::NozzleTemperature).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::BedTemperature).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::MinX).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::MinY).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::MinZ).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::MaxX).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::MaxY).double().null())
+                    .col(ColumnDef::new(PrintFileMetadata::MaxZ).double().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PrintFileMetadata::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PrintFileMetadata {
+    Table,
+    PrintFileUuid,
+    Slicer,
+    SlicerVersion,
+    EstimatedTime,
+    FilamentLength,
+    FilamentWeight,
+    LayerHeight,
+    LayerCount,
+    NozzleTemperature,
+    BedTemperature,
+    MinX,
+    MinY,
+    MinZ,
+    MaxX,
+    MaxY,
+    MaxZ,
+}
@@ -0,0 +1,51 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PrintFileThumbnail::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(PrintFileThumbnail::Uuid).string().not_null().primary_key())
+                    .col(ColumnDef::new(PrintFileThumbnail::PrintFileUuid).string().not_null())
+                    .col(ColumnDef::new(PrintFileThumbnail::Width).integer().not_null())
+                    .col(ColumnDef::new(PrintFileThumbnail::Height).integer().not_null())
+                    .col(ColumnDef::new(PrintFileThumbnail::Format).string().not_null())
+                    .col(ColumnDef::new(PrintFileThumbnail::Path).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_print_file_thumbnail_print_file_uuid")
+                    .table(PrintFileThumbnail::Table)
+                    .col(PrintFileThumbnail::PrintFileUuid)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PrintFileThumbnail::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PrintFileThumbnail {
+    Table,
+    Uuid,
+    PrintFileUuid,
+    Width,
+    Height,
+    Format,
+    Path,
+}
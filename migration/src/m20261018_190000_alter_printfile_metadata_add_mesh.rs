@@ -0,0 +1,44 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PrintFileMetadata::Table)
+                    .add_column(ColumnDef::new(PrintFileMetadata::TriangleCount).big_integer().null())
+                    .add_column(ColumnDef::new(PrintFileMetadata::Volume).double().null())
+                    .add_column(ColumnDef::new(PrintFileMetadata::SurfaceArea).double().null())
+                    .add_column(ColumnDef::new(PrintFileMetadata::Watertight).boolean().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PrintFileMetadata::Table)
+                    .drop_column(PrintFileMetadata::TriangleCount)
+                    .drop_column(PrintFileMetadata::Volume)
+                    .drop_column(PrintFileMetadata::SurfaceArea)
+                    .drop_column(PrintFileMetadata::Watertight)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PrintFileMetadata {
+    Table,
+    TriangleCount,
+    Volume,
+    SurfaceArea,
+    Watertight,
+}
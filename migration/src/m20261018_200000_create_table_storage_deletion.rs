@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(StorageDeletion::Table)
+                    .if_not_exists()
+                    .col(ColumnDef::new(StorageDeletion::Uuid).string().not_null().primary_key())
+                    .col(ColumnDef::new(StorageDeletion::Path).string().not_null())
+                    .col(ColumnDef::new(StorageDeletion::FileStorageType).string().not_null())
+                    .col(ColumnDef::new(StorageDeletion::Attempts).integer().not_null().default(0))
+                    .col(ColumnDef::new(StorageDeletion::LastError).string().null())
+                    .col(ColumnDef::new(StorageDeletion::NextAttemptAt).big_integer().not_null())
+                    .col(ColumnDef::new(StorageDeletion::CreatedAt).string().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_storage_deletion_next_attempt_at")
+                    .table(StorageDeletion::Table)
+                    .col(StorageDeletion::NextAttemptAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(StorageDeletion::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum StorageDeletion {
+    Table,
+    Uuid,
+    Path,
+    FileStorageType,
+    Attempts,
+    LastError,
+    NextAttemptAt,
+    CreatedAt,
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // uploads can be larger than an INT can hold
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PrintFile::Table)
+                    .modify_column(ColumnDef::new(PrintFile::Size).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(PrintFile::Table)
+                    .modify_column(ColumnDef::new(PrintFile::Size).integer().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PrintFile {
+    Table,
+    Size,
+}
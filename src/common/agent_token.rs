@@ -0,0 +1,26 @@
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+/// Generates a new plaintext agent token, it is shown to the user once and never stored
+pub fn generate_token() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Hashes an agent token, only the hash is stored and compared
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_token() {
+        let token = generate_token();
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_eq!(hash_token(&token).len(), 64);
+        assert_ne!(hash_token(&token), token);
+        assert_ne!(hash_token(&token), hash_token(&generate_token()));
+    }
+}
@@ -37,6 +37,12 @@ pub enum AppError {
     #[error("{message:}")]
     Agent { message: String, status: StatusCode },
 
+    #[error("{message:}")]
+    Printer { message: String, status: StatusCode },
+
+    #[error("{message:}")]
+    PrintJob { message: String, status: StatusCode },
+
     #[error("{messages:}")]
     Validation {
         messages: String,
@@ -47,11 +53,13 @@ pub enum AppError {
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = match self {
-            AppError::InternalServer { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::InternalServer => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Auth { status, .. } => status,
             AppError::Token { status, .. } => status,
             AppError::PrintFile { status, .. } => status,
             AppError::Agent { status, .. } => status,
+            AppError::Printer { status, .. } => status,
+            AppError::PrintJob { status, .. } => status,
             AppError::Validation { status, .. } => status,
             AppError::User { status, .. } => status,
         };
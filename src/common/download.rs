@@ -0,0 +1,178 @@
+/// Inclusive byte range of a file, e.g. "bytes=0-99" -> ByteRange { start: 0, end: 99 }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value of the Content-Range header
+    pub fn content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+/// The requested range lies outside of the file
+#[derive(Debug, PartialEq)]
+pub struct RangeNotSatisfiable;
+
+/// Parses the Range header of a request for a file of the given size
+/// - Returns None if the header can't be parsed or requests several ranges, the whole file is sent
+/// - Returns RangeNotSatisfiable if the range starts behind the end of the file
+/// - The end of the range is clamped to the end of the file
+pub fn parse_range(header: &str, size: u64) -> Result<Option<ByteRange>, RangeNotSatisfiable> {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+
+    // suffix range, e.g. "bytes=-500" are the last 500 bytes
+    if start.is_empty() {
+        return match end.parse::<u64>() {
+            Ok(0) => Err(RangeNotSatisfiable),
+            Ok(_) if size == 0 => Err(RangeNotSatisfiable),
+            Ok(length) => Ok(Some(ByteRange {
+                start: size.saturating_sub(length),
+                end: size - 1,
+            })),
+            Err(_) => Ok(None),
+        };
+    }
+
+    let start = match start.parse::<u64>() {
+        Ok(start) => start,
+        Err(_) => return Ok(None),
+    };
+    let end = match end {
+        "" => u64::MAX,
+        end => match end.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return Ok(None),
+        },
+    };
+    if start >= size {
+        return Err(RangeNotSatisfiable);
+    }
+
+    Ok(Some(ByteRange {
+        start,
+        end: end.min(size - 1),
+    }))
+}
+
+/// Strong ETag of a file, derived from its sha256 checksum
+pub fn etag(checksum: &str) -> String {
+    format!("\"{}\"", checksum)
+}
+
+/// Checks an If-None-Match header against the ETag of a file, weak ETags match as well
+pub fn matches_etag(header: &str, etag: &str) -> bool {
+    header.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+/// Value of the Content-Disposition header that makes clients save the file under its name.
+/// Clients that don't support filename* fall back to the name with non ASCII characters replaced
+pub fn content_disposition(filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+
+    let mut encoded = String::new();
+    for byte in filename.bytes() {
+        match byte {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        let range = |start, end| Ok(Some(ByteRange { start, end }));
+
+        assert_eq!(parse_range("bytes=0-99", 1000), range(0, 99));
+        assert_eq!(parse_range("bytes=500-", 1000), range(500, 999));
+        assert_eq!(parse_range("bytes=-100", 1000), range(900, 999));
+        assert_eq!(parse_range("bytes=-2000", 1000), range(0, 999));
+        assert_eq!(parse_range("bytes=900-5000", 1000), range(900, 999));
+        assert_eq!(parse_range("bytes=5-5", 1000), range(5, 5));
+
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(RangeNotSatisfiable));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(RangeNotSatisfiable));
+        assert_eq!(parse_range("bytes=0-", 0), Err(RangeNotSatisfiable));
+
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=99-0", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=abc", 1000), Ok(None));
+        assert_eq!(parse_range("lines=0-10", 1000), Ok(None));
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let range = ByteRange {
+            start: 100,
+            end: 199,
+        };
+        assert_eq!(range.length(), 100);
+        assert_eq!(range.content_range(1000), "bytes 100-199/1000");
+    }
+
+    #[test]
+    fn test_matches_etag() {
+        let etag = etag("abc");
+        assert_eq!(etag, "\"abc\"");
+        assert!(matches_etag("\"abc\"", &etag));
+        assert!(matches_etag("\"xyz\", W/\"abc\"", &etag));
+        assert!(matches_etag("*", &etag));
+        assert!(!matches_etag("\"xyz\"", &etag));
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("part.gcode"),
+            "attachment; filename=\"part.gcode\"; filename*=UTF-8''part.gcode"
+        );
+        assert_eq!(
+            content_disposition("Würfel \"1\".stl"),
+            "attachment; filename=\"W_rfel _1_.stl\"; filename*=UTF-8''W%C3%BCrfel%20%221%22.stl"
+        );
+    }
+}
@@ -0,0 +1,807 @@
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Extracts the normalized command word of a single G-code line, e.g. "N10 g0028 X10*71" -> "G28"
+/// - Line numbers, checksums and comments are ignored
+/// - Returns None if the line contains no command, a second G or M command or more than one line.
+///   T words after the command are parameters, e.g. "M104 T1 S200"
+pub fn command_word(line: &str) -> Option<String> {
+    if line.contains('\n') || line.contains('\r') {
+        return None;
+    }
+
+    // strip comments and the checksum
+    let line = line.split(';').next().unwrap_or_default();
+    let line = line.split('*').next().unwrap_or_default();
+    let line = match line.find('(') {
+        Some(index) => &line[..index],
+        None => line,
+    };
+
+    let mut words = line.split_whitespace();
+    let mut word = words.next()?.to_uppercase();
+    if word.starts_with('N') {
+        word = words.next()?.to_uppercase();
+    }
+
+    let mut chars = word.chars();
+    let letter = chars.next()?;
+    if !matches!(letter, 'G' | 'M' | 'T') {
+        return None;
+    }
+    // firmware may run the other commands of the line too, only the first one would be checked
+    let mut parameters = words.map(|word| word.to_uppercase());
+    if parameters.any(|word| {
+        let mut chars = word.chars();
+        matches!(chars.next(), Some('G' | 'M')) && chars.next().is_some_and(|c| c.is_ascii_digit())
+    }) {
+        return None;
+    }
+
+    let number = chars.as_str();
+    let (integer, fraction) = match number.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (number, None),
+    };
+    let integer: u32 = integer.parse().ok()?;
+
+    match fraction {
+        Some(fraction) if !fraction.is_empty() && fraction.chars().all(|c| c.is_ascii_digit()) => {
+            Some(format!("{}{}.{}", letter, integer, fraction))
+        }
+        Some(_) => None,
+        None => Some(format!("{}{}", letter, integer)),
+    }
+}
+
+/// Slicer metadata of a G-code file, read from the comments slicers write into the file.
+/// Values that are missing from the comments are derived from the commands where possible
+/// - estimated_time: Estimated print time in seconds
+/// - filament_length, filament_weight: Used filament per extruder, in mm and g
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct GcodeMetadata {
+    pub slicer: Option<String>,
+    pub slicer_version: Option<String>,
+    pub estimated_time: Option<u64>,
+    pub filament_length: Vec<f64>,
+    pub filament_weight: Vec<f64>,
+    pub layer_height: Option<f64>,
+    pub layer_count: Option<u32>,
+    pub nozzle_temperature: Option<f64>,
+    pub bed_temperature: Option<f64>,
+    pub bounding_box: Option<BoundingBox>,
+}
+
+/// Bounding box of the printed object in mm
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub min_z: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+    pub max_z: f64,
+}
+
+impl BoundingBox {
+    fn point(x: f64, y: f64, z: f64) -> Self {
+        BoundingBox {
+            min_x: x,
+            min_y: y,
+            min_z: z,
+            max_x: x,
+            max_y: y,
+            max_z: z,
+        }
+    }
+
+    fn include(&mut self, x: f64, y: f64, z: f64) {
+        self.min_x = self.min_x.min(x);
+        self.min_y = self.min_y.min(y);
+        self.min_z = self.min_z.min(z);
+        self.max_x = self.max_x.max(x);
+        self.max_y = self.max_y.max(y);
+        self.max_z = self.max_z.max(z);
+    }
+}
+
+/// Position of the toolhead while walking through the moves of a file
+struct Toolhead {
+    x: f64,
+    y: f64,
+    z: f64,
+    e: f64,
+    absolute: bool,
+    absolute_e: bool,
+}
+
+/// Values derived from the commands, only used when the comments don't contain them
+#[derive(Default)]
+struct Derived {
+    layer_changes: u32,
+    nozzle_temperature: Option<f64>,
+    bed_temperature: Option<f64>,
+    bounding_box: Option<BoundingBox>,
+    cura_box: [Option<f64>; 6],
+}
+
+/// Longest line that is parsed, longer lines can only appear in files that aren't G-code
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Parses the metadata and thumbnails of a G-code file that arrives in chunks,
+/// only the current line is kept in memory
+pub struct GcodeScanner {
+    metadata: MetadataParser,
+    thumbnails: ThumbnailParser,
+    partial: Vec<u8>,
+    overlong: bool,
+}
+
+impl Default for GcodeScanner {
+    fn default() -> Self {
+        GcodeScanner::new()
+    }
+}
+
+impl GcodeScanner {
+    pub fn new() -> Self {
+        GcodeScanner {
+            metadata: MetadataParser::new(),
+            thumbnails: ThumbnailParser::default(),
+            partial: Vec::new(),
+            overlong: false,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let mut lines = chunk.split(|byte| *byte == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            if lines.peek().is_none() {
+                // the last part continues in the next chunk
+                self.append(line);
+                break;
+            }
+            self.append(line);
+            self.end_line();
+        }
+    }
+
+    fn append(&mut self, bytes: &[u8]) {
+        if self.partial.len() + bytes.len() > MAX_LINE_LENGTH {
+            self.partial.clear();
+            self.overlong = true;
+        } else if !self.overlong {
+            self.partial.extend_from_slice(bytes);
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.overlong {
+            let line = String::from_utf8_lossy(&self.partial);
+            self.metadata.line(&line);
+            self.thumbnails.line(&line);
+        }
+        self.partial.clear();
+        self.overlong = false;
+    }
+
+    pub fn finish(mut self) -> (GcodeMetadata, Vec<GcodeThumbnail>) {
+        self.end_line();
+        (self.metadata.finish(), self.thumbnails.thumbnails)
+    }
+}
+
+/// Parses the slicer metadata of a G-code file, supports the comment formats of
+/// PrusaSlicer, SuperSlicer, OrcaSlicer and Cura
+struct MetadataParser {
+    metadata: GcodeMetadata,
+    derived: Derived,
+    toolhead: Toolhead,
+}
+
+impl MetadataParser {
+    fn new() -> Self {
+        MetadataParser {
+            metadata: GcodeMetadata::default(),
+            derived: Derived::default(),
+            toolhead: Toolhead {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+                e: 0.0,
+                absolute: true,
+                absolute_e: true,
+            },
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let line = line.trim();
+        match line.strip_prefix(';') {
+            Some(comment) => {
+                // OrcaSlicer writes several values on one comment line
+                for part in comment.split(';') {
+                    parse_comment(part.trim(), &mut self.metadata, &mut self.derived);
+                }
+            }
+            None => parse_command(line, &mut self.toolhead, &mut self.derived),
+        }
+    }
+
+    fn finish(self) -> GcodeMetadata {
+        let (mut metadata, derived) = (self.metadata, self.derived);
+        if metadata.layer_count.is_none() && derived.layer_changes > 0 {
+            metadata.layer_count = Some(derived.layer_changes);
+        }
+        if metadata.nozzle_temperature.is_none() {
+            metadata.nozzle_temperature = derived.nozzle_temperature;
+        }
+        if metadata.bed_temperature.is_none() {
+            metadata.bed_temperature = derived.bed_temperature;
+        }
+        metadata.bounding_box = match derived.cura_box {
+            [Some(min_x), Some(min_y), Some(min_z), Some(max_x), Some(max_y), Some(max_z)] => {
+                Some(BoundingBox {
+                    min_x,
+                    min_y,
+                    min_z,
+                    max_x,
+                    max_y,
+                    max_z,
+                })
+            }
+            _ => derived.bounding_box,
+        };
+
+        metadata
+    }
+}
+
+fn parse_comment(comment: &str, metadata: &mut GcodeMetadata, derived: &mut Derived) {
+    if comment == "LAYER_CHANGE" || comment.starts_with("LAYER:") {
+        derived.layer_changes += 1;
+        return;
+    }
+
+    let lowercase = comment.to_lowercase();
+    if let Some(generator) = lowercase.strip_prefix("generated by ") {
+        // "generated by PrusaSlicer 2.6.0+win64 on 2023-09-01 at 10:00:00 UTC"
+        let mut words = comment[comment.len() - generator.len()..].split_whitespace();
+        metadata.slicer = words.next().map(|slicer| slicer.to_string());
+        metadata.slicer_version = words.next().map(|version| version.to_string());
+        return;
+    }
+    if let Some(generator) = lowercase.strip_prefix("generated with ") {
+        // "Generated with Cura_SteamEngine 5.4.0"
+        let mut words = comment[comment.len() - generator.len()..].split_whitespace();
+        metadata.slicer = words
+            .next()
+            .map(|slicer| slicer.trim_end_matches("_SteamEngine").to_string());
+        metadata.slicer_version = words.next().map(|version| version.to_string());
+        return;
+    }
+
+    let (key, value) = match comment.split_once('=').or_else(|| comment.split_once(':')) {
+        Some((key, value)) => (key.trim(), value.trim()),
+        None => return,
+    };
+
+    match key.to_lowercase().as_str() {
+        "estimated printing time (normal mode)" | "total estimated time" => {
+            metadata.estimated_time = parse_duration(value)
+        }
+        "time" => metadata.estimated_time = value.parse::<f64>().ok().map(|time| time as u64),
+        "filament used [mm]" => metadata.filament_length = parse_list(value, ""),
+        "filament used [g]" => metadata.filament_weight = parse_list(value, ""),
+        // Cura reports meters
+        "filament used" => {
+            metadata.filament_length = parse_list(value, "m")
+                .into_iter()
+                .map(|meters| meters * 1000.0)
+                .collect()
+        }
+        "layer_height" | "layer height" => metadata.layer_height = value.parse().ok(),
+        "layer_count" | "total layer number" | "total layers count" => {
+            metadata.layer_count = value.parse().ok()
+        }
+        "temperature" | "nozzle_temperature" => {
+            metadata.nozzle_temperature = parse_list(value, "").first().copied()
+        }
+        "bed_temperature" | "hot_plate_temp" => {
+            metadata.bed_temperature = parse_list(value, "").first().copied()
+        }
+        "minx" => derived.cura_box[0] = value.parse().ok(),
+        "miny" => derived.cura_box[1] = value.parse().ok(),
+        "minz" => derived.cura_box[2] = value.parse().ok(),
+        "maxx" => derived.cura_box[3] = value.parse().ok(),
+        "maxy" => derived.cura_box[4] = value.parse().ok(),
+        "maxz" => derived.cura_box[5] = value.parse().ok(),
+        _ => {}
+    }
+}
+
+/// Follows the moves to derive the bounding box of the extruded material,
+/// and remembers the first temperatures that are set
+fn parse_command(line: &str, toolhead: &mut Toolhead, derived: &mut Derived) {
+    let command = match command_word(line) {
+        Some(command) => command,
+        None => return,
+    };
+
+    match command.as_str() {
+        "G0" | "G1" => {
+            let (start_x, start_y, start_z) = (toolhead.x, toolhead.y, toolhead.z);
+            let axis = |letter: char, current: f64| match parameter(line, letter) {
+                Some(value) if toolhead.absolute => value,
+                Some(value) => current + value,
+                None => current,
+            };
+            let (x, y, z) = (
+                axis('X', toolhead.x),
+                axis('Y', toolhead.y),
+                axis('Z', toolhead.z),
+            );
+
+            let extruding = match parameter(line, 'E') {
+                Some(e) if toolhead.absolute_e => {
+                    let extruding = e > toolhead.e;
+                    toolhead.e = e;
+                    extruding
+                }
+                Some(e) => e > 0.0,
+                None => false,
+            };
+            toolhead.x = x;
+            toolhead.y = y;
+            toolhead.z = z;
+
+            if extruding && (x, y) != (start_x, start_y) {
+                let bounding_box = derived
+                    .bounding_box
+                    .get_or_insert(BoundingBox::point(start_x, start_y, start_z));
+                bounding_box.include(start_x, start_y, start_z);
+                bounding_box.include(x, y, z);
+            }
+        }
+        "G90" => {
+            toolhead.absolute = true;
+            toolhead.absolute_e = true;
+        }
+        "G91" => {
+            toolhead.absolute = false;
+            toolhead.absolute_e = false;
+        }
+        "M82" => toolhead.absolute_e = true,
+        "M83" => toolhead.absolute_e = false,
+        "G92" => {
+            if let Some(e) = parameter(line, 'E') {
+                toolhead.e = e;
+            }
+        }
+        "M104" | "M109" if derived.nozzle_temperature.is_none() => {
+            derived.nozzle_temperature = parameter(line, 'S').filter(|s| *s > 0.0)
+        }
+        "M140" | "M190" if derived.bed_temperature.is_none() => {
+            derived.bed_temperature = parameter(line, 'S').filter(|s| *s > 0.0)
+        }
+        _ => {}
+    }
+}
+
+/// Reads the value of a parameter of a command line, e.g. the X of "G1 X10 Y20"
+fn parameter(line: &str, letter: char) -> Option<f64> {
+    let line = line.split(';').next().unwrap_or_default();
+    line.split_whitespace().skip(1).find_map(|word| {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) if first.to_ascii_uppercase() == letter => chars.as_str().parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+/// Parses a comma separated list of numbers, e.g. "1234.5, 12.3" or "1.2m, 0.5m"
+fn parse_list(value: &str, unit: &str) -> Vec<f64> {
+    value
+        .split(',')
+        .filter_map(|item| item.trim().trim_end_matches(unit).trim().parse().ok())
+        .collect()
+}
+
+/// Parses a duration like "1d 2h 3m 4s" into seconds
+fn parse_duration(value: &str) -> Option<u64> {
+    let mut seconds = 0;
+    let mut parsed = false;
+    for part in value.split_whitespace() {
+        let (number, unit) = part.split_at(part.find(|c: char| !c.is_ascii_digit())?);
+        let number: u64 = number.parse().ok()?;
+        seconds += match unit {
+            "d" => number * 24 * 60 * 60,
+            "h" => number * 60 * 60,
+            "m" => number * 60,
+            "s" => number,
+            _ => return None,
+        };
+        parsed = true;
+    }
+    parsed.then_some(seconds)
+}
+
+/// Image format of a thumbnail embedded in a G-code file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbnailFormat {
+    Png,
+    Jpg,
+    Qoi,
+}
+
+impl ThumbnailFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Png => "image/png",
+            ThumbnailFormat::Jpg => "image/jpeg",
+            ThumbnailFormat::Qoi => "image/qoi",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Jpg => "jpg",
+            ThumbnailFormat::Qoi => "qoi",
+        }
+    }
+}
+
+impl FromStr for ThumbnailFormat {
+    type Err = ();
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "png" => Ok(ThumbnailFormat::Png),
+            "jpg" => Ok(ThumbnailFormat::Jpg),
+            "qoi" => Ok(ThumbnailFormat::Qoi),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Decoded thumbnail of a G-code file
+#[derive(Debug, PartialEq)]
+pub struct GcodeThumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub format: ThumbnailFormat,
+    pub data: Vec<u8>,
+}
+
+/// Most base64 characters of a thumbnail, larger blocks are dropped while they are read
+const MAX_THUMBNAIL_ENCODED_SIZE: usize = 4 * 1024 * 1024;
+
+/// Decodes the base64 thumbnails slicers embed between "; thumbnail begin WxH length"
+/// and "; thumbnail end" comments. The format follows the keyword, e.g. "; thumbnail_QOI begin",
+/// plain "thumbnail" blocks are PNG. Blocks that fail to decode are skipped, as are blocks
+/// longer than their declared length or MAX_THUMBNAIL_ENCODED_SIZE
+#[derive(Default)]
+struct ThumbnailParser {
+    thumbnails: Vec<GcodeThumbnail>,
+    current: Option<ThumbnailBlock>,
+}
+
+/// Thumbnail block that is being read
+struct ThumbnailBlock {
+    width: u32,
+    height: u32,
+    format: ThumbnailFormat,
+    max_length: usize,
+    encoded: String,
+}
+
+impl ThumbnailParser {
+    fn line(&mut self, line: &str) {
+        let comment = match line.trim().strip_prefix(';') {
+            Some(comment) => comment.trim(),
+            None => {
+                self.current = None;
+                return;
+            }
+        };
+        let mut words = comment.split_whitespace();
+        let (keyword, action) = (words.next().unwrap_or_default(), words.next());
+
+        match (thumbnail_format(keyword), action) {
+            (Some(format), Some("begin")) => {
+                let size = words
+                    .next()
+                    .and_then(|size| size.split_once('x'))
+                    .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+                let max_length = words
+                    .next()
+                    .and_then(|length| length.parse().ok())
+                    .map_or(MAX_THUMBNAIL_ENCODED_SIZE, |length: usize| {
+                        length.min(MAX_THUMBNAIL_ENCODED_SIZE)
+                    });
+                self.current = size.map(|(width, height)| ThumbnailBlock {
+                    width,
+                    height,
+                    format,
+                    max_length,
+                    encoded: String::new(),
+                });
+            }
+            (Some(_), Some("end")) => {
+                if let Some(block) = self.current.take() {
+                    if let Ok(data) = STANDARD.decode(block.encoded) {
+                        self.thumbnails.push(GcodeThumbnail {
+                            width: block.width,
+                            height: block.height,
+                            format: block.format,
+                            data,
+                        });
+                    }
+                }
+            }
+            _ => {
+                if let Some(block) = self.current.as_mut() {
+                    if block.encoded.len() + comment.len() > block.max_length {
+                        self.current = None;
+                    } else {
+                        block.encoded.push_str(comment);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn thumbnail_format(keyword: &str) -> Option<ThumbnailFormat> {
+    match keyword.to_lowercase().as_str() {
+        "thumbnail" | "thumbnail_png" => Some(ThumbnailFormat::Png),
+        "thumbnail_jpg" => Some(ThumbnailFormat::Jpg),
+        "thumbnail_qoi" => Some(ThumbnailFormat::Qoi),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_metadata(data: &[u8]) -> GcodeMetadata {
+        let mut scanner = GcodeScanner::new();
+        scanner.feed(data);
+        scanner.finish().0
+    }
+
+    fn parse_thumbnails(data: &[u8]) -> Vec<GcodeThumbnail> {
+        let mut scanner = GcodeScanner::new();
+        scanner.feed(data);
+        scanner.finish().1
+    }
+
+    #[test]
+    fn test_command_word() {
+        assert_eq!(command_word("G28"), Some("G28".to_string()));
+        assert_eq!(command_word("g1 X10 Y10"), Some("G1".to_string()));
+        assert_eq!(command_word("M0500"), Some("M500".to_string()));
+        assert_eq!(command_word("N10 M997*71"), Some("M997".to_string()));
+        assert_eq!(command_word("G29.1 ; probe"), Some("G29.1".to_string()));
+        assert_eq!(
+            command_word("  M104 S200 (hotend)"),
+            Some("M104".to_string())
+        );
+        assert_eq!(command_word("M104 T1 S200"), Some("M104".to_string()));
+        assert_eq!(command_word("M117 Going home"), Some("M117".to_string()));
+    }
+
+    #[test]
+    fn test_command_word_invalid() {
+        assert_eq!(command_word(""), None);
+        assert_eq!(command_word("; comment only"), None);
+        assert_eq!(command_word("X10 Y10"), None);
+        assert_eq!(command_word("G28\nM500"), None);
+        assert_eq!(command_word("G28 M500"), None);
+        assert_eq!(command_word("N10 M105 g1 X10"), None);
+        assert_eq!(command_word("Mabc"), None);
+    }
+
+    #[test]
+    fn test_parse_prusaslicer_metadata() {
+        let gcode = b"; generated by PrusaSlicer 2.6.0+win64 on 2023-09-01 at 10:00:00 UTC
+M140 S60
+M104 S215
+G90
+M83
+G1 Z0.2 F720
+G1 X10 Y10
+;LAYER_CHANGE
+G1 X20 Y10 E1.5
+G1 X20 Y30 E1.5
+;LAYER_CHANGE
+G1 Z0.4
+G1 X10 Y30 E1.5
+G1 X0 Y0
+; filament used [mm] = 1234.56, 78.90
+; filament used [g] = 3.70, 0.24
+; estimated printing time (normal mode) = 1h 2m 3s
+; layer_height = 0.2
+; temperature = 220,210
+; bed_temperature = 65,65
+";
+        let metadata = parse_metadata(gcode);
+
+        assert_eq!(metadata.slicer, Some("PrusaSlicer".to_string()));
+        assert_eq!(metadata.slicer_version, Some("2.6.0+win64".to_string()));
+        assert_eq!(metadata.estimated_time, Some(3723));
+        assert_eq!(metadata.filament_length, vec![1234.56, 78.9]);
+        assert_eq!(metadata.filament_weight, vec![3.7, 0.24]);
+        assert_eq!(metadata.layer_height, Some(0.2));
+        assert_eq!(metadata.layer_count, Some(2));
+        assert_eq!(metadata.nozzle_temperature, Some(220.0));
+        assert_eq!(metadata.bed_temperature, Some(65.0));
+        // travel moves are not part of the object
+        assert_eq!(
+            metadata.bounding_box,
+            Some(BoundingBox {
+                min_x: 10.0,
+                min_y: 10.0,
+                min_z: 0.2,
+                max_x: 20.0,
+                max_y: 30.0,
+                max_z: 0.4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_cura_metadata() {
+        let gcode = b";FLAVOR:Marlin
+;TIME:6666
+;Filament used: 1.5m
+;Layer height: 0.12
+;MINX:100.1
+;MINY:90
+;MINZ:0.3
+;MAXX:130.5
+;MAXY:120
+;MAXZ:15.3
+;Generated with Cura_SteamEngine 5.4.0
+M140 S60
+M190 S60
+M104 S200
+M109 S200
+;LAYER_COUNT:125
+;LAYER:0
+G1 X100.1 Y90 E1
+";
+        let metadata = parse_metadata(gcode);
+
+        assert_eq!(metadata.slicer, Some("Cura".to_string()));
+        assert_eq!(metadata.slicer_version, Some("5.4.0".to_string()));
+        assert_eq!(metadata.estimated_time, Some(6666));
+        assert_eq!(metadata.filament_length, vec![1500.0]);
+        assert_eq!(metadata.layer_height, Some(0.12));
+        assert_eq!(metadata.layer_count, Some(125));
+        assert_eq!(metadata.nozzle_temperature, Some(200.0));
+        assert_eq!(metadata.bed_temperature, Some(60.0));
+        assert_eq!(metadata.bounding_box.unwrap().max_z, 15.3);
+    }
+
+    #[test]
+    fn test_parse_orcaslicer_metadata() {
+        let gcode = b"; HEADER_BLOCK_START
+; generated by OrcaSlicer 1.8.0 on 2023-11-01 at 12:00:00
+; total layer number: 80
+; model printing time: 1h 2m; total estimated time: 1h 5m 30s
+; HEADER_BLOCK_END
+; nozzle_temperature = 220
+; hot_plate_temp = 55
+";
+        let metadata = parse_metadata(gcode);
+
+        assert_eq!(metadata.slicer, Some("OrcaSlicer".to_string()));
+        assert_eq!(metadata.slicer_version, Some("1.8.0".to_string()));
+        assert_eq!(metadata.layer_count, Some(80));
+        assert_eq!(metadata.estimated_time, Some(3930));
+        assert_eq!(metadata.nozzle_temperature, Some(220.0));
+        assert_eq!(metadata.bed_temperature, Some(55.0));
+        assert_eq!(metadata.bounding_box, None);
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1d 2h 3m 4s"), Some(93784));
+        assert_eq!(parse_duration("45s"), Some(45));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("soon"), None);
+    }
+
+    #[test]
+    fn test_parse_thumbnails() {
+        let png = STANDARD.encode(b"\x89PNG image data that spans multiple lines");
+        let (first, second) = png.split_at(20);
+        let gcode = format!(
+            "; generated by PrusaSlicer 2.6.0
+;
+; thumbnail begin 16x16 {}
+; {}
+; {}
+; thumbnail end
+;
+; thumbnail_QOI begin 300x300 8
+; cW9pZg==
+; thumbnail_QOI end
+; thumbnail_JPG begin 32x32 4
+; not base64!
+; thumbnail_JPG end
+G28
+",
+            png.len(),
+            first,
+            second
+        );
+        let thumbnails = parse_thumbnails(gcode.as_bytes());
+
+        assert_eq!(thumbnails.len(), 2);
+        assert_eq!(
+            thumbnails[0],
+            GcodeThumbnail {
+                width: 16,
+                height: 16,
+                format: ThumbnailFormat::Png,
+                data: b"\x89PNG image data that spans multiple lines".to_vec(),
+            }
+        );
+        assert_eq!(thumbnails[1].width, 300);
+        assert_eq!(thumbnails[1].format, ThumbnailFormat::Qoi);
+        assert_eq!(thumbnails[1].data, b"qoif".to_vec());
+    }
+
+    #[test]
+    fn test_parse_thumbnails_limits_length() {
+        // longer than declared
+        let gcode = b"; thumbnail begin 1x1 4\n; cW9pZg==\n; thumbnail end\n";
+        assert_eq!(parse_thumbnails(gcode), vec![]);
+
+        // a block that is never closed is dropped once it exceeds the limit
+        let line = format!("; {}\n", "A".repeat(1000));
+        let mut scanner = GcodeScanner::new();
+        scanner.feed(b"; thumbnail begin 16x16\n");
+        for _ in 0..MAX_THUMBNAIL_ENCODED_SIZE / 1000 + 1 {
+            scanner.feed(line.as_bytes());
+        }
+        assert!(scanner.thumbnails.current.is_none());
+        scanner.feed(b"; thumbnail end\n");
+        assert_eq!(scanner.finish().1, vec![]);
+    }
+
+    #[test]
+    fn test_scanner_chunks() {
+        let gcode = b"; generated by PrusaSlicer 2.6.0\n; thumbnail begin 1x1 8\n; cW9pZg==\n\
+                      ; thumbnail end\nM104 S215\n; layer_height = 0.2\n";
+        let mut scanner = GcodeScanner::new();
+        for chunk in gcode.chunks(7) {
+            scanner.feed(chunk);
+        }
+        let (metadata, thumbnails) = scanner.finish();
+
+        assert_eq!(metadata, parse_metadata(gcode));
+        assert_eq!(metadata.layer_height, Some(0.2));
+        assert_eq!(metadata.nozzle_temperature, Some(215.0));
+        assert_eq!(thumbnails, parse_thumbnails(gcode));
+        assert_eq!(thumbnails[0].data, b"qoif".to_vec());
+    }
+
+    #[test]
+    fn test_scanner_skips_overlong_lines() {
+        let mut scanner = GcodeScanner::new();
+        scanner.feed(&[b'x'; MAX_LINE_LENGTH * 2]);
+        scanner.feed(b"; layer_height = 0.4\n; layer_height = 0.2\n");
+
+        assert_eq!(scanner.finish().0.layer_height, Some(0.2));
+    }
+}
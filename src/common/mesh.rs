@@ -0,0 +1,535 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::gcode::BoundingBox;
+
+pub const STL_HEADER_SIZE: usize = 84;
+pub const STL_TRIANGLE_SIZE: usize = 50;
+/// Path of the model inside a 3MF package
+pub const THREEMF_MODEL_PATH: &str = "3D/3dmodel.model";
+
+/// Triangle mesh with shared vertices
+#[derive(Debug, Default, PartialEq)]
+pub struct Mesh {
+    pub vertices: Vec<[f64; 3]>,
+    pub triangles: Vec<[usize; 3]>,
+}
+
+/// Results of the mesh analysis of a model file
+/// - volume: Enclosed volume in mm³, only meaningful for watertight meshes
+/// - surface_area: Area of all triangles in mm²
+/// - watertight: Every edge is shared by exactly two triangles with opposite orientation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MeshAnalysis {
+    pub triangle_count: u64,
+    pub bounding_box: Option<BoundingBox>,
+    pub volume: f64,
+    pub surface_area: f64,
+    pub watertight: bool,
+}
+
+impl Mesh {
+    /// Adds a vertex, vertices with the same coordinates are merged so that
+    /// triangles of formats without shared vertices (STL) are connected
+    fn vertex(&mut self, index: &mut HashMap<[u64; 3], usize>, vertex: [f64; 3]) -> usize {
+        let key = vertex.map(|coordinate| (coordinate + 0.0).to_bits());
+        *index.entry(key).or_insert_with(|| {
+            self.vertices.push(vertex);
+            self.vertices.len() - 1
+        })
+    }
+
+    pub fn analyze(&self) -> MeshAnalysis {
+        let mut bounding_box: Option<BoundingBox> = None;
+        for [x, y, z] in self.triangles.iter().flatten().map(|i| self.vertices[*i]) {
+            match bounding_box.as_mut() {
+                Some(bounding_box) => {
+                    bounding_box.min_x = bounding_box.min_x.min(x);
+                    bounding_box.min_y = bounding_box.min_y.min(y);
+                    bounding_box.min_z = bounding_box.min_z.min(z);
+                    bounding_box.max_x = bounding_box.max_x.max(x);
+                    bounding_box.max_y = bounding_box.max_y.max(y);
+                    bounding_box.max_z = bounding_box.max_z.max(z);
+                }
+                None => {
+                    bounding_box = Some(BoundingBox {
+                        min_x: x,
+                        min_y: y,
+                        min_z: z,
+                        max_x: x,
+                        max_y: y,
+                        max_z: z,
+                    })
+                }
+            }
+        }
+
+        let mut volume = 0.0;
+        let mut surface_area = 0.0;
+        // directed edges, a closed and consistently oriented mesh uses each one exactly once
+        // and always together with its reverse
+        let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|i| self.vertices[i]);
+            let (ab, ac) = (sub(b, a), sub(c, a));
+            surface_area += length(cross(ab, ac)) / 2.0;
+            // signed volume of the tetrahedron with the origin
+            volume += dot(a, cross(b, c)) / 6.0;
+
+            for (from, to) in [(0, 1), (1, 2), (2, 0)] {
+                *edges.entry((triangle[from], triangle[to])).or_default() += 1;
+            }
+        }
+
+        let watertight = !self.triangles.is_empty()
+            && edges
+                .iter()
+                .all(|((from, to), count)| *count == 1 && edges.get(&(*to, *from)) == Some(&1));
+
+        MeshAnalysis {
+            triangle_count: self.triangles.len() as u64,
+            bounding_box,
+            volume: volume.abs(),
+            surface_area,
+            watertight,
+        }
+    }
+}
+
+fn sub(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn length(a: [f64; 3]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+/// Binary STL: 80 byte header, triangle count and 50 bytes per triangle.
+/// Only the header of the file is needed besides its size
+pub fn is_binary_stl(header: &[u8], size: usize) -> bool {
+    if header.len() < STL_HEADER_SIZE {
+        return false;
+    }
+    let triangles = u32::from_le_bytes([header[80], header[81], header[82], header[83]]) as usize;
+    triangles > 0 && size == STL_HEADER_SIZE + triangles * STL_TRIANGLE_SIZE
+}
+
+/// Longest line of a text model file, longer lines can only come from a broken file
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+/// Largest mesh that is analyzed, bounds the memory of the vertices, triangles and edges
+pub const MAX_MESH_TRIANGLES: usize = 10_000_000;
+
+enum MeshFormat {
+    /// STL of the size, binary or ASCII is decided once its header arrived
+    Stl {
+        size: usize,
+    },
+    BinaryStl,
+    AsciiStl,
+    Obj,
+}
+
+/// Builds the mesh of an STL or OBJ file that arrives in chunks.
+/// Only an unfinished line or triangle of the file is kept besides the mesh
+pub struct MeshReader {
+    format: MeshFormat,
+    mesh: Mesh,
+    index: HashMap<[u64; 3], usize>,
+    /// Corners of the current ASCII STL facet
+    corners: Vec<usize>,
+    pending: Vec<u8>,
+    failed: bool,
+}
+
+impl MeshReader {
+    fn new(format: MeshFormat) -> Self {
+        MeshReader {
+            format,
+            mesh: Mesh::default(),
+            index: HashMap::new(),
+            corners: Vec::with_capacity(3),
+            pending: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// Reader of an ASCII or binary STL file, the size tells them apart
+    pub fn stl(size: usize) -> Self {
+        MeshReader::new(MeshFormat::Stl { size })
+    }
+
+    pub fn obj() -> Self {
+        MeshReader::new(MeshFormat::Obj)
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        if self.failed {
+            return;
+        }
+        self.pending.extend_from_slice(chunk);
+        if self.read(false).is_none() {
+            self.failed = true;
+            self.pending = Vec::new();
+        }
+    }
+
+    /// Returns the mesh, None if the file isn't a readable model
+    pub fn finish(mut self) -> Option<Mesh> {
+        if self.failed {
+            return None;
+        }
+        self.read(true)?;
+        Some(self.mesh)
+    }
+
+    /// Reads the complete lines or triangles of the pending bytes, at the end of the file
+    /// the last line doesn't need a line break
+    fn read(&mut self, end: bool) -> Option<()> {
+        if let MeshFormat::Stl { size } = self.format {
+            if self.pending.len() < STL_HEADER_SIZE && !end {
+                return Some(());
+            }
+            self.format = match is_binary_stl(&self.pending, size) {
+                true => {
+                    self.pending.drain(..STL_HEADER_SIZE);
+                    MeshFormat::BinaryStl
+                }
+                false => MeshFormat::AsciiStl,
+            };
+        }
+
+        if let MeshFormat::BinaryStl = self.format {
+            let complete = self.pending.len() / STL_TRIANGLE_SIZE * STL_TRIANGLE_SIZE;
+            let pending = std::mem::take(&mut self.pending);
+            for triangle in pending[..complete].chunks_exact(STL_TRIANGLE_SIZE) {
+                self.binary_stl_triangle(triangle)?;
+            }
+            self.pending = pending[complete..].to_vec();
+            // a binary STL ends with a complete triangle
+            return match end && !self.pending.is_empty() {
+                true => None,
+                false => Some(()),
+            };
+        }
+
+        let complete = match end {
+            true => self.pending.len(),
+            false => match self.pending.iter().rposition(|byte| *byte == b'\n') {
+                Some(position) => position + 1,
+                None if self.pending.len() > MAX_LINE_LENGTH => return None,
+                None => return Some(()),
+            },
+        };
+        let pending = self.pending.split_off(complete);
+        let text = std::mem::replace(&mut self.pending, pending);
+        let text = std::str::from_utf8(&text).ok()?;
+        for line in text.lines() {
+            match self.format {
+                MeshFormat::Obj => self.obj_line(line)?,
+                _ => self.stl_line(line)?,
+            }
+        }
+        Some(())
+    }
+
+    fn triangle(&mut self, triangle: [usize; 3]) -> Option<()> {
+        if self.mesh.triangles.len() >= MAX_MESH_TRIANGLES {
+            return None;
+        }
+        self.mesh.triangles.push(triangle);
+        Some(())
+    }
+
+    fn binary_stl_triangle(&mut self, triangle: &[u8]) -> Option<()> {
+        // the normal is skipped, the vertices follow it
+        let mut corners = [0; 3];
+        for (corner, vertex) in corners.iter_mut().enumerate() {
+            let offset = 12 + corner * 12;
+            let coordinate = |axis: usize| {
+                let bytes = &triangle[offset + axis * 4..offset + axis * 4 + 4];
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f64
+            };
+            let coordinates = [coordinate(0), coordinate(1), coordinate(2)];
+            *vertex = self.mesh.vertex(&mut self.index, coordinates);
+        }
+        self.triangle(corners)
+    }
+
+    fn stl_line(&mut self, line: &str) -> Option<()> {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("vertex") => {
+                let vertex = parse_coordinates(words)?;
+                let corner = self.mesh.vertex(&mut self.index, vertex);
+                self.corners.push(corner);
+            }
+            Some("endfacet") => {
+                if self.corners.len() != 3 {
+                    return None;
+                }
+                self.triangle([self.corners[0], self.corners[1], self.corners[2]])?;
+                self.corners.clear();
+            }
+            _ => {}
+        }
+        Some(())
+    }
+
+    fn obj_line(&mut self, line: &str) -> Option<()> {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => self.mesh.vertices.push(parse_coordinates(words)?),
+            Some("f") => {
+                // "f 1 2 3", "f 1/1/1 2/2/2 3/3/3" or negative indices relative to the end
+                let mut corners = Vec::new();
+                for word in words {
+                    let index: i64 = word.split('/').next()?.parse().ok()?;
+                    let index = match index {
+                        1.. => index as usize - 1,
+                        ..=-1 => self
+                            .mesh
+                            .vertices
+                            .len()
+                            .checked_sub(index.unsigned_abs() as usize)?,
+                        0 => return None,
+                    };
+                    if index >= self.mesh.vertices.len() {
+                        return None;
+                    }
+                    corners.push(index);
+                }
+                for i in 1..corners.len().saturating_sub(1) {
+                    self.triangle([corners[0], corners[i], corners[i + 1]])?;
+                }
+            }
+            _ => {}
+        }
+        Some(())
+    }
+}
+
+/// Parses the meshes of the objects of the model of a 3MF package, the model is the entry at
+/// THREEMF_MODEL_PATH. Each object has its own vertices, build item and component transforms
+/// are not applied. Models with more than MAX_MESH_TRIANGLES triangles aren't parsed
+pub fn parse_3mf_model(model: &[u8]) -> Option<Mesh> {
+    let text = std::str::from_utf8(model).ok()?;
+    let mut mesh = Mesh::default();
+    let mut object_start = 0;
+
+    for tag in text.split('<').skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        let mut parts = tag.trim_end_matches('/').split_whitespace();
+        let name = parts.next().unwrap_or_default();
+        // elements can carry a namespace prefix, e.g. "m:vertex"
+        let name = name.rsplit(':').next().unwrap_or_default();
+
+        match name {
+            "mesh" => object_start = mesh.vertices.len(),
+            "vertex" => mesh.vertices.push([
+                attribute(tag, "x")?.parse().ok()?,
+                attribute(tag, "y")?.parse().ok()?,
+                attribute(tag, "z")?.parse().ok()?,
+            ]),
+            "triangle" => {
+                let mut triangle = [0; 3];
+                for (corner, key) in triangle.iter_mut().zip(["v1", "v2", "v3"]) {
+                    *corner = object_start + attribute(tag, key)?.parse::<usize>().ok()?;
+                    if *corner >= mesh.vertices.len() {
+                        return None;
+                    }
+                }
+                if mesh.triangles.len() >= MAX_MESH_TRIANGLES {
+                    return None;
+                }
+                mesh.triangles.push(triangle);
+            }
+            _ => {}
+        }
+    }
+
+    Some(mesh)
+}
+
+fn parse_coordinates<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<[f64; 3]> {
+    Some([
+        words.next()?.parse().ok()?,
+        words.next()?.parse().ok()?,
+        words.next()?.parse().ok()?,
+    ])
+}
+
+/// Reads the value of an XML attribute of a tag, e.g. x of `vertex x="1.5" y="2"`
+fn attribute<'a>(tag: &'a str, key: &str) -> Option<&'a str> {
+    tag.split_whitespace().skip(1).find_map(|part| {
+        let (name, value) = part.trim_end_matches('/').split_once('=')?;
+        (name == key).then(|| value.trim_matches(|c| c == '"' || c == '\''))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unit cube made of 12 triangles with outward normals
+    const CUBE_TRIANGLES: [[usize; 3]; 12] = [
+        [0, 2, 1],
+        [0, 3, 2],
+        [4, 5, 6],
+        [4, 6, 7],
+        [0, 1, 5],
+        [0, 5, 4],
+        [1, 2, 6],
+        [1, 6, 5],
+        [2, 3, 7],
+        [2, 7, 6],
+        [3, 0, 4],
+        [3, 4, 7],
+    ];
+    const CUBE_VERTICES: [[f64; 3]; 8] = [
+        [0.0, 0.0, 0.0],
+        [10.0, 0.0, 0.0],
+        [10.0, 10.0, 0.0],
+        [0.0, 10.0, 0.0],
+        [0.0, 0.0, 10.0],
+        [10.0, 0.0, 10.0],
+        [10.0, 10.0, 10.0],
+        [0.0, 10.0, 10.0],
+    ];
+
+    /// Parses an ASCII or binary STL file
+    fn parse_stl(data: &[u8]) -> Option<Mesh> {
+        let mut reader = MeshReader::stl(data.len());
+        reader.feed(data);
+        reader.finish()
+    }
+
+    /// Parses the vertices and faces of an OBJ file, polygons are split into triangles
+    fn parse_obj(data: &[u8]) -> Option<Mesh> {
+        let mut reader = MeshReader::obj();
+        reader.feed(data);
+        reader.finish()
+    }
+
+    fn cube_stl() -> String {
+        let mut stl = "solid cube\n".to_string();
+        for triangle in CUBE_TRIANGLES {
+            stl.push_str("facet normal 0 0 0\nouter loop\n");
+            for [x, y, z] in triangle.map(|i| CUBE_VERTICES[i]) {
+                stl.push_str(&format!("vertex {} {} {}\n", x, y, z));
+            }
+            stl.push_str("endloop\nendfacet\n");
+        }
+        stl + "endsolid cube\n"
+    }
+
+    #[test]
+    fn test_analyze_cube() {
+        let mesh = parse_stl(cube_stl().as_bytes()).unwrap();
+        let analysis = mesh.analyze();
+
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(analysis.triangle_count, 12);
+        assert!((analysis.volume - 1000.0).abs() < 1e-9);
+        assert!((analysis.surface_area - 600.0).abs() < 1e-9);
+        assert!(analysis.watertight);
+        assert_eq!(analysis.bounding_box.unwrap().max_z, 10.0);
+    }
+
+    #[test]
+    fn test_analyze_open_mesh() {
+        let mut mesh = parse_stl(cube_stl().as_bytes()).unwrap();
+        mesh.triangles.pop();
+        assert!(!mesh.analyze().watertight);
+
+        // flipped triangle
+        let mut mesh = parse_stl(cube_stl().as_bytes()).unwrap();
+        mesh.triangles[0].swap(1, 2);
+        assert!(!mesh.analyze().watertight);
+    }
+
+    fn binary_cube_stl() -> Vec<u8> {
+        let mut stl = vec![0u8; 80];
+        stl.extend_from_slice(&12u32.to_le_bytes());
+        for triangle in CUBE_TRIANGLES {
+            stl.extend_from_slice(&[0; 12]);
+            for vertex in triangle.map(|i| CUBE_VERTICES[i]) {
+                for coordinate in vertex {
+                    stl.extend_from_slice(&(coordinate as f32).to_le_bytes());
+                }
+            }
+            stl.extend_from_slice(&[0; 2]);
+        }
+        stl
+    }
+
+    #[test]
+    fn test_parse_binary_stl() {
+        let analysis = parse_stl(&binary_cube_stl()).unwrap().analyze();
+        assert_eq!(analysis.triangle_count, 12);
+        assert!((analysis.volume - 1000.0).abs() < 1e-9);
+        assert!(analysis.watertight);
+    }
+
+    #[test]
+    fn test_parse_obj() {
+        // quads are split into two triangles
+        let obj = "o Cube\nv 0 0 0\nv 10 0 0\nv 10 10 0\nv 0 10 0\n\
+                   v 0 0 10\nv 10 0 10\nv 10 10 10\nv 0 10 10\n\
+                   f 1 4 3 2\nf 5/1/1 6/1/1 7/1/1 8/1/1\nf 1 2 6 5\n\
+                   f 2 3 7 6\nf 3 4 8 7\nf -5 -8 -4 -1\n";
+        let analysis = parse_obj(obj.as_bytes()).unwrap().analyze();
+
+        assert_eq!(analysis.triangle_count, 12);
+        assert!((analysis.volume - 1000.0).abs() < 1e-9);
+        assert!(analysis.watertight);
+        assert_eq!(parse_obj(b"v 0 0 0\nf 1 2 3\n"), None);
+    }
+
+    #[test]
+    fn test_mesh_reader_chunks() {
+        let stl = cube_stl();
+        let mut reader = MeshReader::stl(stl.len());
+        for chunk in stl.as_bytes().chunks(7) {
+            reader.feed(chunk);
+        }
+        assert_eq!(reader.finish(), parse_stl(stl.as_bytes()));
+
+        let binary = binary_cube_stl();
+        let mut reader = MeshReader::stl(binary.len());
+        for chunk in binary.chunks(13) {
+            reader.feed(chunk);
+        }
+        assert_eq!(reader.finish().unwrap().analyze().triangle_count, 12);
+
+        // a binary STL that ends inside a triangle
+        let mut reader = MeshReader::stl(binary.len());
+        reader.feed(&binary[..binary.len() - 1]);
+        assert_eq!(reader.finish(), None);
+
+        let mut reader = MeshReader::obj();
+        reader.feed(&vec![b'#'; MAX_LINE_LENGTH + 1]);
+        assert_eq!(reader.finish(), None);
+    }
+
+    #[test]
+    fn test_attribute() {
+        let tag = r#"vertex x="1.5" y='2' z="-3"/"#;
+        assert_eq!(attribute(tag, "x"), Some("1.5"));
+        assert_eq!(attribute(tag, "y"), Some("2"));
+        assert_eq!(attribute(tag, "z"), Some("-3"));
+        assert_eq!(attribute(tag, "v1"), None);
+    }
+}
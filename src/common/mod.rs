@@ -1,2 +1,7 @@
+pub mod agent_token;
 pub mod app_error;
+pub mod download;
+pub mod gcode;
 pub mod jwt_token;
+pub mod mesh;
+pub mod zip;
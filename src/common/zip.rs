@@ -0,0 +1,265 @@
+//! Reads single entries of zip archives, enough to open 3MF packages.
+//! Supports stored and deflated entries, no encryption, zip64 or multi-disk archives
+
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x02014b50;
+const LOCAL_FILE_HEADER: u32 = 0x04034b50;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// Largest entry that is extracted, protects against zip bombs
+pub const MAX_ENTRY_SIZE: usize = 256 * 1024 * 1024;
+
+/// Entry of the central directory of an archive
+#[derive(Debug, PartialEq)]
+pub struct ZipEntry {
+    pub name: String,
+    method: u16,
+    compressed_size: usize,
+    size: usize,
+    local_header_offset: usize,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Bytes at the end of an archive that hold the end of central directory record,
+/// the record is followed by a comment of at most 64 KiB
+pub const END_SEARCH_SIZE: usize = 22 + u16::MAX as usize;
+/// Size of a local file header without its name and extra field
+pub const LOCAL_HEADER_SIZE: usize = 30;
+
+/// Where the central directory is stored in the archive
+#[derive(Debug, PartialEq)]
+pub struct CentralDirectory {
+    pub offset: usize,
+    pub size: usize,
+    count: usize,
+}
+
+impl ZipEntry {
+    pub fn local_header_offset(&self) -> usize {
+        self.local_header_offset
+    }
+
+    pub fn compressed_size(&self) -> usize {
+        self.compressed_size
+    }
+}
+
+/// Finds the central directory in the end of the archive, at most END_SEARCH_SIZE bytes
+/// of it are needed. Large archives are read in parts this way
+pub fn central_directory(end: &[u8]) -> Option<CentralDirectory> {
+    let search_start = end.len().saturating_sub(END_SEARCH_SIZE);
+    let record = (search_start..end.len().saturating_sub(21))
+        .rev()
+        .find(|offset| u32_at(end, *offset) == Some(END_OF_CENTRAL_DIRECTORY))?;
+
+    Some(CentralDirectory {
+        offset: u32_at(end, record + 16)? as usize,
+        size: u32_at(end, record + 12)? as usize,
+        count: u16_at(end, record + 10)? as usize,
+    })
+}
+
+/// Lists the entries of the central directory, directory holds its bytes
+pub fn parse_central_directory(
+    central_directory: &CentralDirectory,
+    directory: &[u8],
+) -> Option<Vec<ZipEntry>> {
+    let mut offset = 0;
+    let mut entries = Vec::with_capacity(central_directory.count);
+    for _ in 0..central_directory.count {
+        if u32_at(directory, offset)? != CENTRAL_DIRECTORY_HEADER {
+            return None;
+        }
+        let name_length = u16_at(directory, offset + 28)? as usize;
+        let extra_length = u16_at(directory, offset + 30)? as usize;
+        let comment_length = u16_at(directory, offset + 32)? as usize;
+        let name = directory.get(offset + 46..offset + 46 + name_length)?;
+
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).to_string(),
+            method: u16_at(directory, offset + 10)?,
+            compressed_size: u32_at(directory, offset + 20)? as usize,
+            size: u32_at(directory, offset + 24)? as usize,
+            local_header_offset: u32_at(directory, offset + 42)? as usize,
+        });
+        offset += 46 + name_length + extra_length + comment_length;
+    }
+
+    Some(entries)
+}
+
+/// Finds the entry with the name, names are compared case insensitive
+pub fn find_entry<'a>(entries: &'a [ZipEntry], name: &str) -> Option<&'a ZipEntry> {
+    entries.iter().find(|entry| {
+        entry
+            .name
+            .trim_start_matches('/')
+            .eq_ignore_ascii_case(name.trim_start_matches('/'))
+    })
+}
+
+/// Offset of the data of the entry in the archive, read from the LOCAL_HEADER_SIZE bytes of
+/// its local file header. The name and extra field may differ from the central directory
+pub fn data_offset(entry: &ZipEntry, local_header: &[u8]) -> Option<usize> {
+    if entry.size > MAX_ENTRY_SIZE || u32_at(local_header, 0)? != LOCAL_FILE_HEADER {
+        return None;
+    }
+    let name_length = u16_at(local_header, 26)? as usize;
+    let extra_length = u16_at(local_header, 28)? as usize;
+    Some(entry.local_header_offset + LOCAL_HEADER_SIZE + name_length + extra_length)
+}
+
+/// Decompresses the data of the entry, data holds its compressed_size bytes
+pub fn decompress(entry: &ZipEntry, data: &[u8]) -> Option<Vec<u8>> {
+    if entry.size > MAX_ENTRY_SIZE || data.len() != entry.compressed_size {
+        return None;
+    }
+    match entry.method {
+        METHOD_STORED => Some(data.to_vec()),
+        METHOD_DEFLATED => inflate(data, entry.size),
+        _ => None,
+    }
+}
+
+/// Decompresses a raw deflate stream, None if it is invalid or doesn't hold expected_size bytes
+fn inflate(data: &[u8], expected_size: usize) -> Option<Vec<u8>> {
+    let output = decompress_to_vec_with_limit(data, expected_size).ok()?;
+    (output.len() == expected_size).then_some(output)
+}
+
+/// Archive with a single stored entry, as written by zipfile with ZIP_STORED
+#[cfg(test)]
+pub fn stored_archive(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let name = name.as_bytes();
+
+    archive.extend_from_slice(&LOCAL_FILE_HEADER.to_le_bytes());
+    archive.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&[0, 0]);
+    archive.extend_from_slice(name);
+    archive.extend_from_slice(data);
+
+    let directory = archive.len() as u32;
+    archive.extend_from_slice(&CENTRAL_DIRECTORY_HEADER.to_le_bytes());
+    archive.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&[0; 12]);
+    archive.extend_from_slice(&0u32.to_le_bytes());
+    archive.extend_from_slice(name);
+    let directory_size = archive.len() as u32 - directory;
+
+    archive.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+    archive.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    archive.extend_from_slice(&directory_size.to_le_bytes());
+    archive.extend_from_slice(&directory.to_le_bytes());
+    archive.extend_from_slice(&[0, 0]);
+    archive
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec;
+
+    /// Lists the entries of the archive, None if it isn't a readable zip archive
+    fn entries(archive: &[u8]) -> Option<Vec<ZipEntry>> {
+        let central_directory = central_directory(archive)?;
+        let start = central_directory.offset;
+        let directory = archive.get(start..start.checked_add(central_directory.size)?)?;
+        parse_central_directory(&central_directory, directory)
+    }
+
+    /// Extracts the data of an entry of the archive
+    fn extract(archive: &[u8], entry: &ZipEntry) -> Option<Vec<u8>> {
+        let offset = entry.local_header_offset;
+        let local_header = archive.get(offset..offset.checked_add(LOCAL_HEADER_SIZE)?)?;
+        let start = data_offset(entry, local_header)?;
+        let data = archive.get(start..start.checked_add(entry.compressed_size)?)?;
+        decompress(entry, data)
+    }
+
+    /// Extracts the entry with the name, names are compared case insensitive
+    fn read_entry(archive: &[u8], name: &str) -> Option<Vec<u8>> {
+        let entries = entries(archive)?;
+        extract(archive, find_entry(&entries, name)?)
+    }
+
+    #[test]
+    fn test_inflate() {
+        // zlib.compressobj(wbits=-15) of "G1 X10 Y10\n" * 8 + "M84\n"
+        let compressed = [
+            0x73, 0x37, 0x54, 0x88, 0x30, 0x34, 0x50, 0x88, 0x34, 0x34, 0xe0, 0x72, 0xa7, 0x26,
+            0xd3, 0xd7, 0xc2, 0x84, 0x0b, 0x00,
+        ];
+        let expected = format!("{}M84\n", "G1 X10 Y10\n".repeat(8));
+
+        assert_eq!(
+            inflate(&compressed, expected.len()),
+            Some(expected.as_bytes().to_vec())
+        );
+        // output larger than announced
+        assert_eq!(inflate(&compressed, 10), None);
+        assert_eq!(inflate(&compressed[..10], expected.len()), None);
+    }
+
+    #[test]
+    fn test_inflate_round_trip() {
+        let model = "<vertex x=\"1.5\" y=\"2\" z=\"0\"/>\n".repeat(500);
+        let noise = (0..5000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8)
+            .collect::<Vec<_>>();
+        for data in [model.as_bytes(), &noise, b""] {
+            for level in [0, 1, 6, 10] {
+                let compressed = compress_to_vec(data, level);
+                assert_eq!(inflate(&compressed, data.len()), Some(data.to_vec()));
+                // truncated and corrupted streams are rejected without panicking
+                assert_eq!(
+                    inflate(&compressed[..compressed.len() / 2], data.len()),
+                    None
+                );
+                for position in (0..compressed.len()).step_by(7) {
+                    let mut corrupted = compressed.clone();
+                    corrupted[position] ^= 0x5a;
+                    let _ = inflate(&corrupted, data.len());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_inflate_limits_size() {
+        // a small stream that expands far beyond the announced size
+        let compressed = compress_to_vec(&[0; 1024 * 1024], 10);
+        assert_eq!(inflate(&compressed, 1024), None);
+    }
+
+    #[test]
+    fn test_read_entry() {
+        // zipfile with a stored "3D/3dmodel.model" entry containing "<model/>"
+        let archive = stored_archive("3D/3dmodel.model", b"<model/>");
+
+        assert_eq!(
+            read_entry(&archive, "3d/3DModel.model"),
+            Some(b"<model/>".to_vec())
+        );
+        assert_eq!(read_entry(&archive, "missing"), None);
+        assert_eq!(read_entry(b"not a zip archive", "3D/3dmodel.model"), None);
+    }
+}
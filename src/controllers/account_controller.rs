@@ -2,14 +2,18 @@ use std::sync::Arc;
 
 use crate::common::app_error::AppError;
 use axum::extract::State;
-use axum::routing::get;
+use axum::routing::{get, put};
 use axum::{middleware, Extension, Json, Router};
 use tracing::info;
 
 use crate::middlewares::auth_middleware;
 use crate::models::account::AccountViewModel;
+use crate::models::command_policy::{CommandPolicyUpdateRequest, CommandPolicyViewModel};
+use crate::models::file_type_policy::{FileTypePolicyUpdateRequest, FileTypePolicyViewModel};
 use crate::models::view_model::ViewModel;
 use crate::services::account_service::{AccountService, AccountServiceImpl};
+use crate::services::command_policy_service::{CommandPolicyService, CommandPolicyServiceImpl};
+use crate::services::file_type_policy_service::{FileTypePolicyService, FileTypePolicyServiceImpl};
 use crate::AppState;
 
 /// Initializes the user controller, defining the routes and middlewares
@@ -17,6 +21,13 @@ pub fn init() -> Router<Arc<AppState>> {
     info!("Ok");
     Router::new()
         .route("/accounts/me", get(info))
+        .route("/accounts/me/command-policy", get(get_command_policy))
+        .route("/accounts/me/command-policy", put(update_command_policy))
+        .route("/accounts/me/file-type-policy", get(get_file_type_policy))
+        .route(
+            "/accounts/me/file-type-policy",
+            put(update_file_type_policy),
+        )
         .route_layer(middleware::from_fn(auth_middleware::handle))
 }
 
@@ -34,3 +45,45 @@ pub async fn info(
     let viewmodel = account.to_viewmodel();
     Ok(Json(viewmodel))
 }
+
+pub async fn get_command_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+) -> Result<Json<CommandPolicyViewModel>, AppError> {
+    let command_policy_service = CommandPolicyServiceImpl::new(state.db_pool.clone());
+    let policy = command_policy_service.get(&user_uuid).await?;
+
+    Ok(Json(policy.to_viewmodel()))
+}
+
+pub async fn update_command_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Json(json): Json<CommandPolicyUpdateRequest>,
+) -> Result<Json<CommandPolicyViewModel>, AppError> {
+    let command_policy_service = CommandPolicyServiceImpl::new(state.db_pool.clone());
+    let policy = command_policy_service.update(&user_uuid, json).await?;
+
+    Ok(Json(policy.to_viewmodel()))
+}
+
+pub async fn get_file_type_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+) -> Result<Json<FileTypePolicyViewModel>, AppError> {
+    let file_type_policy_service = FileTypePolicyServiceImpl::new(state.db_pool.clone());
+    let policy = file_type_policy_service.get(&user_uuid).await?;
+
+    Ok(Json(policy.to_viewmodel()))
+}
+
+pub async fn update_file_type_policy(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Json(json): Json<FileTypePolicyUpdateRequest>,
+) -> Result<Json<FileTypePolicyViewModel>, AppError> {
+    let file_type_policy_service = FileTypePolicyServiceImpl::new(state.db_pool.clone());
+    let policy = file_type_policy_service.update(&user_uuid, json).await?;
+
+    Ok(Json(policy.to_viewmodel()))
+}
@@ -1,11 +1,13 @@
 use crate::common::app_error::AppError;
 use crate::middlewares::auth_middleware;
-use crate::models::agent::{AgentAddRequest, AgentViewModel};
+use crate::models::agent::{
+    AgentAddRequest, AgentDetailViewModel, AgentTokenViewModel, AgentUpdateRequest, AgentViewModel,
+};
 use crate::models::view_model::ViewModel;
 use crate::services::agent_service::{AgentService, AgentServiceImpl};
 use crate::AppState;
 use axum::extract::{Path, State};
-use axum::routing::{delete, get, post};
+use axum::routing::{delete, get, post, put};
 use axum::{middleware, Extension, Json, Router};
 use std::sync::Arc;
 use tracing::info;
@@ -15,7 +17,11 @@ pub fn init() -> Router<Arc<AppState>> {
     Router::new()
         .route("/agents", get(get_all))
         .route("/agents", post(add))
+        .route("/agents/:uuid", get(get_by_uuid))
+        .route("/agents/:uuid", put(update))
         .route("/agents/:uuid", delete(delete_by_uuid))
+        .route("/agents/:uuid/token/rotate", post(rotate_token))
+        .route("/agents/:uuid/token", delete(revoke_token))
         .route_layer(middleware::from_fn(auth_middleware::handle))
 }
 
@@ -25,9 +31,11 @@ async fn add(
     Json(json): Json<AgentAddRequest>,
 ) -> Result<Json<AgentViewModel>, AppError> {
     let agent_service = AgentServiceImpl::new(state.db_pool.clone());
-    let agent = agent_service.add(&user_uuid, json).await?;
+    let (agent, token) = agent_service.add(&user_uuid, json).await?;
 
-    let viewmodel = agent.to_viewmodel();
+    // the plaintext token is only shown once
+    let mut viewmodel = agent.to_viewmodel();
+    viewmodel.token = Some(token);
     Ok(Json(viewmodel))
 }
 
@@ -46,6 +54,35 @@ async fn get_all(
     Ok(Json(agents))
 }
 
+async fn get_by_uuid(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+) -> Result<Json<AgentDetailViewModel>, AppError> {
+    let agent_service = AgentServiceImpl::new(state.db_pool.clone());
+    let agent = agent_service.get_by_uuid(&user_uuid, &uuid).await?;
+
+    Ok(Json(AgentDetailViewModel {
+        agent: agent.to_viewmodel(),
+        connection: state.agent_registry.connection_info(&uuid).await,
+    }))
+}
+
+async fn update(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+    Json(json): Json<AgentUpdateRequest>,
+) -> Result<Json<AgentDetailViewModel>, AppError> {
+    let agent_service = AgentServiceImpl::new(state.db_pool.clone());
+    let agent = agent_service.update(&user_uuid, &uuid, json).await?;
+
+    Ok(Json(AgentDetailViewModel {
+        agent: agent.to_viewmodel(),
+        connection: state.agent_registry.connection_info(&uuid).await,
+    }))
+}
+
 async fn delete_by_uuid(
     State(state): State<Arc<AppState>>,
     Extension(user_uuid): Extension<String>,
@@ -53,6 +90,43 @@ async fn delete_by_uuid(
 ) -> Result<Json<bool>, AppError> {
     let agent_service = AgentServiceImpl::new(state.db_pool.clone());
     agent_service.delete(&user_uuid, &uuid).await?;
+    state
+        .agent_registry
+        .disconnect(&uuid, "Agent deleted")
+        .await;
+    state.printer_statuses.remove_agent(&uuid).await;
+
+    Ok(Json(true))
+}
+
+/// Replaces the token of the agent, a socket authenticated with the old token is closed
+async fn rotate_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+) -> Result<Json<AgentTokenViewModel>, AppError> {
+    let agent_service = AgentServiceImpl::new(state.db_pool.clone());
+    let token = agent_service.rotate_token(&user_uuid, &uuid).await?;
+    state
+        .agent_registry
+        .disconnect(&uuid, "Agent token rotated")
+        .await;
+
+    Ok(Json(AgentTokenViewModel { uuid, token }))
+}
+
+/// Revokes the token of the agent, a socket authenticated with it is closed
+async fn revoke_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+) -> Result<Json<bool>, AppError> {
+    let agent_service = AgentServiceImpl::new(state.db_pool.clone());
+    agent_service.revoke_token(&user_uuid, &uuid).await?;
+    state
+        .agent_registry
+        .disconnect(&uuid, "Agent token revoked")
+        .await;
 
     Ok(Json(true))
 }
@@ -0,0 +1,46 @@
+use crate::common::app_error::AppError;
+use crate::middlewares::auth_middleware;
+use crate::models::print_job::{PrintJobFilter, PrintJobViewModel};
+use crate::models::view_model::ViewModel;
+use crate::services::print_job_service::{PrintJobService, PrintJobServiceImpl};
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::routing::get;
+use axum::{middleware, Extension, Json, Router};
+use std::sync::Arc;
+use tracing::info;
+
+pub fn init() -> Router<Arc<AppState>> {
+    info!("Ok");
+    Router::new()
+        .route("/jobs", get(get_all))
+        .route("/jobs/:uuid", get(get_by_uuid))
+        .route_layer(middleware::from_fn(auth_middleware::handle))
+}
+
+async fn get_all(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Query(filter): Query<PrintJobFilter>,
+) -> Result<Json<Vec<PrintJobViewModel>>, AppError> {
+    let print_job_service = PrintJobServiceImpl::new(state.db_pool.clone());
+    let jobs = print_job_service.get_all(&user_uuid, filter).await?;
+
+    let jobs = jobs
+        .into_iter()
+        .map(|job| job.to_viewmodel())
+        .collect::<Vec<PrintJobViewModel>>();
+
+    Ok(Json(jobs))
+}
+
+async fn get_by_uuid(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+) -> Result<Json<PrintJobViewModel>, AppError> {
+    let print_job_service = PrintJobServiceImpl::new(state.db_pool.clone());
+    let job = print_job_service.get_by_uuid(&user_uuid, &uuid).await?;
+
+    Ok(Json(job.to_viewmodel()))
+}
@@ -1,10 +1,14 @@
 pub mod account_controller;
 pub mod agent_controller;
 pub mod auth_controller;
+pub mod job_controller;
+pub mod printer_controller;
 pub mod printfile_controller;
+pub mod queue_controller;
 
 pub mod websockets {
     pub mod agent_websocket;
+    pub mod file_transfer;
     pub mod user_websocket;
     pub mod websocket_message;
 }
@@ -0,0 +1,136 @@
+use crate::common::app_error::AppError;
+use crate::middlewares::auth_middleware;
+use crate::models::printer::{PrinterRequest, PrinterViewModel};
+use crate::models::printer_status::PrinterStatusViewModel;
+use crate::models::view_model::ViewModel;
+use crate::services::printer_service::{PrinterService, PrinterServiceImpl};
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post, put};
+use axum::{middleware, Extension, Json, Router};
+use std::sync::Arc;
+use tracing::info;
+
+pub fn init() -> Router<Arc<AppState>> {
+    info!("Ok");
+    Router::new()
+        .route("/agents/:uuid/printers", get(get_all))
+        .route("/agents/:uuid/printers", post(add))
+        .route("/agents/:uuid/printers/:printer_uuid", get(get_by_uuid))
+        .route("/agents/:uuid/printers/:printer_uuid", put(update))
+        .route(
+            "/agents/:uuid/printers/:printer_uuid",
+            delete(delete_by_uuid),
+        )
+        .route("/printers/:uuid/status", get(get_status))
+        .route("/printers/:uuid/bed-cleared", post(bed_cleared))
+        .route_layer(middleware::from_fn(auth_middleware::handle))
+}
+
+async fn add(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(agent_uuid): Path<String>,
+    Json(json): Json<PrinterRequest>,
+) -> Result<Json<PrinterViewModel>, AppError> {
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    let printer = printer_service.add(&user_uuid, &agent_uuid, json).await?;
+
+    Ok(Json(printer.to_viewmodel()))
+}
+
+async fn get_all(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(agent_uuid): Path<String>,
+) -> Result<Json<Vec<PrinterViewModel>>, AppError> {
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    let printers = printer_service.get_all(&user_uuid, &agent_uuid).await?;
+
+    let printers = printers
+        .into_iter()
+        .map(|printer| printer.to_viewmodel())
+        .collect::<Vec<PrinterViewModel>>();
+
+    Ok(Json(printers))
+}
+
+async fn get_by_uuid(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path((agent_uuid, printer_uuid)): Path<(String, String)>,
+) -> Result<Json<PrinterViewModel>, AppError> {
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    let printer = printer_service
+        .get_by_agent(&user_uuid, &agent_uuid, &printer_uuid)
+        .await?;
+
+    Ok(Json(printer.to_viewmodel()))
+}
+
+async fn update(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path((agent_uuid, printer_uuid)): Path<(String, String)>,
+    Json(json): Json<PrinterRequest>,
+) -> Result<Json<PrinterViewModel>, AppError> {
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    printer_service
+        .get_by_agent(&user_uuid, &agent_uuid, &printer_uuid)
+        .await?;
+    let printer = printer_service
+        .update(&user_uuid, &printer_uuid, json)
+        .await?;
+
+    Ok(Json(printer.to_viewmodel()))
+}
+
+async fn delete_by_uuid(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path((agent_uuid, printer_uuid)): Path<(String, String)>,
+) -> Result<Json<bool>, AppError> {
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    printer_service
+        .get_by_agent(&user_uuid, &agent_uuid, &printer_uuid)
+        .await?;
+    let deleted = printer_service.delete(&user_uuid, &printer_uuid).await?;
+    state.printer_statuses.remove(&printer_uuid).await;
+
+    Ok(Json(deleted))
+}
+
+async fn get_status(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(printer_uuid): Path<String>,
+) -> Result<Json<PrinterStatusViewModel>, AppError> {
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    printer_service
+        .get_by_uuid(&user_uuid, &printer_uuid)
+        .await?;
+
+    match state.printer_statuses.get(&printer_uuid).await {
+        Some(stored) => Ok(Json(stored.status)),
+        None => Err(AppError::Printer {
+            message: "No status reported for this printer".to_string(),
+            status: StatusCode::NOT_FOUND,
+        }),
+    }
+}
+
+/// Confirms the bed of the printer is cleared, allows the printer to start the next queued print file
+async fn bed_cleared(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(printer_uuid): Path<String>,
+) -> Result<Json<PrinterViewModel>, AppError> {
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    let printer = printer_service
+        .set_bed_cleared(&user_uuid, &printer_uuid, true)
+        .await?;
+    state.print_dispatcher.notify(&user_uuid, &printer_uuid);
+
+    Ok(Json(printer.to_viewmodel()))
+}
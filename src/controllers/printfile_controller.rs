@@ -1,16 +1,34 @@
+use std::str::FromStr;
 use std::sync::Arc;
 
+use axum::body::StreamBody;
 use axum::extract::Path;
-use axum::extract::{DefaultBodyLimit, Multipart, State};
+use axum::extract::{DefaultBodyLimit, Multipart, Query, State};
+use axum::http::header::{
+    HeaderName, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE,
+    ETAG, IF_NONE_MATCH, IF_RANGE, RANGE,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::routing::{delete, get, post};
 use axum::{middleware, Extension, Json, Router};
-use tracing::info;
+use tracing::{error, info};
 
 use crate::common::app_error::AppError;
+use crate::common::download::{
+    content_disposition, etag, matches_etag, parse_range, RangeNotSatisfiable,
+};
 use crate::middlewares::auth_middleware;
-use crate::models::printfile::PrintFileViewModel;
+use crate::models::printfile::{FileType, PrintFileViewModel, MAX_UPLOAD_SIZE};
+use crate::models::printfile_thumbnail::{select_thumbnail, ThumbnailQuery};
 use crate::models::view_model::ViewModel;
+use crate::services::printfile_metadata_service::{
+    PrintFileMetadataService, PrintFileMetadataServiceImpl,
+};
 use crate::services::printfile_service::{PrintFileService, PrintFileServiceImpl};
+use crate::services::printfile_thumbnail_service::{
+    PrintFileThumbnailService, PrintFileThumbnailServiceImpl,
+};
 use crate::AppState;
 
 pub fn init() -> Router<Arc<AppState>> {
@@ -21,7 +39,8 @@ pub fn init() -> Router<Arc<AppState>> {
         .route("/files/upload", post(upload))
         .route("/files/:uuid", delete(delete_by_uuid))
         .route("/files/:uuid/download", get(download))
-        .route_layer(DefaultBodyLimit::max(1024 * 1024 * 20)) // 20MB
+        .route("/files/:uuid/thumbnail", get(thumbnail))
+        .route_layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE))
         .route_layer(middleware::from_fn(auth_middleware::handle))
 }
 
@@ -29,12 +48,21 @@ async fn get_all(
     State(state): State<Arc<AppState>>,
     Extension(user_uuid): Extension<String>,
 ) -> Result<Json<Vec<PrintFileViewModel>>, AppError> {
-    let printfile_service = PrintFileServiceImpl::new(state.db_pool.clone());
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let metadata_service = PrintFileMetadataServiceImpl::new(state.db_pool.clone());
     let printfiles = printfile_service.get_all(&user_uuid).await?;
+    let metadata = metadata_service.get_all(&user_uuid).await?;
 
     let files = printfiles
         .into_iter()
-        .map(|printfile| printfile.to_viewmodel())
+        .map(|printfile| {
+            let mut file = printfile.to_viewmodel();
+            file.metadata = metadata
+                .get(&printfile.uuid)
+                .map(|metadata| metadata.to_viewmodel());
+            file
+        })
         .collect::<Vec<PrintFileViewModel>>();
 
     Ok(Json(files))
@@ -45,12 +73,12 @@ async fn get_by_uuid(
     Extension(user_uuid): Extension<String>,
     Path(uuid): Path<String>,
 ) -> Result<Json<PrintFileViewModel>, AppError> {
-    let printfile_service = PrintFileServiceImpl::new(state.db_pool.clone());
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
 
     let printfile = printfile_service.get_by_uuid(&user_uuid, &uuid).await?;
-    let printfile = printfile.to_viewmodel();
 
-    Ok(Json(printfile))
+    Ok(Json(with_metadata(&state, printfile.to_viewmodel()).await?))
 }
 
 async fn upload(
@@ -58,22 +86,129 @@ async fn upload(
     Extension(user_uuid): Extension<String>,
     multipart: Multipart,
 ) -> Result<Json<PrintFileViewModel>, AppError> {
-    let printfile_service = PrintFileServiceImpl::new(state.db_pool.clone());
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
     let printfile = printfile_service.upload(&user_uuid, multipart).await?;
-    let printfile = printfile.to_viewmodel();
 
-    Ok(Json(printfile))
+    Ok(Json(with_metadata(&state, printfile.to_viewmodel()).await?))
 }
 
+async fn with_metadata(
+    state: &Arc<AppState>,
+    mut printfile: PrintFileViewModel,
+) -> Result<PrintFileViewModel, AppError> {
+    let metadata_service = PrintFileMetadataServiceImpl::new(state.db_pool.clone());
+    printfile.metadata = metadata_service
+        .get_by_printfile(&printfile.uuid)
+        .await?
+        .map(|metadata| metadata.to_viewmodel());
+
+    Ok(printfile)
+}
+
+/// Streams the file with its name, type and checksum as ETag.
+/// Supports If-None-Match, If-Range and single byte ranges to resume downloads
 async fn download(
     State(state): State<Arc<AppState>>,
     Extension(user_uuid): Extension<String>,
     Path(uuid): Path<String>,
-) -> Result<Vec<u8>, AppError> {
-    let printfile_service = PrintFileServiceImpl::new(state.db_pool.clone());
-    let printfile = printfile_service.download(&user_uuid, &uuid).await?;
+    request_headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let printfile = printfile_service.get_by_uuid(&user_uuid, &uuid).await?;
 
-    Ok(printfile)
+    let etag = etag(&printfile.checksum);
+    let size = printfile.size as u64;
+    let header = |name: HeaderName| {
+        request_headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ETAG, header_value(&etag)?);
+    headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+    if header(IF_NONE_MATCH).is_some_and(|if_none_match| matches_etag(if_none_match, &etag)) {
+        return Ok((StatusCode::NOT_MODIFIED, headers).into_response());
+    }
+
+    // a range is only sent if the client still has the same version of the file
+    let range = match header(RANGE) {
+        Some(range) if header(IF_RANGE).is_none_or(|if_range| if_range == etag) => {
+            parse_range(range, size)
+        }
+        _ => Ok(None),
+    };
+    let range = match range {
+        Ok(range) => range,
+        Err(RangeNotSatisfiable) => {
+            headers.insert(CONTENT_RANGE, header_value(&format!("bytes */{}", size))?);
+            return Ok((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response());
+        }
+    };
+
+    let file_type = FileType::from_str(&printfile.file_type).unwrap_or(FileType::Unknown);
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static(file_type.content_type()),
+    );
+    headers.insert(
+        CONTENT_DISPOSITION,
+        header_value(&content_disposition(&printfile.name))?,
+    );
+
+    let stream = printfile_service.download_stream(&printfile, range).await?;
+    let status = match range {
+        Some(range) => {
+            headers.insert(CONTENT_RANGE, header_value(&range.content_range(size))?);
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(range.length()));
+            StatusCode::PARTIAL_CONTENT
+        }
+        None => {
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(size));
+            StatusCode::OK
+        }
+    };
+
+    Ok((status, headers, StreamBody::new(stream)).into_response())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, AppError> {
+    HeaderValue::from_str(value).map_err(|e| {
+        error!("invalid header value {}: {}", value, e);
+        AppError::InternalServer
+    })
+}
+
+async fn thumbnail(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+    Query(query): Query<ThumbnailQuery>,
+) -> Result<([(HeaderName, &'static str); 1], Vec<u8>), AppError> {
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let thumbnail_service =
+        PrintFileThumbnailServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+
+    let printfile = printfile_service.get_by_uuid(&user_uuid, &uuid).await?;
+    let thumbnails = thumbnail_service.get_by_printfile(&printfile.uuid).await?;
+    let thumbnail = match select_thumbnail(&thumbnails, query.size.as_deref()) {
+        Some(thumbnail) => thumbnail,
+        None => {
+            return Err(AppError::PrintFile {
+                message: "No thumbnail found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            })
+        }
+    };
+    let data = thumbnail_service
+        .download(&printfile.file_storage_type, thumbnail)
+        .await?;
+
+    Ok(([(CONTENT_TYPE, thumbnail.format().content_type())], data))
 }
 
 async fn delete_by_uuid(
@@ -81,7 +216,8 @@ async fn delete_by_uuid(
     Extension(user_uuid): Extension<String>,
     Path(uuid): Path<String>,
 ) -> Result<Json<bool>, AppError> {
-    let printfile_service = PrintFileServiceImpl::new(state.db_pool.clone());
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
     let deleted = printfile_service.delete(&user_uuid, &uuid).await?;
 
     Ok(Json(deleted))
@@ -0,0 +1,89 @@
+use crate::common::app_error::AppError;
+use crate::middlewares::auth_middleware;
+use crate::models::print_queue::{
+    PrintQueueFilter, PrintQueuePositionRequest, PrintQueueRequest, PrintQueueViewModel,
+};
+use crate::models::view_model::ViewModel;
+use crate::services::print_queue_service::{PrintQueueService, PrintQueueServiceImpl};
+use crate::AppState;
+use axum::extract::{Path, Query, State};
+use axum::routing::{delete, get, post, put};
+use axum::{middleware, Extension, Json, Router};
+use std::sync::Arc;
+use tracing::info;
+
+pub fn init() -> Router<Arc<AppState>> {
+    info!("Ok");
+    Router::new()
+        .route("/queue", get(get_all))
+        .route("/queue", post(add))
+        .route("/queue/:uuid/position", put(move_to))
+        .route("/queue/:uuid", delete(delete_by_uuid))
+        .route_layer(middleware::from_fn(auth_middleware::handle))
+}
+
+async fn add(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Json(json): Json<PrintQueueRequest>,
+) -> Result<Json<PrintQueueViewModel>, AppError> {
+    let print_queue_service =
+        PrintQueueServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let entry = print_queue_service.add(&user_uuid, json).await?;
+
+    // one of the printers may already be waiting for work
+    for printer_uuid in entry.printer_uuids() {
+        state.print_dispatcher.notify(&user_uuid, &printer_uuid);
+    }
+
+    Ok(Json(entry.to_viewmodel()))
+}
+
+async fn get_all(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Query(filter): Query<PrintQueueFilter>,
+) -> Result<Json<Vec<PrintQueueViewModel>>, AppError> {
+    let print_queue_service =
+        PrintQueueServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let entries = print_queue_service.get_all(&user_uuid, filter).await?;
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| entry.to_viewmodel())
+        .collect::<Vec<PrintQueueViewModel>>();
+
+    Ok(Json(entries))
+}
+
+async fn move_to(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+    Json(json): Json<PrintQueuePositionRequest>,
+) -> Result<Json<Vec<PrintQueueViewModel>>, AppError> {
+    let print_queue_service =
+        PrintQueueServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let entries = print_queue_service
+        .move_to(&user_uuid, &uuid, json.position)
+        .await?;
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| entry.to_viewmodel())
+        .collect::<Vec<PrintQueueViewModel>>();
+
+    Ok(Json(entries))
+}
+
+async fn delete_by_uuid(
+    State(state): State<Arc<AppState>>,
+    Extension(user_uuid): Extension<String>,
+    Path(uuid): Path<String>,
+) -> Result<Json<bool>, AppError> {
+    let print_queue_service =
+        PrintQueueServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let deleted = print_queue_service.delete(&user_uuid, &uuid).await?;
+
+    Ok(Json(deleted))
+}
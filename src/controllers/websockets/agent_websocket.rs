@@ -1,25 +1,468 @@
-use axum::extract::ws::WebSocket;
-use axum::extract::{ConnectInfo, WebSocketUpgrade};
-use axum::response::IntoResponse;
-use futures_util::stream::StreamExt;
+use std::borrow::Cow;
 use std::net::SocketAddr;
-use tracing::info;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use axum::extract::{ConnectInfo, State, WebSocketUpgrade};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{stream::StreamExt, SinkExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::common::app_error::AppError;
+use crate::controllers::websockets::file_transfer::FileTransfers;
+use crate::controllers::websockets::websocket_message::{
+    parse_message, AgentStatus, AgentStatusMessage, AuthenticationMessage, DiscoveredPrinter,
+    Payload, PrinterDiscoveryMessage, StatusResponse, WebSocketMessage,
+};
+use crate::infra::agent_registry::AgentConnection;
+use crate::infra::heartbeat::Heartbeat;
+use crate::infra::printer_status_store::StoredPrinterStatus;
+use crate::models::printer::PrinterRequest;
+use crate::models::printer_status::{PrinterStatus, PrinterStatusReport};
+use crate::services::agent_service::{AgentService, AgentServiceImpl};
+use crate::services::print_job_service::{PrintJobService, PrintJobServiceImpl};
+use crate::services::printer_service::{PrinterService, PrinterServiceImpl};
+use crate::AppState;
+
+/// Time an agent has to authenticate after connecting before the socket is closed
+const AUTHENTICATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AgentSession {
+    pub uuid: String,
+    pub user_uuid: String,
+    pub name: String,
+    pub authenticated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AgentWebSocketSession {
+    pub agent: AgentSession,
+}
 
 pub async fn handler(
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
     info!("agent at {addr} connected.");
-    ws.on_upgrade(move |socket| handle_socket(socket, addr))
+    ws.on_upgrade(move |socket| handle_socket(socket, addr, State(state)))
 }
 
-async fn handle_socket(socket: WebSocket, addr: SocketAddr) {
-    let (_sender, mut receiver) = socket.split();
+async fn handle_socket(socket: WebSocket, addr: SocketAddr, State(state): State<Arc<AppState>>) {
+    let (mut sender, mut receiver) = socket.split();
+
+    let mut session = AgentWebSocketSession {
+        agent: AgentSession {
+            uuid: "".to_string(),
+            user_uuid: "".to_string(),
+            name: "".to_string(),
+            authenticated: false,
+        },
+    };
 
     tokio::spawn(async move {
-        while let Some(message) = &receiver.next().await {
-            info!("Received message: {:?} from {:?}", message, addr);
+        // the agent has to authenticate before anything else, otherwise the socket is closed
+        let authentication = authenticate(&mut sender, &mut receiver, &state, &mut session);
+        match timeout(AUTHENTICATION_TIMEOUT, authentication).await {
+            Ok(true) => {
+                info!(
+                    "agent {} (user: {}) authenticated from {:?}",
+                    session.agent.uuid, session.agent.user_uuid, addr
+                );
+            }
+            Ok(false) => {
+                info!("Connection closed with {:?} before authenticating", addr);
+                return;
+            }
+            Err(_) => {
+                warn!("Agent at {:?} did not authenticate in time", addr);
+                let _ = sender
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::POLICY,
+                        reason: Cow::from("Authentication timeout"),
+                    })))
+                    .await;
+                return;
+            }
+        }
+
+        // all outbound messages go through the registry channel, so others can reach the agent
+        let (outbound, mut outbound_receiver) = unbounded_channel::<Message>();
+        let writer = tokio::spawn(async move {
+            while let Some(message) = outbound_receiver.recv().await {
+                let closing = matches!(message, Message::Close(_));
+                if sender.send(message).await.is_err() || closing {
+                    break;
+                }
+            }
+        });
+
+        let connection_uuid = Uuid::new_v4().to_string();
+        let closed = CancellationToken::new();
+        state
+            .agent_registry
+            .register(
+                &session.agent.uuid,
+                AgentConnection {
+                    connection_uuid: connection_uuid.to_string(),
+                    user_uuid: session.agent.user_uuid.to_string(),
+                    address: addr,
+                    sender: outbound.clone(),
+                    closed: closed.clone(),
+                },
+            )
+            .await;
+        publish_agent_status(&state, &session, AgentStatus::Online).await;
+
+        // agents on flaky networks drop without closing the socket, missed heartbeats end the loop
+        let mut heartbeat = Heartbeat::new(state.heartbeat, outbound.clone());
+        let mut file_transfers = FileTransfers::new();
+        while let Some(message) = &next_message(&mut heartbeat, &mut receiver, &closed).await {
+            // messages carry file chunks and frequent status reports
+            debug!(
+                "Received message: {:?} from agent {}",
+                message, session.agent.uuid
+            );
+            state.agent_registry.touch(&session.agent.uuid).await;
+
+            // control frames are handled by axum
+            if !matches!(message, Ok(Message::Text(_))) {
+                continue;
+            }
+
+            let message = match parse_message(message) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!("Error parsing message: {:?}", err);
+                    let _ = outbound.send(WebSocketMessage::error(&err).to_message());
+                    continue;
+                }
+            };
+
+            // acks and replies answer a request the backend sent to the agent
+            if let Some(reply_to) = message.reply_to {
+                handle_reply(&reply_to, message.message, &session, &state).await;
+                continue;
+            }
+
+            let id = message.id;
+            match message.message {
+                WebSocketMessage::PrinterStatus(Payload::Request(report)) => {
+                    if let Err(err) = handle_printer_status(report, &session, &state).await {
+                        warn!("Error handling printer status: {:?}", err);
+                        let response = WebSocketMessage::PrinterStatus(Payload::Reply(
+                            StatusResponse::error(&err),
+                        ));
+                        let _ = outbound.send(response.reply_to(id).to_message());
+                    }
+                }
+                WebSocketMessage::PrinterDiscovery(Payload::Request(discovery)) => {
+                    let response = match handle_printer_discovery(discovery, &session, &state).await
+                    {
+                        Ok(discovery) => Payload::Request(discovery),
+                        Err(err) => {
+                            warn!("Error handling printer discovery: {:?}", err);
+                            Payload::Reply(StatusResponse::error(&err))
+                        }
+                    };
+                    let response = WebSocketMessage::PrinterDiscovery(response);
+                    let _ = outbound.send(response.reply_to(id).to_message());
+                }
+                WebSocketMessage::FileRequest(request) => {
+                    let response = match file_transfers
+                        .handle_request(request, &session.agent.user_uuid, &state)
+                        .await
+                    {
+                        Ok(chunk) => Payload::Request(chunk),
+                        Err(err) => {
+                            warn!("Error handling file request: {:?}", err);
+                            Payload::Reply(StatusResponse::error(&err))
+                        }
+                    };
+                    let response = WebSocketMessage::FileChunk(response);
+                    let _ = outbound.send(response.reply_to(id).to_message());
+                }
+                message => {
+                    warn!("Unsupported message: {:?}", message);
+                }
+            }
+        }
+
+        if heartbeat.timed_out() {
+            warn!(
+                "agent {} at {:?} missed its heartbeats",
+                session.agent.uuid, addr
+            );
+        }
+
+        // a newer connection of the same agent keeps it online
+        let unregistered = state
+            .agent_registry
+            .unregister(&session.agent.uuid, &connection_uuid)
+            .await;
+        if unregistered {
+            publish_agent_status(&state, &session, AgentStatus::Offline).await;
+
+            // the printers of the agent can't be reached anymore
+            let statuses = state
+                .printer_statuses
+                .mark_agent_offline(&session.agent.uuid)
+                .await;
+            for stored in statuses {
+                publish_printer_status(&state, &stored).await;
+            }
         }
+        writer.abort();
         info!("Connection closed with {:?}", addr);
     });
 }
+
+/// Waits for the next message of the agent, returns None once the socket closed, the agent missed
+/// its heartbeats or the backend dropped the connection
+async fn next_message(
+    heartbeat: &mut Heartbeat,
+    receiver: &mut SplitStream<WebSocket>,
+    closed: &CancellationToken,
+) -> Option<Result<Message, axum::Error>> {
+    tokio::select! {
+        message = heartbeat.next(receiver) => message,
+        _ = closed.cancelled() => None,
+    }
+}
+
+/// Waits for a valid authentication message, any other message is answered with an error.
+/// Returns false if the connection was closed before the agent authenticated
+async fn authenticate(
+    sender: &mut SplitSink<WebSocket, Message>,
+    receiver: &mut SplitStream<WebSocket>,
+    state: &Arc<AppState>,
+    session: &mut AgentWebSocketSession,
+) -> bool {
+    while let Some(message) = &receiver.next().await {
+        // control frames are handled by axum
+        if !matches!(message, Ok(Message::Text(_))) {
+            continue;
+        }
+
+        let message = match parse_message(message) {
+            Ok(message) => message,
+            Err(err) => {
+                warn!("Error parsing message: {:?}", err);
+                let _ = sender
+                    .send(WebSocketMessage::error(&err).to_message())
+                    .await;
+                continue;
+            }
+        };
+
+        let authentication = match message.message {
+            WebSocketMessage::Authentication(Payload::Request(authentication)) => authentication,
+            _ => {
+                let err = AppError::Auth {
+                    message: "Session not authenticated".to_string(),
+                    status: StatusCode::UNAUTHORIZED,
+                };
+                let _ = sender
+                    .send(WebSocketMessage::error(&err).to_message())
+                    .await;
+                continue;
+            }
+        };
+
+        match handle_auth_message(authentication, state, session).await {
+            Ok(_) => {
+                let response =
+                    WebSocketMessage::Authentication(Payload::Reply(StatusResponse::ok()));
+                let _ = sender.send(response.to_message()).await;
+                return true;
+            }
+            Err(err) => {
+                warn!("Error authenticating agent: {:?}", err);
+                let response =
+                    WebSocketMessage::Authentication(Payload::Reply(StatusResponse::error(&err)));
+                let _ = sender.send(response.to_message()).await;
+            }
+        }
+    }
+
+    false
+}
+
+async fn handle_auth_message(
+    authentication: AuthenticationMessage,
+    state: &Arc<AppState>,
+    session: &mut AgentWebSocketSession,
+) -> Result<(), AppError> {
+    let agent_service = AgentServiceImpl::new(state.db_pool.clone());
+    let agent = agent_service.get_by_token(&authentication.token).await?;
+
+    // outdated agents would receive requests they don't understand
+    authentication.handshake.check_protocol()?;
+    agent_service
+        .update_handshake(&agent.uuid, &authentication.handshake)
+        .await?;
+
+    session.agent.uuid = agent.uuid;
+    session.agent.user_uuid = agent.user_uuid;
+    session.agent.name = agent.name;
+    session.agent.authenticated = true;
+
+    Ok(())
+}
+
+/// Hands an ack or reply of the agent to the handler awaiting it
+async fn handle_reply(
+    reply_to: &str,
+    message: WebSocketMessage,
+    session: &AgentWebSocketSession,
+    state: &Arc<AppState>,
+) {
+    let registry = &state.agent_registry;
+    let handled = match message {
+        WebSocketMessage::Ack => registry.acknowledge(&session.agent.uuid, reply_to).await,
+        message => {
+            registry
+                .resolve(&session.agent.uuid, reply_to, message)
+                .await
+        }
+    };
+
+    // replies to requests that timed out or were answered already are dropped
+    if !handled {
+        warn!(
+            "agent {} answered unknown request {}",
+            session.agent.uuid, reply_to
+        );
+    }
+}
+
+/// Lets the open sessions of the owner know that the agent came online or went offline
+async fn publish_agent_status(
+    state: &Arc<AppState>,
+    session: &AgentWebSocketSession,
+    status: AgentStatus,
+) {
+    let agent_status = AgentStatusMessage {
+        agent_name: session.agent.name.to_string(),
+        agent_uuid: session.agent.uuid.to_string(),
+        status,
+    };
+    let message = WebSocketMessage::AgentStatus(agent_status);
+
+    state
+        .user_hub
+        .publish(&session.agent.user_uuid, message.to_message())
+        .await;
+}
+
+/// Stores the printers reported by the agent and answers with their uuids
+async fn handle_printer_discovery(
+    discovery: PrinterDiscoveryMessage,
+    session: &AgentWebSocketSession,
+    state: &Arc<AppState>,
+) -> Result<PrinterDiscoveryMessage, AppError> {
+    let printers = discovery
+        .printers
+        .into_iter()
+        .map(|printer| PrinterRequest {
+            identifier: printer.printer_identifier,
+            adapter_identifier: printer.printer_adapter_identifier,
+            adapter_interface: printer.printer_adapter_interface,
+        })
+        .collect();
+
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    let printers = printer_service
+        .report(&session.agent.user_uuid, &session.agent.uuid, printers)
+        .await?;
+
+    Ok(PrinterDiscoveryMessage {
+        printers: printers
+            .into_iter()
+            .map(|printer| DiscoveredPrinter {
+                printer_uuid: Some(printer.uuid),
+                printer_identifier: printer.identifier,
+                printer_adapter_identifier: printer.adapter_identifier,
+                printer_adapter_interface: printer.adapter_interface,
+            })
+            .collect(),
+    })
+}
+
+/// Stores the latest status of a printer of the agent and forwards it to the sessions of the owner
+async fn handle_printer_status(
+    report: PrinterStatusReport,
+    session: &AgentWebSocketSession,
+    state: &Arc<AppState>,
+) -> Result<(), AppError> {
+    report.validate()?;
+
+    if report.agent_uuid != session.agent.uuid {
+        return Err(AppError::Agent {
+            message: "Printer status was reported for another agent".to_string(),
+            status: StatusCode::FORBIDDEN,
+        });
+    }
+
+    // the ownership is only looked up once, statuses are reported continuously
+    let statuses = &state.printer_statuses;
+    if !statuses
+        .is_known(&report.printer_uuid, &session.agent.uuid)
+        .await
+    {
+        let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+        printer_service
+            .get_by_agent(
+                &session.agent.user_uuid,
+                &session.agent.uuid,
+                &report.printer_uuid,
+            )
+            .await?;
+    }
+
+    // the status of the printer drives the state of its active print job
+    let print_job_service = PrintJobServiceImpl::new(state.db_pool.clone());
+    if let Err(err) = print_job_service
+        .apply_status(&session.agent.user_uuid, &report)
+        .await
+    {
+        warn!(
+            "Error applying status of printer {} to its print job: {:?}",
+            report.printer_uuid, err
+        );
+    }
+
+    let was_available = matches!(
+        statuses.get(&report.printer_uuid).await,
+        Some(previous) if previous.status.report.status == PrinterStatus::Available
+    );
+    let stored = statuses.update(&session.agent.user_uuid, report).await;
+    publish_printer_status(state, &stored).await;
+
+    // a printer that just became available may start the next queued print file
+    if !was_available && stored.status.report.status == PrinterStatus::Available {
+        state
+            .print_dispatcher
+            .notify(&stored.user_uuid, &stored.status.report.printer_uuid);
+    }
+
+    Ok(())
+}
+
+async fn publish_printer_status(state: &Arc<AppState>, stored: &StoredPrinterStatus) {
+    let message = WebSocketMessage::PrinterStatus(Payload::Request(stored.status.report.clone()));
+
+    state
+        .user_hub
+        .publish(&stored.user_uuid, message.to_message())
+        .await;
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+
+use crate::common::app_error::AppError;
+use crate::common::download::ByteRange;
+use crate::controllers::websockets::websocket_message::{FileChunkMessage, FileRequestMessage};
+use crate::infra::strategies::file_storage_strategy::FileStorageStrategy;
+use crate::services::printfile_service::{PrintFileService, PrintFileServiceImpl};
+use crate::AppState;
+
+/// Size of a chunk when the agent doesn't ask for a length
+pub const DEFAULT_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Largest chunk an agent can ask for, keeps websocket frames small on slow links
+pub const MAX_CHUNK_SIZE: u64 = 512 * 1024;
+
+/// Print file that is being transferred to the agent
+struct TransferredFile {
+    print_file_uuid: String,
+    file_storage_type: String,
+    path: String,
+    checksum: String,
+    size: u64,
+}
+
+/// Transfers of print files to one agent connection. Only the location of the file being
+/// transferred is kept, each chunk is read from the file storage with a range read
+#[derive(Default)]
+pub struct FileTransfers {
+    current: Option<TransferredFile>,
+}
+
+impl FileTransfers {
+    pub fn new() -> Self {
+        FileTransfers::default()
+    }
+
+    /// Answers a file request of the agent with the requested chunk, the agent may only
+    /// request print files of its owner
+    pub async fn handle_request(
+        &mut self,
+        request: FileRequestMessage,
+        user_uuid: &str,
+        state: &Arc<AppState>,
+    ) -> Result<FileChunkMessage, AppError> {
+        let file = match &self.current {
+            Some(file) if file.print_file_uuid == request.print_file_uuid => file,
+            _ => self
+                .current
+                .insert(load_file(&request.print_file_uuid, user_uuid, state).await?),
+        };
+
+        let length = request
+            .length
+            .unwrap_or(DEFAULT_CHUNK_SIZE)
+            .clamp(1, MAX_CHUNK_SIZE);
+        let file_storage = state.file_storages.get(&file.file_storage_type)?;
+        let (data, last) = read_chunk(
+            &*file_storage,
+            &file.path,
+            file.size,
+            request.offset,
+            length,
+        )
+        .await?;
+
+        let chunk = FileChunkMessage {
+            print_file_uuid: file.print_file_uuid.to_string(),
+            offset: request.offset,
+            size: file.size,
+            checksum: file.checksum.to_string(),
+            chunk_checksum: format!("{:x}", Sha256::digest(&data)),
+            data: STANDARD.encode(&data),
+            last,
+        };
+
+        if last {
+            info!("sent last chunk of print file {}", chunk.print_file_uuid);
+            self.current = None;
+        }
+        Ok(chunk)
+    }
+}
+
+/// Looks up the print file and the size of its stored file, the agent verifies the assembled
+/// file against the stored checksum
+async fn load_file(
+    print_file_uuid: &str,
+    user_uuid: &str,
+    state: &Arc<AppState>,
+) -> Result<TransferredFile, AppError> {
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let printfile = printfile_service
+        .get_by_uuid(user_uuid, print_file_uuid)
+        .await?;
+    let stat = state
+        .file_storages
+        .get(&printfile.file_storage_type)?
+        .stat(&printfile.path)
+        .await?;
+
+    if stat.size != printfile.size as u64 {
+        error!(
+            "size of print file {} does not match, expected {} but got {}",
+            print_file_uuid, printfile.size, stat.size
+        );
+        return Err(AppError::PrintFile {
+            message: "Print file is corrupted".to_string(),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
+        });
+    }
+
+    Ok(TransferredFile {
+        print_file_uuid: print_file_uuid.to_string(),
+        file_storage_type: printfile.file_storage_type,
+        path: printfile.path,
+        checksum: printfile.checksum,
+        size: stat.size,
+    })
+}
+
+/// Reads the bytes of the file from the offset, at most length bytes,
+/// and whether they end at the end of the file
+async fn read_chunk(
+    file_storage: &dyn FileStorageStrategy,
+    path: &str,
+    size: u64,
+    offset: u64,
+    length: u64,
+) -> Result<(Vec<u8>, bool), AppError> {
+    if offset > size {
+        return Err(AppError::Validation {
+            messages: format!(
+                "Offset {} is past the end of the file ({} bytes)",
+                offset, size
+            ),
+            status: StatusCode::RANGE_NOT_SATISFIABLE,
+        });
+    }
+
+    let end = offset.saturating_add(length).min(size);
+    if end == offset {
+        return Ok((Vec::new(), true));
+    }
+
+    let range = ByteRange {
+        start: offset,
+        end: end - 1,
+    };
+    let data = file_storage.retrieve_range(path, range).await?;
+
+    Ok((data, end == size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infra::strategies::memory_file_strategy::MemoryFileStrategy;
+
+    #[tokio::test]
+    async fn test_chunk() {
+        let storage = MemoryFileStrategy::new();
+        let path = storage
+            .write_file("user", "part.gcode", b"G28\nG1 X10\n")
+            .await
+            .unwrap();
+        let read = |offset, length| read_chunk(&storage, &path, 11, offset, length);
+
+        assert_eq!(read(0, 4).await.unwrap(), (b"G28\n".to_vec(), false));
+        assert_eq!(read(4, 4).await.unwrap(), (b"G1 X".to_vec(), false));
+        // resuming at the offset the agent stopped at
+        assert_eq!(read(8, 100).await.unwrap(), (b"10\n".to_vec(), true));
+        assert_eq!(read(10, 1).await.unwrap(), (b"\n".to_vec(), true));
+        assert_eq!(read(11, 4).await.unwrap(), (Vec::new(), true));
+        assert!(read(12, 4).await.is_err());
+    }
+}
@@ -1,21 +1,39 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::extract::ws::{Message, WebSocket};
 use axum::extract::{ConnectInfo, State, WebSocketUpgrade};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
-use axum::Error;
 use futures_util::{stream::StreamExt, SinkExt};
 use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::Mutex;
 use tracing::{info, warn};
+use uuid::Uuid;
 
 use crate::common::app_error::AppError;
 use crate::common::jwt_token::decode_token;
-use crate::controllers::websockets::websocket_message::{WebSocketMessage, WebSocketMessageType};
+use crate::controllers::websockets::websocket_message::{
+    parse_message, Payload, PrintJobMessage, PrintJobType, PrinterCommandMessage, StatusResponse,
+    WebSocketMessage,
+};
+use crate::infra::heartbeat::Heartbeat;
+use crate::infra::user_hub::UserConnection;
+use crate::jobs::print_dispatcher::{
+    confirm_print_job, print_job_reply_result, start_print_job, PRINT_JOB_REPLY_TIMEOUT,
+};
+use crate::models::print_job::PrintJobState;
+use crate::services::agent_service::{AgentService, AgentServiceImpl};
+use crate::services::command_policy_service::{CommandPolicyService, CommandPolicyServiceImpl};
+use crate::services::print_job_service::{PrintJobService, PrintJobServiceImpl};
+use crate::services::printer_service::{PrinterService, PrinterServiceImpl};
 use crate::AppState;
 
+/// Time a printer has to answer a command after the agent acknowledged it
+const COMMAND_REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserSession {
     pub uuid: String,
@@ -24,6 +42,7 @@ pub struct UserSession {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct UserWebSocketSession {
+    pub uuid: String,
     pub user: UserSession,
 }
 
@@ -36,10 +55,11 @@ pub async fn handler(
     ws.on_upgrade(move |socket| handle_socket(socket, addr, State(state)))
 }
 
-async fn handle_socket(socket: WebSocket, addr: SocketAddr, State(_state): State<Arc<AppState>>) {
+async fn handle_socket(socket: WebSocket, addr: SocketAddr, State(state): State<Arc<AppState>>) {
     let (mut sender, mut receiver) = socket.split();
 
     let session = Arc::new(Mutex::new(UserWebSocketSession {
+        uuid: Uuid::new_v4().to_string(),
         user: UserSession {
             uuid: "".to_string(),
             authenticated: false,
@@ -48,44 +68,71 @@ async fn handle_socket(socket: WebSocket, addr: SocketAddr, State(_state): State
 
     info!("Created session: {:?}", session);
 
-    // spawn receiver task
+    // outbound channel, allows other sockets to send messages to this session
+    let (outbound, mut outbound_receiver) = unbounded_channel::<Message>();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = outbound_receiver.recv().await {
+            if sender.send(message).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // spawn receiver task, sessions that stop answering pings are closed and unsubscribed
+    let mut heartbeat = Heartbeat::new(state.heartbeat, outbound.clone());
     tokio::spawn(async move {
-        while let Some(message) = &receiver.next().await {
+        while let Some(message) = &heartbeat.next(&mut receiver).await {
             info!("Received message: {:?} from {:?}", &message, addr);
 
+            // control frames are handled by axum
+            if !matches!(message, Ok(Message::Text(_))) {
+                continue;
+            }
+
             let message = match parse_message(message) {
                 Ok(message) => message,
                 Err(err) => {
                     warn!("Error parsing message: {:?}", err);
-                    let _ = sender.send(Message::from("Error parsing message")).await;
+                    let _ = outbound.send(WebSocketMessage::error(&err).to_message());
                     continue;
                 }
             };
 
+            // replies to the user answer the id of the request
+            let id = message.id;
+            let message = message.message;
+
             // check if message is authentication
-            if message.message_type == WebSocketMessageType::UserAuthentication {
-                match handle_auth_message(message, &session).await {
+            if let WebSocketMessage::Authentication(Payload::Request(authentication)) = &message {
+                let already_authenticated = session.lock().await.user.authenticated;
+                match handle_auth_message(&authentication.token, &session).await {
                     Ok(_) => {
                         let session_info = session.lock().await;
-                        let session_json = serde_json::to_string(&session_info.user).unwrap();
-                        let message = WebSocketMessage {
-                            message_type: WebSocketMessageType::UserAuthentication,
-                            body: session_json,
-                        };
-                        let message = serde_json::to_string(&message).unwrap();
-                        let _ = sender.send(Message::from(message)).await;
+                        // the session receives published messages once it is authenticated
+                        if !already_authenticated {
+                            state
+                                .user_hub
+                                .subscribe(
+                                    &session_info.user.uuid,
+                                    UserConnection {
+                                        session_uuid: session_info.uuid.to_string(),
+                                        sender: outbound.clone(),
+                                    },
+                                )
+                                .await;
+                        }
+                        let response =
+                            WebSocketMessage::Authentication(Payload::Reply(StatusResponse::ok()));
+                        let _ = outbound.send(response.reply_to(id).to_message());
 
                         continue;
                     }
                     Err(err) => {
                         warn!("Error authenticating: {:?}", err);
-                        let message = WebSocketMessage {
-                            message_type: WebSocketMessageType::Error,
-                            body: err.to_string(),
-                        };
-                        let _ = sender
-                            .send(Message::from(serde_json::to_string(&message).unwrap()))
-                            .await;
+                        let response = WebSocketMessage::Authentication(Payload::Reply(
+                            StatusResponse::error(&err),
+                        ));
+                        let _ = outbound.send(response.reply_to(id).to_message());
                         continue;
                     }
                 }
@@ -93,46 +140,74 @@ async fn handle_socket(socket: WebSocket, addr: SocketAddr, State(_state): State
 
             //if session is not authenticated, ignore message
             if !session.lock().await.user.authenticated {
-                let message = WebSocketMessage {
-                    message_type: WebSocketMessageType::Error,
-                    body: "Session not authenticated".to_string(),
+                let err = AppError::Auth {
+                    message: "Session not authenticated".to_string(),
+                    status: StatusCode::UNAUTHORIZED,
                 };
-                let _ = sender
-                    .send(Message::from(serde_json::to_string(&message).unwrap()))
-                    .await;
+                let _ = outbound.send(WebSocketMessage::error(&err).to_message());
                 continue;
             }
+
+            let user_uuid = session.lock().await.user.uuid.to_string();
+            // requests to agents wait for their reply, the session keeps receiving meanwhile
+            let state = state.clone();
+            let outbound = outbound.clone();
+            match message {
+                WebSocketMessage::PrintJob(Payload::Request(print_job)) => {
+                    tokio::spawn(async move {
+                        let result = handle_print_job_message(print_job, &user_uuid, &state).await;
+                        if let Err(err) = &result {
+                            warn!("Error handling print job: {:?}", err);
+                        }
+                        let response = WebSocketMessage::PrintJob(Payload::Reply(
+                            StatusResponse::from_result(&result),
+                        ));
+                        let _ = outbound.send(response.reply_to(id).to_message());
+                    });
+                }
+                WebSocketMessage::PrinterCommand(Payload::Request(printer_command)) => {
+                    tokio::spawn(async move {
+                        let response = match handle_printer_command_message(
+                            printer_command,
+                            &user_uuid,
+                            &state,
+                        )
+                        .await
+                        {
+                            Ok(printer_command) => Payload::Request(printer_command),
+                            Err(err) => {
+                                warn!("Error handling printer command: {:?}", err);
+                                Payload::Reply(StatusResponse::error(&err))
+                            }
+                        };
+                        let response = WebSocketMessage::PrinterCommand(response);
+                        let _ = outbound.send(response.reply_to(id).to_message());
+                    });
+                }
+                message => {
+                    warn!("Unsupported message: {:?}", message);
+                }
+            }
+        }
+        if heartbeat.timed_out() {
+            warn!("Session at {:?} missed its heartbeats", addr);
+        }
+        let session_info = session.lock().await;
+        if session_info.user.authenticated {
+            state
+                .user_hub
+                .unsubscribe(&session_info.user.uuid, &session_info.uuid)
+                .await;
         }
+        writer.abort();
         info!("Connection closed with {:?}", addr);
     });
 }
 
-fn parse_message(message: &Result<Message, Error>) -> Result<WebSocketMessage, AppError> {
-    // check if is valid
-    let message = match message {
-        Ok(message) => message,
-        Err(_) => return Err(AppError::InternalServer),
-    };
-    // check if message is text
-    let message = match message.to_text() {
-        Ok(message) => message,
-        Err(_) => return Err(AppError::InternalServer),
-    };
-
-    // try to deserialize message
-    let message: WebSocketMessage = match serde_json::from_str(message) {
-        Ok(message) => message,
-        Err(_) => return Err(AppError::InternalServer),
-    };
-
-    Ok(message)
-}
-
 async fn handle_auth_message(
-    message: WebSocketMessage,
+    token: &str,
     session: &Arc<Mutex<UserWebSocketSession>>,
 ) -> Result<(), AppError> {
-    let token = message.body;
     let mut session = session.lock().await;
 
     if session.user.authenticated {
@@ -140,7 +215,7 @@ async fn handle_auth_message(
     }
 
     // check if token is valid and set session to authenticated
-    match decode_token(&token) {
+    match decode_token(token) {
         Ok(jwt) => {
             session.user.authenticated = true;
             session.user.uuid = jwt.claims.sub;
@@ -156,3 +231,133 @@ async fn handle_auth_message(
 
     Ok(())
 }
+
+/// Forwards a print job to the agent and waits for its reply, the user has to own the agent,
+/// the printer and the print file. START records a new print job, the other types change the
+/// state of the active job of the printer once the agent confirms them
+async fn handle_print_job_message(
+    mut print_job: PrintJobMessage,
+    user_uuid: &str,
+    state: &Arc<AppState>,
+) -> Result<(), AppError> {
+    let agent_service = AgentServiceImpl::new(state.db_pool.clone());
+    agent_service
+        .get_by_uuid(user_uuid, &print_job.agent_uuid)
+        .await?;
+
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    printer_service
+        .get_by_agent(user_uuid, &print_job.agent_uuid, &print_job.printer_uuid)
+        .await?;
+
+    let next_state = match print_job.job_type {
+        PrintJobType::Start => {
+            let (job, request) = start_print_job(
+                state,
+                user_uuid,
+                &print_job.agent_uuid,
+                &print_job.printer_uuid,
+                &print_job.print_file_uuid,
+            )
+            .await?;
+            return confirm_print_job(state, job, request).await;
+        }
+        PrintJobType::Pause => PrintJobState::Paused,
+        PrintJobType::Resume => PrintJobState::Printing,
+        PrintJobType::Cancel => PrintJobState::Cancelled,
+    };
+
+    let print_job_service = PrintJobServiceImpl::new(state.db_pool.clone());
+    let job = match print_job_service
+        .get_active(user_uuid, &print_job.printer_uuid)
+        .await?
+    {
+        Some(job) => job,
+        None => {
+            return Err(AppError::PrintJob {
+                message: "No active print job on this printer".to_string(),
+                status: StatusCode::NOT_FOUND,
+            })
+        }
+    };
+    job.check_transition(next_state)?;
+    // the agent doesn't know the job before it leaves the queued state
+    if job.state() == PrintJobState::Queued {
+        print_job_service.transition(job, next_state, None).await?;
+        return Ok(());
+    }
+    print_job.print_file_uuid = job.print_file_uuid.to_string();
+    print_job.print_job_uuid = Some(job.uuid.to_string());
+
+    info!(
+        "forwarding print job {:?} ({}) of file {} to agent {}",
+        print_job.job_type, job.uuid, print_job.print_file_uuid, print_job.agent_uuid
+    );
+    let agent_uuid = print_job.agent_uuid.to_string();
+    let message = WebSocketMessage::PrintJob(Payload::Request(print_job));
+    let registry = &state.agent_registry;
+    let request = registry.request(&agent_uuid, message).await?;
+    let reply = registry
+        .await_reply(request, PRINT_JOB_REPLY_TIMEOUT)
+        .await?;
+    print_job_reply_result(reply)?;
+
+    // the job may have changed while the agent handled the request
+    let job = print_job_service.get_by_uuid(user_uuid, &job.uuid).await?;
+    print_job_service.transition(job, next_state, None).await?;
+
+    Ok(())
+}
+
+/// Forwards a G-code command to the agent after checking it against the command policy of the user,
+/// returns the command with the response of the printer
+async fn handle_printer_command_message(
+    mut printer_command: PrinterCommandMessage,
+    user_uuid: &str,
+    state: &Arc<AppState>,
+) -> Result<PrinterCommandMessage, AppError> {
+    let agent_service = AgentServiceImpl::new(state.db_pool.clone());
+    agent_service
+        .get_by_uuid(user_uuid, &printer_command.agent_uuid)
+        .await?;
+
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    printer_service
+        .get_by_agent(
+            user_uuid,
+            &printer_command.agent_uuid,
+            &printer_command.printer_uuid,
+        )
+        .await?;
+
+    let command_policy_service = CommandPolicyServiceImpl::new(state.db_pool.clone());
+    command_policy_service
+        .check(user_uuid, &printer_command.command)
+        .await?;
+
+    printer_command.response = None;
+
+    info!(
+        "forwarding command {} to printer {} of agent {}",
+        printer_command.command, printer_command.printer_uuid, printer_command.agent_uuid
+    );
+    let agent_uuid = printer_command.agent_uuid.to_string();
+    let message = WebSocketMessage::PrinterCommand(Payload::Request(printer_command));
+    let registry = &state.agent_registry;
+    let request = registry.request(&agent_uuid, message).await?;
+
+    match registry.await_reply(request, COMMAND_REPLY_TIMEOUT).await? {
+        WebSocketMessage::PrinterCommand(Payload::Request(printer_command)) => Ok(printer_command),
+        WebSocketMessage::PrinterCommand(Payload::Reply(response))
+        | WebSocketMessage::Error(response) => {
+            Err(response.into_result().err().unwrap_or(AppError::Agent {
+                message: "Agent did not return the response of the printer".to_string(),
+                status: StatusCode::BAD_GATEWAY,
+            }))
+        }
+        reply => Err(AppError::Agent {
+            message: format!("Unexpected reply to printer command: {:?}", reply),
+            status: StatusCode::BAD_GATEWAY,
+        }),
+    }
+}
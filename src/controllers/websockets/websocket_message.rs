@@ -1,17 +1,429 @@
-use serde::{Deserialize, Serialize};
+use axum::extract::ws::Message;
+use axum::http::StatusCode;
+use axum::Error;
+use serde::de::{DeserializeOwned, Error as DeError};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct WebSocketMessage {
-    pub body: String,
-    pub message_type: WebSocketMessageType,
+use crate::common::app_error::AppError;
+use crate::models::agent::AgentHandshake;
+use crate::models::printer_status::PrinterStatusReport;
+
+/// Message as it goes over the wire, the id and reply_to fields correlate requests and replies
+/// - {"id": "...", "type": "print_job", "data": {...}}
+/// - {"reply_to": "...", "type": "ack"}
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Envelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    #[serde(flatten)]
+    pub message: WebSocketMessage,
+}
+
+/// Message sent over the user and agent websockets, see docs/websockets_specification.md
+/// - {"type": "print_job", "data": {...}}
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum WebSocketMessage {
+    Ack,
+    Authentication(Payload<AuthenticationMessage>),
+    PrintJob(Payload<PrintJobMessage>),
+    PrinterCommand(Payload<PrinterCommandMessage>),
+    AgentStatus(AgentStatusMessage),
+    PrinterStatus(Payload<PrinterStatusReport>),
+    PrinterDiscovery(Payload<PrinterDiscoveryMessage>),
+    FileRequest(FileRequestMessage),
+    FileChunk(Payload<FileChunkMessage>),
+    Error(StatusResponse),
+}
+
+/// Data of a message type that is used both for requests and for the replies to them.
+/// Data that only holds a status and an optional message is a reply
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Payload<T> {
+    Request(T),
+    Reply(StatusResponse),
+}
+
+/// Data of an authentication message, a user JWT or an agent token
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AuthenticationMessage {
+    pub token: String,
+    #[serde(flatten)]
+    pub handshake: AgentHandshake,
 }
 
+/// Body of a PrintJob message, sent by the user and forwarded to the agent.
+/// The print_job_uuid is assigned by the backend and refers to the recorded print job
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
-pub enum WebSocketMessageType {
-    UserAuthentication,
-    User,
-    AgentAuthentication,
-    Agent,
-    Printer,
-    Error,
+pub struct PrintJobMessage {
+    #[serde(rename = "type")]
+    pub job_type: PrintJobType,
+    pub agent_uuid: String,
+    pub print_file_uuid: String,
+    pub printer_uuid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub print_job_uuid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum PrintJobType {
+    Start,
+    Pause,
+    Resume,
+    Cancel,
+}
+
+/// Body of a PrinterCommand message, a single G-code line for a printer of the agent.
+/// The agent answers with the same message in reply to it, including the response of the printer
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PrinterCommandMessage {
+    pub agent_uuid: String,
+    pub printer_uuid: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<String>,
+}
+
+/// Body of an AgentStatus message, sent to the sessions of the owner when an agent connects or drops
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AgentStatusMessage {
+    pub agent_name: String,
+    pub agent_uuid: String,
+    pub status: AgentStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AgentStatus {
+    Online,
+    Offline,
+}
+
+/// Body of a PrinterDiscovery message, the printers an agent found on its adapters.
+/// The backend answers with the same message, including the uuid of every printer
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PrinterDiscoveryMessage {
+    pub printers: Vec<DiscoveredPrinter>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DiscoveredPrinter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub printer_uuid: Option<String>,
+    pub printer_identifier: String,
+    pub printer_adapter_identifier: String,
+    pub printer_adapter_interface: String,
+}
+
+/// Body of a FileRequest message, an agent asks for the part of a print file starting at the offset.
+/// Agents resume an interrupted transfer by asking for the offset they stopped at
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileRequestMessage {
+    pub print_file_uuid: String,
+    #[serde(default)]
+    pub offset: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<u64>,
+}
+
+/// Body of a FileChunk message, the answer to a FileRequest
+/// - size, checksum: Size and SHA-256 checksum of the whole file, to verify the assembled file
+/// - data: Base64 encoded bytes of the chunk, chunk_checksum is the SHA-256 checksum of those bytes
+/// - last: Whether the chunk ends at the end of the file
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileChunkMessage {
+    pub print_file_uuid: String,
+    pub offset: u64,
+    pub size: u64,
+    pub checksum: String,
+    pub chunk_checksum: String,
+    pub data: String,
+    pub last: bool,
+}
+
+/// Reply to a message, status is either OK or ERROR
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct StatusResponse {
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl StatusResponse {
+    pub fn ok() -> StatusResponse {
+        StatusResponse {
+            status: "OK".to_string(),
+            message: None,
+        }
+    }
+
+    pub fn error(err: &AppError) -> StatusResponse {
+        StatusResponse {
+            status: "ERROR".to_string(),
+            message: Some(err.to_string()),
+        }
+    }
+
+    /// Creates the OK/ERROR reply of a message based on the result of its handler
+    pub fn from_result(result: &Result<(), AppError>) -> StatusResponse {
+        match result {
+            Ok(_) => StatusResponse::ok(),
+            Err(err) => StatusResponse::error(err),
+        }
+    }
+
+    /// Converts a reply of an agent into the result of the request it answers
+    pub fn into_result(self) -> Result<(), AppError> {
+        match self.status.as_str() {
+            "OK" => Ok(()),
+            _ => Err(AppError::Agent {
+                message: self
+                    .message
+                    .unwrap_or_else(|| "Agent could not process the request".to_string()),
+                status: StatusCode::BAD_GATEWAY,
+            }),
+        }
+    }
+
+    /// Checks if the data has the shape of a reply, e.g. {"status": "OK"}
+    fn matches(value: &Value) -> bool {
+        match value.as_object() {
+            Some(object) => {
+                object.contains_key("status")
+                    && object.keys().all(|key| key == "status" || key == "message")
+            }
+            None => false,
+        }
+    }
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Payload<T> {
+    /// Not untagged, so errors of the request data are reported as is
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+
+        if StatusResponse::matches(&value) {
+            return serde_json::from_value(value)
+                .map(Payload::Reply)
+                .map_err(D::Error::custom);
+        }
+        serde_json::from_value(value)
+            .map(Payload::Request)
+            .map_err(D::Error::custom)
+    }
+}
+
+impl WebSocketMessage {
+    /// Creates a message that only tells the receiver something went wrong
+    pub fn error(err: &AppError) -> WebSocketMessage {
+        WebSocketMessage::Error(StatusResponse::error(err))
+    }
+
+    /// Wraps the message in an envelope that answers the message with the given id
+    pub fn reply_to(self, reply_to: Option<String>) -> Envelope {
+        Envelope {
+            id: None,
+            reply_to,
+            message: self,
+        }
+    }
+
+    /// Serializes the message into a websocket text message
+    pub fn to_message(&self) -> Message {
+        Message::from(serde_json::to_string(self).unwrap())
+    }
+}
+
+impl Envelope {
+    /// Serializes the envelope into a websocket text message
+    pub fn to_message(&self) -> Message {
+        Message::from(serde_json::to_string(self).unwrap())
+    }
+}
+
+/// Parses a received websocket text message, malformed messages are reported with the reason
+pub fn parse_message(message: &Result<Message, Error>) -> Result<Envelope, AppError> {
+    let message = match message {
+        Ok(message) => message,
+        Err(_) => return Err(AppError::InternalServer),
+    };
+
+    let message = match message {
+        Message::Text(message) => message,
+        _ => {
+            return Err(AppError::Validation {
+                messages: "Only text messages are supported".to_string(),
+                status: StatusCode::BAD_REQUEST,
+            })
+        }
+    };
+
+    match serde_json::from_str(message) {
+        Ok(message) => Ok(message),
+        Err(err) => Err(AppError::Validation {
+            messages: format!("Invalid message: {}", err),
+            status: StatusCode::BAD_REQUEST,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::printer_status::PrinterStatus;
+
+    fn text(message: &str) -> Result<Message, Error> {
+        Ok(Message::Text(message.to_string()))
+    }
+
+    #[test]
+    fn test_status_reply_ok() {
+        let message =
+            WebSocketMessage::PrintJob(Payload::Reply(StatusResponse::from_result(&Ok(()))));
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"type":"print_job","data":{"status":"OK"}}"#
+        );
+    }
+
+    #[test]
+    fn test_status_reply_error() {
+        let err = AppError::Agent {
+            message: "Agent not found".to_string(),
+            status: StatusCode::NOT_FOUND,
+        };
+        let message =
+            WebSocketMessage::PrintJob(Payload::Reply(StatusResponse::from_result(&Err(err))));
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"type":"print_job","data":{"status":"ERROR","message":"Agent not found"}}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_print_job_message() {
+        let message = r#"{"type":"print_job","data":{"type":"PAUSE","agent_uuid":"a","print_file_uuid":"f","printer_uuid":"p"}}"#;
+        match parse_message(&text(message)).unwrap().message {
+            WebSocketMessage::PrintJob(Payload::Request(print_job)) => {
+                assert_eq!(print_job.job_type, PrintJobType::Pause);
+                assert_eq!(print_job.agent_uuid, "a");
+            }
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_parse_authentication_message() {
+        let message = r#"{"type":"authentication","data":{"token":"secret"}}"#;
+        assert_eq!(
+            parse_message(&text(message)).unwrap().message,
+            WebSocketMessage::Authentication(Payload::Request(AuthenticationMessage {
+                token: "secret".to_string(),
+                handshake: AgentHandshake::default(),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_agent_handshake() {
+        let message = r#"{"type":"authentication","data":{"token":"secret","version":"1.2.0","protocol_version":2,"os":"linux","arch":"aarch64","capabilities":{"adapters":["SERIAL"],"webcam":true,"max_printers":4}}}"#;
+        match parse_message(&text(message)).unwrap().message {
+            WebSocketMessage::Authentication(Payload::Request(authentication)) => {
+                let handshake = authentication.handshake;
+                assert_eq!(handshake.version, Some("1.2.0".to_string()));
+                assert_eq!(handshake.protocol_version, Some(2));
+                assert_eq!(handshake.arch, Some("aarch64".to_string()));
+                let capabilities = handshake.capabilities.unwrap();
+                assert!(capabilities.webcam);
+                assert_eq!(capabilities.max_printers, Some(4));
+            }
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_parse_printer_status_message() {
+        let message = r#"{"type":"printer_status","data":{"agent_uuid":"a","printer_uuid":"p","printer_identifier":"Demo","status":"available"}}"#;
+        match parse_message(&text(message)).unwrap().message {
+            WebSocketMessage::PrinterStatus(Payload::Request(report)) => {
+                assert_eq!(report.status, PrinterStatus::Available)
+            }
+            message => panic!("unexpected message {:?}", message),
+        }
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let message = r#"{"type":"printer_command","data":{"status":"ERROR","message":"nope"}}"#;
+        assert_eq!(
+            parse_message(&text(message)).unwrap().message,
+            WebSocketMessage::PrinterCommand(Payload::Reply(StatusResponse {
+                status: "ERROR".to_string(),
+                message: Some("nope".to_string()),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_parse_malformed_messages() {
+        let err =
+            parse_message(&text(r#"{"type":"print_job","data":{"type":"START"}}"#)).unwrap_err();
+        assert!(err.to_string().contains("missing field `agent_uuid`"));
+
+        let err = parse_message(&text(r#"{"type":"unknown","data":{}}"#)).unwrap_err();
+        assert!(err.to_string().contains("unknown variant `unknown`"));
+
+        assert!(parse_message(&text("Error parsing message")).is_err());
+        assert!(parse_message(&Ok(Message::Binary(vec![1, 2]))).is_err());
+    }
+
+    #[test]
+    fn test_error_message() {
+        let err = AppError::Validation {
+            messages: "Invalid message".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        };
+        assert_eq!(
+            serde_json::to_string(&WebSocketMessage::error(&err)).unwrap(),
+            r#"{"type":"error","data":{"status":"ERROR","message":"Invalid message"}}"#
+        );
+    }
+
+    #[test]
+    fn test_envelope_ids() {
+        let message = r#"{"id":"1","type":"print_job","data":{"type":"CANCEL","agent_uuid":"a","print_file_uuid":"f","printer_uuid":"p"}}"#;
+        let envelope = parse_message(&text(message)).unwrap();
+        assert_eq!(envelope.id, Some("1".to_string()));
+        assert_eq!(envelope.reply_to, None);
+
+        let ack = parse_message(&text(r#"{"reply_to":"1","type":"ack"}"#)).unwrap();
+        assert_eq!(ack.reply_to, Some("1".to_string()));
+        assert_eq!(ack.message, WebSocketMessage::Ack);
+
+        let reply = WebSocketMessage::PrintJob(Payload::Reply(StatusResponse::ok()))
+            .reply_to(Some("1".to_string()));
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"reply_to":"1","type":"print_job","data":{"status":"OK"}}"#
+        );
+    }
+
+    #[test]
+    fn test_reply_into_result() {
+        assert!(StatusResponse::ok().into_result().is_ok());
+
+        let err = StatusResponse {
+            status: "ERROR".to_string(),
+            message: Some("Printer is busy".to_string()),
+        };
+        assert_eq!(
+            err.into_result().unwrap_err().to_string(),
+            "Printer is busy"
+        );
+    }
 }
@@ -0,0 +1,576 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::extract::ws::{close_code, CloseFrame, Message};
+use axum::http::StatusCode;
+use chrono::Utc;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{oneshot, RwLock};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::common::app_error::AppError;
+use crate::controllers::websockets::websocket_message::{Envelope, WebSocketMessage};
+use crate::models::agent::AgentConnectionInfo;
+
+/// Time an agent has to acknowledge a request before it is sent again
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of times a request is sent before it fails
+pub const ACK_ATTEMPTS: u32 = 3;
+
+/// A live connection with an authenticated agent
+/// - connection_uuid: Identifies the socket, an agent that reconnects gets a new one
+/// - sender: Outbound channel, messages are written to the agent socket in order
+/// - closed: Cancelled when the backend drops the connection, ends the receive loop of the socket
+#[derive(Clone, Debug)]
+pub struct AgentConnection {
+    pub connection_uuid: String,
+    pub user_uuid: String,
+    pub address: SocketAddr,
+    pub sender: UnboundedSender<Message>,
+    pub closed: CancellationToken,
+}
+
+impl AgentConnection {
+    /// Closes the socket with the reason, the agent is unregistered once its receive loop ends
+    fn close(&self, reason: &str) {
+        let _ = self.sender.send(Message::Close(Some(CloseFrame {
+            code: close_code::POLICY,
+            reason: Cow::from(reason.to_string()),
+        })));
+        self.closed.cancel();
+    }
+}
+
+/// Request sent to an agent that awaits its acknowledgement and reply.
+/// Dropping the senders wakes up the waiting handler, which happens when the agent disconnects
+#[derive(Debug)]
+struct PendingRequest {
+    agent_uuid: String,
+    ack: Option<oneshot::Sender<()>>,
+    reply: Option<oneshot::Sender<WebSocketMessage>>,
+}
+
+/// Request acknowledged by an agent, its reply can be awaited with AgentRegistry::await_reply
+#[derive(Debug)]
+pub struct AgentRequest {
+    pub id: String,
+    pub agent_uuid: String,
+    reply: oneshot::Receiver<WebSocketMessage>,
+}
+
+/// Registry of the agents that are currently connected, keyed by agent uuid
+/// - pending_requests: Requests sent to agents that await a reply, keyed by message id
+/// - last_seen: Timestamp of the last frame of every agent, kept after it disconnects
+#[derive(Default, Debug)]
+pub struct AgentRegistry {
+    connections: RwLock<HashMap<String, AgentConnection>>,
+    pending_requests: RwLock<HashMap<String, PendingRequest>>,
+    last_seen: RwLock<HashMap<String, i64>>,
+}
+
+impl AgentRegistry {
+    pub fn new() -> Self {
+        AgentRegistry::default()
+    }
+
+    /// Registers the connection of an agent, an existing connection of the same agent is closed
+    pub async fn register(&self, agent_uuid: &str, connection: AgentConnection) {
+        self.touch(agent_uuid).await;
+        let mut connections = self.connections.write().await;
+
+        if let Some(previous) = connections.insert(agent_uuid.to_string(), connection) {
+            warn!(
+                "agent {} connected again, closing previous connection from {}",
+                agent_uuid, previous.address
+            );
+            previous.close("Agent connected again");
+        }
+        info!(
+            "agent {} registered, {} online",
+            agent_uuid,
+            connections.len()
+        );
+    }
+
+    /// Removes the connection of an agent, only if it is still the registered connection
+    pub async fn unregister(&self, agent_uuid: &str, connection_uuid: &str) -> bool {
+        let mut connections = self.connections.write().await;
+
+        match connections.get(agent_uuid) {
+            Some(connection) if connection.connection_uuid == connection_uuid => {
+                connections.remove(agent_uuid);
+                // replies of the agent can no longer arrive, their handlers fail right away
+                self.pending_requests
+                    .write()
+                    .await
+                    .retain(|_, pending| pending.agent_uuid != agent_uuid);
+                info!(
+                    "agent {} unregistered, {} online",
+                    agent_uuid,
+                    connections.len()
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Closes the connection of the agent, e.g. when its token is revoked.
+    /// Returns false if the agent was not connected
+    pub async fn disconnect(&self, agent_uuid: &str, reason: &str) -> bool {
+        match self.connections.read().await.get(agent_uuid) {
+            Some(connection) => {
+                info!("disconnecting agent {}: {}", agent_uuid, reason);
+                connection.close(reason);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Records that a frame of the agent was received just now
+    pub async fn touch(&self, agent_uuid: &str) {
+        self.last_seen
+            .write()
+            .await
+            .insert(agent_uuid.to_string(), Utc::now().timestamp());
+    }
+
+    /// Live connection details of the agent, the last seen time is known until the backend restarts
+    pub async fn connection_info(&self, agent_uuid: &str) -> AgentConnectionInfo {
+        let last_seen = self
+            .last_seen
+            .read()
+            .await
+            .get(agent_uuid)
+            .map(|last_seen| last_seen.to_string());
+
+        match self.connections.read().await.get(agent_uuid) {
+            Some(connection) => AgentConnectionInfo {
+                online: true,
+                address: Some(connection.address.to_string()),
+                last_seen,
+            },
+            None => AgentConnectionInfo {
+                online: false,
+                address: None,
+                last_seen,
+            },
+        }
+    }
+
+    /// Checks if the agent currently has a live connection
+    pub async fn is_online(&self, agent_uuid: &str) -> bool {
+        self.connections.read().await.contains_key(agent_uuid)
+    }
+
+    /// Queues a message on the outbound channel of a connected agent
+    pub async fn send(&self, agent_uuid: &str, message: Message) -> Result<(), AppError> {
+        let connections = self.connections.read().await;
+
+        let connection = match connections.get(agent_uuid) {
+            Some(connection) => connection,
+            None => {
+                return Err(AppError::Agent {
+                    message: "Agent is not connected".to_string(),
+                    status: StatusCode::CONFLICT,
+                })
+            }
+        };
+
+        match connection.sender.send(message) {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                warn!("outbound channel of agent {} is closed", agent_uuid);
+                Err(AppError::Agent {
+                    message: "Agent is not connected".to_string(),
+                    status: StatusCode::CONFLICT,
+                })
+            }
+        }
+    }
+
+    /// Sends a request to a connected agent and waits until the agent acknowledges it.
+    /// The request is sent again with the same id if no ack arrives in time, and fails
+    /// once all attempts are used or the agent disconnects
+    pub async fn request(
+        &self,
+        agent_uuid: &str,
+        message: WebSocketMessage,
+    ) -> Result<AgentRequest, AppError> {
+        let id = Uuid::new_v4().to_string();
+        let (reply_sender, reply) = oneshot::channel();
+        let message = Envelope {
+            id: Some(id.to_string()),
+            reply_to: None,
+            message,
+        }
+        .to_message();
+
+        self.pending_requests.write().await.insert(
+            id.to_string(),
+            PendingRequest {
+                agent_uuid: agent_uuid.to_string(),
+                ack: None,
+                reply: Some(reply_sender),
+            },
+        );
+
+        for attempt in 1..=ACK_ATTEMPTS {
+            let (ack_sender, ack) = oneshot::channel();
+            match self.pending_requests.write().await.get_mut(&id) {
+                Some(pending) => pending.ack = Some(ack_sender),
+                // the reply to an earlier attempt may have arrived after its ack timed out
+                None => return answered_request(id, agent_uuid, reply),
+            }
+
+            if let Err(err) = self.send(agent_uuid, message.clone()).await {
+                self.pending_requests.write().await.remove(&id);
+                return Err(err);
+            }
+
+            match timeout(ACK_TIMEOUT, ack).await {
+                Ok(Ok(_)) => {
+                    return Ok(AgentRequest {
+                        id,
+                        agent_uuid: agent_uuid.to_string(),
+                        reply,
+                    })
+                }
+                Ok(Err(_)) => return answered_request(id, agent_uuid, reply),
+                Err(_) => warn!(
+                    "agent {} did not acknowledge request {} (attempt {}/{})",
+                    agent_uuid, id, attempt, ACK_ATTEMPTS
+                ),
+            }
+        }
+
+        self.pending_requests.write().await.remove(&id);
+        Err(AppError::Agent {
+            message: "Agent did not acknowledge the request".to_string(),
+            status: StatusCode::GATEWAY_TIMEOUT,
+        })
+    }
+
+    /// Waits for the reply of the agent to an acknowledged request
+    pub async fn await_reply(
+        &self,
+        request: AgentRequest,
+        reply_timeout: Duration,
+    ) -> Result<WebSocketMessage, AppError> {
+        match timeout(reply_timeout, request.reply).await {
+            Ok(Ok(message)) => Ok(message),
+            Ok(Err(_)) => Err(agent_disconnected()),
+            Err(_) => {
+                self.pending_requests.write().await.remove(&request.id);
+                Err(AppError::Agent {
+                    message: "Agent did not reply in time".to_string(),
+                    status: StatusCode::GATEWAY_TIMEOUT,
+                })
+            }
+        }
+    }
+
+    /// Marks the request as received by the agent, only the agent it was sent to may acknowledge it
+    pub async fn acknowledge(&self, agent_uuid: &str, reply_to: &str) -> bool {
+        let mut pending_requests = self.pending_requests.write().await;
+
+        match pending_requests.get_mut(reply_to) {
+            Some(pending) if pending.agent_uuid == agent_uuid => {
+                if let Some(ack) = pending.ack.take() {
+                    let _ = ack.send(());
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Hands the reply of the agent to the handler awaiting it, a reply also acknowledges the request.
+    /// Returns false if no request with the id is pending for the agent
+    pub async fn resolve(
+        &self,
+        agent_uuid: &str,
+        reply_to: &str,
+        message: WebSocketMessage,
+    ) -> bool {
+        let mut pending_requests = self.pending_requests.write().await;
+
+        let mut pending = match pending_requests.get(reply_to) {
+            Some(pending) if pending.agent_uuid == agent_uuid => {
+                pending_requests.remove(reply_to).unwrap()
+            }
+            _ => return false,
+        };
+
+        if let Some(ack) = pending.ack.take() {
+            let _ = ack.send(());
+        }
+        if let Some(reply) = pending.reply.take() {
+            let _ = reply.send(message);
+        }
+        true
+    }
+}
+
+/// A request that is no longer pending was either answered or dropped by a disconnect
+fn answered_request(
+    id: String,
+    agent_uuid: &str,
+    mut reply: oneshot::Receiver<WebSocketMessage>,
+) -> Result<AgentRequest, AppError> {
+    let message = match reply.try_recv() {
+        Ok(message) => message,
+        Err(_) => return Err(agent_disconnected()),
+    };
+
+    let (reply_sender, reply) = oneshot::channel();
+    let _ = reply_sender.send(message);
+    Ok(AgentRequest {
+        id,
+        agent_uuid: agent_uuid.to_string(),
+        reply,
+    })
+}
+
+fn agent_disconnected() -> AppError {
+    AppError::Agent {
+        message: "Agent disconnected before answering the request".to_string(),
+        status: StatusCode::CONFLICT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::websockets::websocket_message::{Payload, PrinterCommandMessage};
+    use std::sync::Arc;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    fn connection(connection_uuid: &str) -> (AgentConnection, UnboundedReceiver<Message>) {
+        let (sender, receiver) = unbounded_channel();
+        let connection = AgentConnection {
+            connection_uuid: connection_uuid.to_string(),
+            user_uuid: "user".to_string(),
+            address: SocketAddr::from(([127, 0, 0, 1], 3000)),
+            sender,
+            closed: CancellationToken::new(),
+        };
+        (connection, receiver)
+    }
+
+    #[tokio::test]
+    async fn test_register_and_send() {
+        let registry = AgentRegistry::new();
+        let (connection, mut receiver) = connection("first");
+
+        registry.register("agent", connection).await;
+        assert!(registry.is_online("agent").await);
+
+        registry
+            .send("agent", Message::from("hello"))
+            .await
+            .unwrap();
+        assert_eq!(receiver.recv().await, Some(Message::from("hello")));
+    }
+
+    #[tokio::test]
+    async fn test_send_to_offline_agent() {
+        let registry = AgentRegistry::new();
+        let result = registry.send("agent", Message::from("hello")).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_closes_previous_connection() {
+        let registry = AgentRegistry::new();
+        let (first, mut first_receiver) = connection("first");
+        let (second, _second_receiver) = connection("second");
+
+        let first_closed = first.closed.clone();
+        registry.register("agent", first).await;
+        registry.register("agent", second).await;
+        assert!(matches!(
+            first_receiver.recv().await,
+            Some(Message::Close(Some(_)))
+        ));
+        assert!(first_closed.is_cancelled());
+
+        // the stale connection must not remove the new one
+        assert!(!registry.unregister("agent", "first").await);
+        assert!(registry.is_online("agent").await);
+        assert!(registry.unregister("agent", "second").await);
+        assert!(!registry.is_online("agent").await);
+    }
+
+    #[tokio::test]
+    async fn test_connection_info() {
+        let registry = AgentRegistry::new();
+        let (connection, _receiver) = connection("first");
+
+        let info = registry.connection_info("agent").await;
+        assert!(!info.online);
+        assert_eq!(info.last_seen, None);
+
+        registry.register("agent", connection).await;
+        let info = registry.connection_info("agent").await;
+        assert!(info.online);
+        assert_eq!(info.address, Some("127.0.0.1:3000".to_string()));
+
+        // the last seen time outlives the connection
+        registry.unregister("agent", "first").await;
+        let info = registry.connection_info("agent").await;
+        assert!(!info.online);
+        assert_eq!(info.address, None);
+        assert!(info.last_seen.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let registry = AgentRegistry::new();
+        let (connection, mut receiver) = connection("first");
+        let closed = connection.closed.clone();
+
+        assert!(!registry.disconnect("agent", "Agent token revoked").await);
+        registry.register("agent", connection).await;
+        assert!(registry.disconnect("agent", "Agent token revoked").await);
+
+        match receiver.recv().await {
+            Some(Message::Close(Some(frame))) => assert_eq!(frame.reason, "Agent token revoked"),
+            message => panic!("Unexpected message {:?}", message),
+        }
+        assert!(closed.is_cancelled());
+    }
+
+    fn command() -> WebSocketMessage {
+        WebSocketMessage::PrinterCommand(Payload::Request(PrinterCommandMessage {
+            agent_uuid: "agent".to_string(),
+            printer_uuid: "printer".to_string(),
+            command: "M105".to_string(),
+            response: None,
+        }))
+    }
+
+    /// Reads the id of the next request the agent receives
+    async fn received_id(receiver: &mut UnboundedReceiver<Message>) -> String {
+        match receiver.recv().await {
+            Some(Message::Text(text)) => {
+                let envelope: Envelope = serde_json::from_str(&text).unwrap();
+                envelope.id.unwrap()
+            }
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_ack_and_reply() {
+        let registry = Arc::new(AgentRegistry::new());
+        let (connection, mut receiver) = connection("first");
+        registry.register("agent", connection).await;
+
+        let agent = registry.clone();
+        let agent_task = tokio::spawn(async move {
+            let id = received_id(&mut receiver).await;
+            // only the agent the request was sent to may answer it
+            assert!(!agent.acknowledge("other", &id).await);
+            assert!(agent.acknowledge("agent", &id).await);
+            assert!(agent.resolve("agent", &id, command()).await);
+            // a request is answered only once
+            assert!(!agent.resolve("agent", &id, command()).await);
+        });
+
+        let request = registry.request("agent", command()).await.unwrap();
+        let reply = registry
+            .await_reply(request, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(reply, command());
+        agent_task.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_is_sent_again_without_ack() {
+        let registry = Arc::new(AgentRegistry::new());
+        let (connection, mut receiver) = connection("first");
+        registry.register("agent", connection).await;
+
+        let agent = registry.clone();
+        let agent_task = tokio::spawn(async move {
+            let first = received_id(&mut receiver).await;
+            let second = received_id(&mut receiver).await;
+            assert_eq!(first, second);
+            assert!(agent.acknowledge("agent", &second).await);
+        });
+
+        assert!(registry.request("agent", command()).await.is_ok());
+        agent_task.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_late_reply_to_earlier_attempt() {
+        let registry = Arc::new(AgentRegistry::new());
+        let (connection, mut receiver) = connection("first");
+        registry.register("agent", connection).await;
+
+        let agent = registry.clone();
+        let agent_task = tokio::spawn(async move {
+            let id = received_id(&mut receiver).await;
+            // the reply arrives after the ack timed out, before the request is sent again
+            let mut pending_requests = agent.pending_requests.write().await;
+            tokio::time::sleep(ACK_TIMEOUT + Duration::from_millis(1)).await;
+            let pending = pending_requests.remove(&id).unwrap();
+            let _ = pending.reply.unwrap().send(command());
+        });
+
+        let request = registry.request("agent", command()).await.unwrap();
+        let reply = registry
+            .await_reply(request, Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(reply, command());
+        agent_task.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_request_fails_without_ack() {
+        let registry = AgentRegistry::new();
+        let (connection, _receiver) = connection("first");
+        registry.register("agent", connection).await;
+
+        let err = registry.request("agent", command()).await.unwrap_err();
+        assert_eq!(err.to_string(), "Agent did not acknowledge the request");
+        assert!(registry.pending_requests.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_fails_pending_request() {
+        let registry = Arc::new(AgentRegistry::new());
+        let (connection, mut receiver) = connection("first");
+        registry.register("agent", connection).await;
+
+        let agent = registry.clone();
+        let agent_task = tokio::spawn(async move {
+            let id = received_id(&mut receiver).await;
+            agent.acknowledge("agent", &id).await;
+        });
+
+        let request = registry.request("agent", command()).await.unwrap();
+        agent_task.await.unwrap();
+        registry.unregister("agent", "first").await;
+
+        let err = registry
+            .await_reply(request, Duration::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Agent disconnected before answering the request"
+        );
+    }
+}
@@ -1,43 +1,109 @@
+use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
 
-use tracing::error;
+use tracing::{error, info};
 
 use crate::common::app_error::AppError;
 use crate::infra::strategies::file_storage_strategy::FileStorageStrategy;
 use crate::infra::strategies::local_file_strategy::LocalFileStrategy;
+use crate::infra::strategies::memory_file_strategy::MemoryFileStrategy;
 use crate::infra::strategies::s3_file_strategy::S3FileStrategy;
 
 const FILE_STORAGE_TYPE_S3: &str = "s3";
 const FILE_STORAGE_TYPE_LOCAL: &str = "local";
+const FILE_STORAGE_TYPE_MEMORY: &str = "memory";
 
-pub async fn store_file(user_uuid: &str, filename: &str, data: &[u8]) -> Result<String, AppError> {
-    let file_storage_type = env::var("FILESTORAGE_TYPE").expect("FILESTORAGE_TYPE must be set!");
+/// The file storages files are read from, each file is read from the storage it was written to.
+/// New files are written to the current storage
+pub struct FileStorages {
+    current: Arc<dyn FileStorageStrategy>,
+    storages: HashMap<&'static str, Arc<dyn FileStorageStrategy>>,
+}
 
-    match file_storage_type.as_str() {
-        FILE_STORAGE_TYPE_LOCAL => {
-            LocalFileStrategy::write_file(&LocalFileStrategy {}, user_uuid, filename, data).await
+impl FileStorages {
+    pub fn new(current: Arc<dyn FileStorageStrategy>) -> Self {
+        FileStorages {
+            current: current.clone(),
+            storages: HashMap::from([(current.storage_type(), current)]),
         }
-        FILE_STORAGE_TYPE_S3 => {
-            S3FileStrategy::write_file(&S3FileStrategy {}, user_uuid, filename, data).await
+    }
+
+    /// Adds a storage that is only read from
+    pub fn with(mut self, storage: Arc<dyn FileStorageStrategy>) -> Self {
+        self.storages
+            .entry(storage.storage_type())
+            .or_insert(storage);
+        self
+    }
+
+    /// Writes to the storage configured with FILESTORAGE_TYPE. The other storages stay readable
+    /// as long as they are configured, so files stored before FILESTORAGE_TYPE changed can be read
+    pub fn from_env() -> Self {
+        let file_storage_type =
+            env::var("FILESTORAGE_TYPE").expect("FILESTORAGE_TYPE must be set!");
+        info!("file storage type: {}", file_storage_type);
+
+        let mut storages = FileStorages::new(build(&file_storage_type));
+        if env::var("FILESTORAGE_PATH").is_ok() {
+            storages = storages.with(build(FILE_STORAGE_TYPE_LOCAL));
         }
-        _ => {
-            error!("unknown file storage type: {}", file_storage_type);
-            Err(AppError::InternalServer)
+        if env::var("S3_BUCKET_NAME").is_ok() {
+            storages = storages.with(build(FILE_STORAGE_TYPE_S3));
         }
+        storages
     }
-}
 
-pub async fn retrieve_file(filepath: &str) -> Result<Vec<u8>, AppError> {
-    let file_storage_type = env::var("FILESTORAGE_TYPE").expect("FILESTORAGE_TYPE must be set!");
+    /// The storage new files are written to
+    pub fn current(&self) -> Arc<dyn FileStorageStrategy> {
+        self.current.clone()
+    }
 
-    match file_storage_type.as_str() {
-        FILE_STORAGE_TYPE_LOCAL => {
-            LocalFileStrategy::retrieve_file(&LocalFileStrategy {}, filepath).await
+    /// The storage a file was written to, by the file_storage_type recorded with the file
+    pub fn get(&self, file_storage_type: &str) -> Result<Arc<dyn FileStorageStrategy>, AppError> {
+        match self.storages.get(file_storage_type) {
+            Some(storage) => Ok(storage.clone()),
+            None => {
+                error!("file storage {} is not configured", file_storage_type);
+                Err(AppError::InternalServer)
+            }
         }
-        FILE_STORAGE_TYPE_S3 => S3FileStrategy::retrieve_file(&S3FileStrategy {}, filepath).await,
-        _ => {
-            error!("unknown file storage type: {}", file_storage_type);
-            Err(AppError::InternalServer)
+    }
+}
+
+/// Builds the file storage of the type from its environment variables
+pub fn build(file_storage_type: &str) -> Arc<dyn FileStorageStrategy> {
+    match file_storage_type {
+        FILE_STORAGE_TYPE_LOCAL => {
+            let base_directory =
+                env::var("FILESTORAGE_PATH").expect("FILESTORAGE_PATH must be set!");
+            Arc::new(LocalFileStrategy::new(&base_directory))
         }
+        FILE_STORAGE_TYPE_S3 => Arc::new(S3FileStrategy::from_env()),
+        FILE_STORAGE_TYPE_MEMORY => Arc::new(MemoryFileStrategy::new()),
+        _ => panic!("unknown file storage type: {}", file_storage_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_storages() {
+        let storages = FileStorages::new(Arc::new(MemoryFileStrategy::new()))
+            .with(Arc::new(LocalFileStrategy::new("files")));
+
+        assert_eq!(storages.current().storage_type(), "memory");
+        assert_eq!(storages.get("local").unwrap().storage_type(), "local");
+        assert!(storages.get("s3").is_err());
+
+        let path = storages
+            .current()
+            .write_file("user", "part.gcode", b"G28")
+            .await
+            .unwrap();
+        let memory = storages.get("memory").unwrap();
+        assert_eq!(memory.retrieve_file(&path).await.unwrap(), b"G28");
     }
 }
@@ -0,0 +1,191 @@
+use std::env;
+use std::time::Duration;
+
+use axum::extract::ws::Message;
+use axum::Error;
+use futures_util::{Stream, StreamExt};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{interval_at, Instant, Interval, MissedTickBehavior};
+use tracing::warn;
+
+const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(15);
+const DEFAULT_PING_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Heartbeat settings of the websockets
+/// - interval: Time between the pings sent to the other side
+/// - timeout: Time without any frame after which the connection is considered dead
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        HeartbeatConfig {
+            interval: DEFAULT_PING_INTERVAL,
+            timeout: DEFAULT_PING_TIMEOUT,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// Reads WS_PING_INTERVAL_SECONDS and WS_PING_TIMEOUT_SECONDS, unset values use the defaults
+    pub fn from_env() -> Self {
+        HeartbeatConfig::from_values(
+            env::var("WS_PING_INTERVAL_SECONDS").ok(),
+            env::var("WS_PING_TIMEOUT_SECONDS").ok(),
+        )
+    }
+
+    /// Parses the values of WS_PING_INTERVAL_SECONDS and WS_PING_TIMEOUT_SECONDS
+    fn from_values(interval: Option<String>, timeout: Option<String>) -> Self {
+        let config = HeartbeatConfig {
+            interval: parse_seconds("WS_PING_INTERVAL_SECONDS", interval, DEFAULT_PING_INTERVAL),
+            timeout: parse_seconds("WS_PING_TIMEOUT_SECONDS", timeout, DEFAULT_PING_TIMEOUT),
+        };
+        if config.timeout <= config.interval {
+            panic!("WS_PING_TIMEOUT_SECONDS must be larger than WS_PING_INTERVAL_SECONDS");
+        }
+        config
+    }
+}
+
+fn parse_seconds(key: &str, value: Option<String>, default: Duration) -> Duration {
+    match value {
+        Some(value) => match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+            _ => panic!("{} must be a positive number of seconds", key),
+        },
+        None => default,
+    }
+}
+
+/// Pings the other side of a websocket while waiting for its messages.
+/// Any received frame, including pongs, counts as a sign of life
+pub struct Heartbeat {
+    config: HeartbeatConfig,
+    ticker: Interval,
+    last_seen: Instant,
+    outbound: UnboundedSender<Message>,
+}
+
+impl Heartbeat {
+    pub fn new(config: HeartbeatConfig, outbound: UnboundedSender<Message>) -> Self {
+        let mut ticker = interval_at(Instant::now() + config.interval, config.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        Heartbeat {
+            config,
+            ticker,
+            last_seen: Instant::now(),
+            outbound,
+        }
+    }
+
+    /// Waits for the next message, returns None when the stream closed or the other side
+    /// stopped answering in time
+    pub async fn next<S>(&mut self, receiver: &mut S) -> Option<Result<Message, Error>>
+    where
+        S: Stream<Item = Result<Message, Error>> + Unpin,
+    {
+        loop {
+            tokio::select! {
+                message = receiver.next() => {
+                    self.last_seen = Instant::now();
+                    return message;
+                }
+                _ = self.ticker.tick() => {
+                    if self.timed_out() {
+                        warn!(
+                            "No frames received for {:?}, closing connection",
+                            self.last_seen.elapsed()
+                        );
+                        return None;
+                    }
+                    if self.outbound.send(Message::Ping(Vec::new())).is_err() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Checks if the other side missed its heartbeats
+    pub fn timed_out(&self) -> bool {
+        self.last_seen.elapsed() >= self.config.timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::stream;
+    use tokio::sync::mpsc::unbounded_channel;
+
+    fn config() -> HeartbeatConfig {
+        HeartbeatConfig {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(25),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pings_until_timeout() {
+        let (outbound, mut outbound_receiver) = unbounded_channel();
+        let mut heartbeat = Heartbeat::new(config(), outbound);
+        let mut receiver = stream::pending::<Result<Message, Error>>();
+
+        assert!(heartbeat.next(&mut receiver).await.is_none());
+        assert!(heartbeat.timed_out());
+
+        // pinged at 10s and 20s, gave up at 30s
+        assert_eq!(
+            outbound_receiver.recv().await,
+            Some(Message::Ping(Vec::new()))
+        );
+        assert_eq!(
+            outbound_receiver.recv().await,
+            Some(Message::Ping(Vec::new()))
+        );
+        assert!(outbound_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_received_frames_keep_connection_alive() {
+        let (outbound, _outbound_receiver) = unbounded_channel();
+        let mut heartbeat = Heartbeat::new(config(), outbound);
+        let mut receiver = stream::iter(vec![Ok(Message::Pong(Vec::new()))]);
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        assert_eq!(
+            heartbeat.next(&mut receiver).await.unwrap().unwrap(),
+            Message::Pong(Vec::new())
+        );
+        assert!(!heartbeat.timed_out());
+    }
+
+    #[test]
+    fn test_config_from_values() {
+        assert_eq!(
+            HeartbeatConfig::from_values(None, None),
+            HeartbeatConfig::default()
+        );
+        assert_eq!(
+            HeartbeatConfig::from_values(Some("10".to_string()), Some("25".to_string())),
+            config()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "WS_PING_TIMEOUT_SECONDS must be larger")]
+    fn test_config_rejects_short_timeout() {
+        HeartbeatConfig::from_values(Some("30".to_string()), Some("20".to_string()));
+    }
+
+    #[test]
+    #[should_panic(expected = "WS_PING_INTERVAL_SECONDS must be a positive number")]
+    fn test_config_rejects_invalid_seconds() {
+        HeartbeatConfig::from_values(Some("0".to_string()), None);
+    }
+}
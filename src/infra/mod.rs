@@ -1,3 +1,7 @@
+pub mod agent_registry;
 pub mod database;
 pub mod filestorage;
+pub mod heartbeat;
+pub mod printer_status_store;
 pub mod strategies;
+pub mod user_hub;
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use tokio::sync::RwLock;
+
+use crate::models::printer_status::{PrinterStatus, PrinterStatusReport, PrinterStatusViewModel};
+
+/// Latest status of a printer, together with the user that owns the printer
+#[derive(Clone, Debug)]
+pub struct StoredPrinterStatus {
+    pub user_uuid: String,
+    pub status: PrinterStatusViewModel,
+}
+
+/// In-memory store of the latest status reported for every printer, keyed by printer uuid
+#[derive(Default, Debug)]
+pub struct PrinterStatusStore {
+    statuses: RwLock<HashMap<String, StoredPrinterStatus>>,
+}
+
+impl PrinterStatusStore {
+    pub fn new() -> Self {
+        PrinterStatusStore::default()
+    }
+
+    /// Replaces the status of the printer, returns the stored status
+    pub async fn update(
+        &self,
+        user_uuid: &str,
+        report: PrinterStatusReport,
+    ) -> StoredPrinterStatus {
+        let stored = StoredPrinterStatus {
+            user_uuid: user_uuid.to_string(),
+            status: PrinterStatusViewModel {
+                report,
+                updated_at: Utc::now().timestamp().to_string(),
+            },
+        };
+
+        self.statuses.write().await.insert(
+            stored.status.report.printer_uuid.to_string(),
+            stored.clone(),
+        );
+        stored
+    }
+
+    pub async fn get(&self, printer_uuid: &str) -> Option<StoredPrinterStatus> {
+        self.statuses.read().await.get(printer_uuid).cloned()
+    }
+
+    /// Checks if the printer already reported through the agent, so its ownership is known
+    pub async fn is_known(&self, printer_uuid: &str, agent_uuid: &str) -> bool {
+        match self.statuses.read().await.get(printer_uuid) {
+            Some(stored) => stored.status.report.agent_uuid == agent_uuid,
+            None => false,
+        }
+    }
+
+    /// Marks all printers of the agent as unavailable, returns the changed statuses
+    pub async fn mark_agent_offline(&self, agent_uuid: &str) -> Vec<StoredPrinterStatus> {
+        let mut statuses = self.statuses.write().await;
+        let updated_at = Utc::now().timestamp().to_string();
+
+        statuses
+            .values_mut()
+            .filter(|stored| stored.status.report.agent_uuid == agent_uuid)
+            .map(|stored| {
+                stored.status.report.status = PrinterStatus::Unavailable;
+                stored.status.report.job = None;
+                stored.status.report.state = None;
+                stored.status.updated_at = updated_at.to_string();
+                stored.clone()
+            })
+            .collect()
+    }
+
+    /// Removes the status of a printer, e.g. when the printer is deleted
+    pub async fn remove(&self, printer_uuid: &str) {
+        self.statuses.write().await.remove(printer_uuid);
+    }
+
+    /// Removes the statuses of all printers of the agent, when the agent is deleted
+    pub async fn remove_agent(&self, agent_uuid: &str) {
+        self.statuses
+            .write()
+            .await
+            .retain(|_, stored| stored.status.report.agent_uuid != agent_uuid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(printer_uuid: &str, status: PrinterStatus) -> PrinterStatusReport {
+        PrinterStatusReport {
+            agent_uuid: "agent".to_string(),
+            printer_uuid: printer_uuid.to_string(),
+            printer_identifier: "Demo".to_string(),
+            printer_adapter_identifier: None,
+            printer_adapter_interface: None,
+            status,
+            job: None,
+            state: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_and_get() {
+        let store = PrinterStatusStore::new();
+        store
+            .update("user", report("printer", PrinterStatus::Available))
+            .await;
+        store
+            .update("user", report("printer", PrinterStatus::Busy))
+            .await;
+
+        let stored = store.get("printer").await.unwrap();
+        assert_eq!(stored.user_uuid, "user");
+        assert_eq!(stored.status.report.status, PrinterStatus::Busy);
+        assert!(store.is_known("printer", "agent").await);
+        assert!(!store.is_known("printer", "other").await);
+    }
+
+    #[tokio::test]
+    async fn test_mark_agent_offline() {
+        let store = PrinterStatusStore::new();
+        store
+            .update("user", report("first", PrinterStatus::Busy))
+            .await;
+        store
+            .update("user", report("second", PrinterStatus::Available))
+            .await;
+
+        let changed = store.mark_agent_offline("agent").await;
+        assert_eq!(changed.len(), 2);
+
+        let stored = store.get("first").await.unwrap();
+        assert_eq!(stored.status.report.status, PrinterStatus::Unavailable);
+    }
+
+    #[tokio::test]
+    async fn test_remove_agent() {
+        let store = PrinterStatusStore::new();
+        store
+            .update("user", report("first", PrinterStatus::Busy))
+            .await;
+        let mut other = report("second", PrinterStatus::Available);
+        other.agent_uuid = "other".to_string();
+        store.update("user", other).await;
+
+        store.remove_agent("agent").await;
+        assert!(store.get("first").await.is_none());
+        assert!(store.get("second").await.is_some());
+    }
+}
@@ -1,14 +1,78 @@
+use std::io;
+use std::pin::Pin;
+
 use axum::async_trait;
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use tracing::error;
 
 use crate::common::app_error::AppError;
+use crate::common::download::ByteRange;
+
+/// Chunks of a file that is written or read without buffering it in memory
+pub type ByteStream<'a> = Pin<Box<dyn Stream<Item = Result<Bytes, io::Error>> + Send + 'a>>;
+
+/// Size and last modification of a stored file
+/// - path: Path of the file as returned when writing it
+/// - modified_at: Unix timestamp in seconds
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileStat {
+    pub path: String,
+    pub size: u64,
+    pub modified_at: i64,
+}
 
+/// Backend the print files and thumbnails are stored in, built once at startup.
+/// Files are addressed by the path returned when writing them
 #[async_trait]
-pub trait FileStorageStrategy {
+pub trait FileStorageStrategy: Send + Sync {
+    /// Name of the backend, recorded with every stored file
+    fn storage_type(&self) -> &'static str;
     async fn write_file(
         &self,
         user_uuid: &str,
         filename: &str,
         data: &[u8],
     ) -> Result<String, AppError>;
+    /// Writes the chunks of the stream as they arrive, nothing is left behind when the stream fails
+    async fn write_stream(
+        &self,
+        user_uuid: &str,
+        filename: &str,
+        stream: ByteStream<'_>,
+    ) -> Result<String, AppError>;
     async fn retrieve_file(&self, filepath: &str) -> Result<Vec<u8>, AppError>;
+    /// Reads the file, or the range of it, in chunks
+    async fn retrieve_stream(
+        &self,
+        filepath: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream<'static>, AppError>;
+    /// Reads the range of the file into memory, for small parts of large files
+    async fn retrieve_range(&self, filepath: &str, range: ByteRange) -> Result<Vec<u8>, AppError> {
+        let mut stream = self.retrieve_stream(filepath, Some(range)).await?;
+        let mut data = Vec::with_capacity(range.length() as usize);
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(e) => {
+                    error!("error reading {}: {}", filepath, e);
+                    return Err(AppError::InternalServer);
+                }
+            }
+        }
+        Ok(data)
+    }
+    async fn delete_file(&self, filepath: &str) -> Result<(), AppError>;
+    async fn exists(&self, filepath: &str) -> Result<bool, AppError>;
+    async fn stat(&self, filepath: &str) -> Result<FileStat, AppError>;
+    /// Lists the files whose path starts with the prefix
+    async fn list(&self, prefix: &str) -> Result<Vec<FileStat>, AppError>;
+    /// Copies the file to the files of the user, returns the path of the copy
+    async fn copy_file(
+        &self,
+        filepath: &str,
+        user_uuid: &str,
+        filename: &str,
+    ) -> Result<String, AppError>;
 }
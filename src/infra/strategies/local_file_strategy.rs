@@ -1,47 +1,297 @@
-use std::env;
+use std::io::{ErrorKind, SeekFrom};
+use std::time::UNIX_EPOCH;
 
 use axum::async_trait;
+use axum::http::StatusCode;
+use futures_util::StreamExt;
 use tokio::fs;
 use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
 use tracing::{error, info};
 
 use crate::common::app_error::AppError;
-use crate::infra::strategies::file_storage_strategy::FileStorageStrategy;
+use crate::common::download::ByteRange;
+use crate::infra::strategies::file_storage_strategy::{ByteStream, FileStat, FileStorageStrategy};
 
-pub struct LocalFileStrategy {}
+/// Stores the files in a directory per user below the base directory,
+/// files are addressed by their path relative to the base directory
+pub struct LocalFileStrategy {
+    base_directory: String,
+}
+
+impl LocalFileStrategy {
+    pub fn new(base_directory: &str) -> Self {
+        LocalFileStrategy {
+            base_directory: base_directory.to_string(),
+        }
+    }
+
+    /// Path of the file on disk. Files stored before paths became relative were
+    /// recorded with the base directory in front, these are used as they are
+    fn resolve(&self, filepath: &str) -> String {
+        match filepath.strip_prefix(&self.base_directory) {
+            Some(relative) if relative.starts_with('/') => filepath.to_string(),
+            _ => format!("{}/{}", self.base_directory, filepath),
+        }
+    }
+
+    /// Creates the directories the file is stored in, filenames may contain directories
+    async fn create_directory(&self, filepath: &str) {
+        if let Some((directory, _)) = self.resolve(filepath).rsplit_once('/') {
+            fs::create_dir_all(directory).await.unwrap(); // We don't care if the directory already exists
+        }
+    }
+
+    async fn create_file(
+        &self,
+        user_uuid: &str,
+        filename: &str,
+    ) -> Result<(String, File), AppError> {
+        let filepath = format!("{}/{}", user_uuid, filename);
+        self.create_directory(&filepath).await;
+
+        match File::create(self.resolve(&filepath)).await {
+            Ok(file) => Ok((filepath, file)),
+            Err(e) => {
+                error!("error creating file {}: {}", filepath, e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
+
+fn storage_error(filepath: &str, e: std::io::Error) -> AppError {
+    if e.kind() == ErrorKind::NotFound {
+        return AppError::PrintFile {
+            message: "File not found in the file storage".to_string(),
+            status: StatusCode::NOT_FOUND,
+        };
+    }
+    error!("error accessing file {}: {}", filepath, e);
+    AppError::InternalServer
+}
 
 #[async_trait]
 impl FileStorageStrategy for LocalFileStrategy {
+    fn storage_type(&self) -> &'static str {
+        "local"
+    }
+
     async fn write_file(
         &self,
         user_uuid: &str,
         filename: &str,
         data: &[u8],
     ) -> Result<String, AppError> {
-        let base_directory = env::var("FILESTORAGE_PATH").expect("FILESTORAGE_PATH must be set!");
+        let (filepath, mut file) = self.create_file(user_uuid, filename).await?;
+        match file.write_all(data).await {
+            Ok(_) => {
+                info!("file {} written successfully", filename);
+                Ok(filepath.to_string())
+            }
+            Err(e) => {
+                error!("error writing file {}: {}", filename, e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
 
-        let directory = format!("{}/{}", base_directory, user_uuid);
-        fs::create_dir_all(&directory).await.unwrap(); // We don't care if the directory already exists
+    async fn write_stream(
+        &self,
+        user_uuid: &str,
+        filename: &str,
+        mut stream: ByteStream<'_>,
+    ) -> Result<String, AppError> {
+        let (filepath, mut file) = self.create_file(user_uuid, filename).await?;
+        let mut written = Ok(());
+        while let Some(chunk) = stream.next().await {
+            written = match chunk {
+                Ok(chunk) => file.write_all(&chunk).await,
+                Err(e) => Err(e),
+            };
+            if written.is_err() {
+                break;
+            }
+        }
+        if written.is_ok() {
+            written = file.flush().await;
+        }
 
-        let filepath = format!("{}/{}", directory, filename);
-        let mut file = File::create(&filepath).await.unwrap();
-        match file.write_all(data).await {
+        match written {
             Ok(_) => {
                 info!("file {} written successfully", filename);
                 Ok(filepath.to_string())
             }
             Err(e) => {
                 error!("error writing file {}: {}", filename, e);
+                let _ = fs::remove_file(self.resolve(&filepath)).await;
                 Err(AppError::InternalServer)
             }
         }
     }
 
     async fn retrieve_file(&self, filepath: &str) -> Result<Vec<u8>, AppError> {
-        let base_directory = env::var("FILESTORAGE_PATH").expect("FILESTORAGE_PATH must be set!");
-        let filepath = format!("{}/{}", base_directory, filepath);
-        let data = fs::read(filepath).await.unwrap();
-        Ok(data)
+        fs::read(self.resolve(filepath))
+            .await
+            .map_err(|e| storage_error(filepath, e))
+    }
+
+    async fn retrieve_stream(
+        &self,
+        filepath: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream<'static>, AppError> {
+        let mut file = File::open(self.resolve(filepath))
+            .await
+            .map_err(|e| storage_error(filepath, e))?;
+
+        let (start, length) = match range {
+            Some(range) => (range.start, range.length()),
+            None => (0, u64::MAX),
+        };
+        file.seek(SeekFrom::Start(start))
+            .await
+            .map_err(|e| storage_error(filepath, e))?;
+
+        Ok(Box::pin(ReaderStream::new(file.take(length))))
+    }
+
+    async fn delete_file(&self, filepath: &str) -> Result<(), AppError> {
+        fs::remove_file(self.resolve(filepath))
+            .await
+            .map_err(|e| storage_error(filepath, e))
+    }
+
+    async fn exists(&self, filepath: &str) -> Result<bool, AppError> {
+        fs::try_exists(self.resolve(filepath))
+            .await
+            .map_err(|e| storage_error(filepath, e))
+    }
+
+    async fn stat(&self, filepath: &str) -> Result<FileStat, AppError> {
+        let metadata = fs::metadata(self.resolve(filepath))
+            .await
+            .map_err(|e| storage_error(filepath, e))?;
+        let modified_at = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_secs() as i64);
+
+        Ok(FileStat {
+            path: filepath.to_string(),
+            size: metadata.len(),
+            modified_at,
+        })
+    }
+
+    /// Walks the directories below the last directory of the prefix
+    async fn list(&self, prefix: &str) -> Result<Vec<FileStat>, AppError> {
+        let start = match prefix.rsplit_once('/') {
+            Some((directory, _)) => self.resolve(directory),
+            None => self.base_directory.to_string(),
+        };
+
+        let mut files = Vec::new();
+        let mut directories = vec![start];
+        while let Some(directory) = directories.pop() {
+            let mut entries = match fs::read_dir(&directory).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(storage_error(&directory, e)),
+            };
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|e| storage_error(&directory, e))?
+            {
+                let path = format!("{}/{}", directory, entry.file_name().to_string_lossy());
+                let file_type = entry
+                    .file_type()
+                    .await
+                    .map_err(|e| storage_error(&path, e))?;
+                let filepath = &path[self.base_directory.len() + 1..];
+                if file_type.is_dir() {
+                    directories.push(path.to_string());
+                } else if filepath.starts_with(prefix) {
+                    files.push(self.stat(filepath).await?);
+                }
+            }
+        }
+
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    async fn copy_file(
+        &self,
+        filepath: &str,
+        user_uuid: &str,
+        filename: &str,
+    ) -> Result<String, AppError> {
+        let target = format!("{}/{}", user_uuid, filename);
+        self.create_directory(&target).await;
+
+        fs::copy(self.resolve(filepath), self.resolve(&target))
+            .await
+            .map_err(|e| storage_error(filepath, e))?;
+
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_file_strategy() {
+        let base_directory =
+            std::env::temp_dir().join(format!("printerlynx-{}", uuid::Uuid::new_v4()));
+        let strategy = LocalFileStrategy::new(&base_directory.to_string_lossy());
+
+        let path = strategy
+            .write_file("user", "part.gcode", b"G28\nG1 X10\n")
+            .await
+            .unwrap();
+        assert!(strategy.exists(&path).await.unwrap());
+        assert_eq!(strategy.stat(&path).await.unwrap().size, 11);
+
+        let range = ByteRange { start: 4, end: 9 };
+        let mut stream = strategy.retrieve_stream(&path, Some(range)).await.unwrap();
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        assert_eq!(data, b"G1 X10");
+
+        let copy = strategy
+            .copy_file(&path, "other", "file/part.gcode")
+            .await
+            .unwrap();
+        assert_eq!(path, "user/part.gcode");
+        assert_eq!(copy, "other/file/part.gcode");
+        let listed = strategy.list("").await.unwrap();
+        assert_eq!(
+            listed
+                .iter()
+                .map(|file| file.path.as_str())
+                .collect::<Vec<_>>(),
+            [copy.as_str(), path.as_str()]
+        );
+
+        // paths stored before they became relative
+        let legacy = format!("{}/{}", base_directory.to_string_lossy(), copy);
+        assert_eq!(
+            strategy.retrieve_file(&legacy).await.unwrap(),
+            b"G28\nG1 X10\n"
+        );
+
+        strategy.delete_file(&path).await.unwrap();
+        assert!(!strategy.exists(&path).await.unwrap());
+        assert!(strategy.stat(&path).await.is_err());
+
+        fs::remove_dir_all(&base_directory).await.unwrap();
     }
 }
@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use axum::async_trait;
+use axum::body::Bytes;
+use axum::http::StatusCode;
+use chrono::Utc;
+use futures_util::{stream, StreamExt};
+
+use crate::common::app_error::AppError;
+use crate::common::download::ByteRange;
+use crate::infra::strategies::file_storage_strategy::{ByteStream, FileStat, FileStorageStrategy};
+
+/// Keeps the files in memory, for tests and development without a disk or S3 server.
+/// The files are lost when the server stops
+#[derive(Default)]
+pub struct MemoryFileStrategy {
+    files: RwLock<BTreeMap<String, MemoryFile>>,
+}
+
+struct MemoryFile {
+    data: Bytes,
+    modified_at: i64,
+}
+
+impl MemoryFileStrategy {
+    pub fn new() -> Self {
+        MemoryFileStrategy::default()
+    }
+
+    fn insert(&self, filepath: &str, data: Bytes) {
+        let file = MemoryFile {
+            data,
+            modified_at: Utc::now().timestamp(),
+        };
+        self.files
+            .write()
+            .unwrap()
+            .insert(filepath.to_string(), file);
+    }
+
+    fn get(&self, filepath: &str) -> Result<Bytes, AppError> {
+        match self.files.read().unwrap().get(filepath) {
+            Some(file) => Ok(file.data.clone()),
+            None => Err(not_found()),
+        }
+    }
+}
+
+fn not_found() -> AppError {
+    AppError::PrintFile {
+        message: "File not found in the file storage".to_string(),
+        status: StatusCode::NOT_FOUND,
+    }
+}
+
+#[async_trait]
+impl FileStorageStrategy for MemoryFileStrategy {
+    fn storage_type(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn write_file(
+        &self,
+        user_uuid: &str,
+        filename: &str,
+        data: &[u8],
+    ) -> Result<String, AppError> {
+        let filepath = format!("{}/{}", user_uuid, filename);
+        self.insert(&filepath, Bytes::copy_from_slice(data));
+        Ok(filepath)
+    }
+
+    async fn write_stream(
+        &self,
+        user_uuid: &str,
+        filename: &str,
+        mut stream: ByteStream<'_>,
+    ) -> Result<String, AppError> {
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            match chunk {
+                Ok(chunk) => data.extend_from_slice(&chunk),
+                Err(_) => return Err(AppError::InternalServer),
+            }
+        }
+
+        let filepath = format!("{}/{}", user_uuid, filename);
+        self.insert(&filepath, Bytes::from(data));
+        Ok(filepath)
+    }
+
+    async fn retrieve_file(&self, filepath: &str) -> Result<Vec<u8>, AppError> {
+        Ok(self.get(filepath)?.to_vec())
+    }
+
+    async fn retrieve_stream(
+        &self,
+        filepath: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream<'static>, AppError> {
+        let data = self.get(filepath)?;
+        let data = match range {
+            Some(range) => {
+                let end = (range.end as usize + 1).min(data.len());
+                data.slice((range.start as usize).min(end)..end)
+            }
+            None => data,
+        };
+
+        Ok(Box::pin(stream::once(async move { Ok(data) })))
+    }
+
+    async fn delete_file(&self, filepath: &str) -> Result<(), AppError> {
+        match self.files.write().unwrap().remove(filepath) {
+            Some(_) => Ok(()),
+            None => Err(not_found()),
+        }
+    }
+
+    async fn exists(&self, filepath: &str) -> Result<bool, AppError> {
+        Ok(self.files.read().unwrap().contains_key(filepath))
+    }
+
+    async fn stat(&self, filepath: &str) -> Result<FileStat, AppError> {
+        match self.files.read().unwrap().get(filepath) {
+            Some(file) => Ok(FileStat {
+                path: filepath.to_string(),
+                size: file.data.len() as u64,
+                modified_at: file.modified_at,
+            }),
+            None => Err(not_found()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<FileStat>, AppError> {
+        Ok(self
+            .files
+            .read()
+            .unwrap()
+            .range(prefix.to_string()..)
+            .take_while(|(path, _)| path.starts_with(prefix))
+            .map(|(path, file)| FileStat {
+                path: path.to_string(),
+                size: file.data.len() as u64,
+                modified_at: file.modified_at,
+            })
+            .collect())
+    }
+
+    async fn copy_file(
+        &self,
+        filepath: &str,
+        user_uuid: &str,
+        filename: &str,
+    ) -> Result<String, AppError> {
+        let data = self.get(filepath)?;
+        let target = format!("{}/{}", user_uuid, filename);
+        self.insert(&target, data);
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn collect(mut stream: ByteStream<'static>) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn test_memory_file_strategy() {
+        let strategy = MemoryFileStrategy::new();
+
+        let path = strategy
+            .write_file("user", "part.gcode", b"G28\nG1 X10\n")
+            .await
+            .unwrap();
+        assert_eq!(path, "user/part.gcode");
+        assert!(strategy.exists(&path).await.unwrap());
+        assert_eq!(strategy.stat(&path).await.unwrap().size, 11);
+        assert_eq!(
+            strategy.retrieve_file(&path).await.unwrap(),
+            b"G28\nG1 X10\n"
+        );
+
+        let range = ByteRange { start: 4, end: 9 };
+        let stream = strategy.retrieve_stream(&path, Some(range)).await.unwrap();
+        assert_eq!(collect(stream).await, b"G1 X10");
+
+        let chunks = stream::iter([Ok(Bytes::from("solid ")), Ok(Bytes::from("cube"))]);
+        let streamed = strategy
+            .write_stream("user", "cube.stl", Box::pin(chunks))
+            .await
+            .unwrap();
+        assert_eq!(
+            strategy.retrieve_file(&streamed).await.unwrap(),
+            b"solid cube"
+        );
+
+        let copy = strategy
+            .copy_file(&path, "other", "part.gcode")
+            .await
+            .unwrap();
+        assert_eq!(copy, "other/part.gcode");
+
+        let listed = strategy.list("user/").await.unwrap();
+        let paths = listed
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["user/cube.stl", "user/part.gcode"]);
+
+        strategy.delete_file(&path).await.unwrap();
+        assert!(!strategy.exists(&path).await.unwrap());
+        assert!(strategy.stat(&path).await.is_err());
+        assert!(strategy.delete_file(&path).await.is_err());
+        assert!(strategy.exists(&copy).await.unwrap());
+    }
+}
@@ -1,3 +1,4 @@
 pub mod file_storage_strategy;
 pub mod local_file_strategy;
+pub mod memory_file_strategy;
 pub mod s3_file_strategy;
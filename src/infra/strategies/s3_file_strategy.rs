@@ -1,21 +1,35 @@
 use crate::common::app_error::AppError;
-use crate::infra::strategies::file_storage_strategy::FileStorageStrategy;
+use crate::common::download::ByteRange;
+use crate::infra::strategies::file_storage_strategy::{ByteStream, FileStat, FileStorageStrategy};
 use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::DateTime;
+use futures_util::{future, stream, StreamExt};
 use s3::creds::Credentials;
+use s3::error::S3Error;
 use s3::{Bucket, Region};
 use std::env;
-use tracing::info;
+use std::io;
+use tokio::io::AsyncReadExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::{error, info};
 
-pub struct S3FileStrategy {}
+/// Buffer between the S3 download and the response stream
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Stores the files in a S3 compatible bucket, with a prefix per user
+pub struct S3FileStrategy {
+    bucket: Bucket,
+}
 
 impl S3FileStrategy {
-    pub fn get_bucket(&self) -> Bucket {
+    pub fn from_env() -> Self {
         let bucket_name = env::var("S3_BUCKET_NAME").expect("S3_BUCKET_NAME must be set!");
         let endpoint = env::var("S3_ENDPOINT").expect("S3_ENDPOINT must be set!");
         let access_key = env::var("S3_ACCESS_KEY").expect("S3_TOKEN must be set!");
         let secret_key = env::var("S3_SECRET_KEY").expect("S3_SECRET_KEY must be set!");
 
-        Bucket::new(
+        let bucket = Bucket::new(
             &bucket_name,
             Region::Custom {
                 region: "eu-central-1".to_owned(),
@@ -25,38 +39,223 @@ impl S3FileStrategy {
                 .expect("Failed to retrieve Credentials from S3"),
         )
         .expect("Failed to retrieve Bucket from S3")
-        .with_path_style()
+        .with_path_style();
+
+        S3FileStrategy { bucket }
+    }
+}
+
+fn storage_error(filepath: &str, e: S3Error) -> AppError {
+    if let S3Error::Http(404, _) = e {
+        return AppError::PrintFile {
+            message: "File not found in the file storage".to_string(),
+            status: StatusCode::NOT_FOUND,
+        };
+    }
+    error!("error accessing {} on S3: {}", filepath, e);
+    AppError::InternalServer
+}
+
+/// Reads the timestamps of S3, listings use RFC 3339 and headers RFC 2822
+fn parse_timestamp(value: &str) -> i64 {
+    DateTime::parse_from_rfc3339(value)
+        .or_else(|_| DateTime::parse_from_rfc2822(value))
+        .map_or(0, |timestamp| timestamp.timestamp())
+}
+
+/// Start, end and length of the part of the object that is downloaded. rust-s3 requires
+/// start < end, a single byte is requested together with the next one and cut to its length.
+/// The range stays closed so the download ends with the byte, S3 clamps an end past the object
+fn object_range(range: Option<ByteRange>) -> (u64, Option<u64>, u64) {
+    match range {
+        Some(range) => (
+            range.start,
+            Some(range.end.max(range.start + 1)),
+            range.length(),
+        ),
+        None => (0, None, u64::MAX),
     }
 }
 
 #[async_trait]
 impl FileStorageStrategy for S3FileStrategy {
+    fn storage_type(&self) -> &'static str {
+        "s3"
+    }
+
     async fn write_file(
         &self,
         user_uuid: &str,
         filename: &str,
         data: &[u8],
     ) -> Result<String, AppError> {
-        let bucket = self.get_bucket();
         let filepath = format!("{}/{}", user_uuid, filename);
 
-        let response_data = bucket
+        let response_data = self
+            .bucket
             .put_object(&filepath, data)
             .await
-            .expect("Failed to put object to S3");
+            .map_err(|e| storage_error(&filepath, e))?;
         info!("response_data: {:?}", response_data);
 
         Ok(filepath.to_string())
     }
 
+    /// Uploads the stream in parts with a multipart upload
+    async fn write_stream(
+        &self,
+        user_uuid: &str,
+        filename: &str,
+        stream: ByteStream<'_>,
+    ) -> Result<String, AppError> {
+        let filepath = format!("{}/{}", user_uuid, filename);
+
+        let mut reader = StreamReader::new(stream);
+        match self.bucket.put_object_stream(&mut reader, &filepath).await {
+            Ok(status) => {
+                info!("streamed {} to S3, status: {}", filepath, status);
+                Ok(filepath.to_string())
+            }
+            Err(e) => {
+                error!("error streaming {} to S3: {}", filepath, e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
     async fn retrieve_file(&self, filepath: &str) -> Result<Vec<u8>, AppError> {
-        let bucket = self.get_bucket();
-        let data = bucket
+        let data = self
+            .bucket
             .get_object(filepath)
             .await
-            .expect("Failed to get object from S3");
+            .map_err(|e| storage_error(filepath, e))?;
+
+        Ok(data.to_vec())
+    }
+
+    /// Downloads the object into a pipe that is read by the returned stream,
+    /// a failed download ends the stream with an error
+    async fn retrieve_stream(
+        &self,
+        filepath: &str,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream<'static>, AppError> {
+        let bucket = self.bucket.clone();
+        let path = filepath.to_string();
+        let (start, end, length) = object_range(range);
+
+        let (reader, mut writer) = tokio::io::duplex(STREAM_BUFFER_SIZE);
+        let download = tokio::spawn(async move {
+            bucket
+                .get_object_range_to_writer(&path, start, end, &mut writer)
+                .await
+        });
+        let failed = stream::once(async move {
+            match download.await {
+                Ok(Ok(status)) if status < 300 => None,
+                Ok(Ok(status)) => Some(format!("S3 responded with status {}", status)),
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(e) => Some(e.to_string()),
+            }
+        })
+        .filter_map(|failure| future::ready(failure.map(|message| Err(io::Error::other(message)))));
+
+        Ok(Box::pin(
+            ReaderStream::new(reader.take(length)).chain(failed),
+        ))
+    }
+
+    async fn delete_file(&self, filepath: &str) -> Result<(), AppError> {
+        self.bucket
+            .delete_object(filepath)
+            .await
+            .map_err(|e| storage_error(filepath, e))?;
+        Ok(())
+    }
+
+    async fn exists(&self, filepath: &str) -> Result<bool, AppError> {
+        match self.bucket.head_object(filepath).await {
+            Ok((_, 404)) | Err(S3Error::Http(404, _)) => Ok(false),
+            Ok(_) => Ok(true),
+            Err(e) => Err(storage_error(filepath, e)),
+        }
+    }
+
+    async fn stat(&self, filepath: &str) -> Result<FileStat, AppError> {
+        let head = match self.bucket.head_object(filepath).await {
+            Ok((_, 404)) => Err(S3Error::Http(404, String::new())),
+            Ok((head, _)) => Ok(head),
+            Err(e) => Err(e),
+        }
+        .map_err(|e| storage_error(filepath, e))?;
+
+        Ok(FileStat {
+            path: filepath.to_string(),
+            size: head.content_length.unwrap_or_default() as u64,
+            modified_at: head.last_modified.as_deref().map_or(0, parse_timestamp),
+        })
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<FileStat>, AppError> {
+        let pages = self
+            .bucket
+            .list(prefix.to_string(), None)
+            .await
+            .map_err(|e| storage_error(prefix, e))?;
+
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| FileStat {
+                modified_at: parse_timestamp(&object.last_modified),
+                path: object.key,
+                size: object.size,
+            })
+            .collect())
+    }
+
+    async fn copy_file(
+        &self,
+        filepath: &str,
+        user_uuid: &str,
+        filename: &str,
+    ) -> Result<String, AppError> {
+        let target = format!("{}/{}", user_uuid, filename);
+        self.bucket
+            .copy_object_internal(filepath, &target)
+            .await
+            .map_err(|e| storage_error(filepath, e))?;
+
+        Ok(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("2009-10-12T17:50:30.000Z"), 1255369830);
+        assert_eq!(parse_timestamp("Mon, 12 Oct 2009 17:50:30 GMT"), 1255369830);
+        assert_eq!(parse_timestamp("yesterday"), 0);
+    }
 
-        let file_data = data.to_vec();
-        Ok(file_data)
+    #[test]
+    fn test_object_range() {
+        assert_eq!(object_range(None), (0, None, u64::MAX));
+        assert_eq!(
+            object_range(Some(ByteRange { start: 4, end: 9 })),
+            (4, Some(9), 6)
+        );
+        // a single byte is downloaded with the next one, not with the rest of the object
+        assert_eq!(
+            object_range(Some(ByteRange { start: 5, end: 5 })),
+            (5, Some(6), 1)
+        );
+        assert_eq!(
+            object_range(Some(ByteRange { start: 0, end: 0 })),
+            (0, Some(1), 1)
+        );
     }
 }
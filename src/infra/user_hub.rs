@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use axum::extract::ws::Message;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::RwLock;
+use tracing::info;
+
+/// A live websocket session of an authenticated user
+#[derive(Clone, Debug)]
+pub struct UserConnection {
+    pub session_uuid: String,
+    pub sender: UnboundedSender<Message>,
+}
+
+/// Pub/sub hub of the authenticated user sessions, keyed by user uuid.
+/// A user can have multiple sessions open, e.g. multiple browser tabs
+#[derive(Default, Debug)]
+pub struct UserHub {
+    sessions: RwLock<HashMap<String, Vec<UserConnection>>>,
+}
+
+impl UserHub {
+    pub fn new() -> Self {
+        UserHub::default()
+    }
+
+    /// Subscribes a session to the messages published to the user
+    pub async fn subscribe(&self, user_uuid: &str, connection: UserConnection) {
+        let mut sessions = self.sessions.write().await;
+        let user_sessions = sessions.entry(user_uuid.to_string()).or_default();
+        user_sessions.push(connection);
+        info!(
+            "user {} subscribed, {} open session(s)",
+            user_uuid,
+            user_sessions.len()
+        );
+    }
+
+    /// Removes a session, the user is removed once its last session is gone
+    pub async fn unsubscribe(&self, user_uuid: &str, session_uuid: &str) {
+        let mut sessions = self.sessions.write().await;
+
+        if let Some(user_sessions) = sessions.get_mut(user_uuid) {
+            user_sessions.retain(|connection| connection.session_uuid != session_uuid);
+            if user_sessions.is_empty() {
+                sessions.remove(user_uuid);
+            }
+        }
+    }
+
+    /// Sends the message to all sessions of the user, returns the amount of sessions reached
+    pub async fn publish(&self, user_uuid: &str, message: Message) -> usize {
+        let sessions = self.sessions.read().await;
+
+        match sessions.get(user_uuid) {
+            Some(user_sessions) => user_sessions
+                .iter()
+                .filter(|connection| connection.sender.send(message.clone()).is_ok())
+                .count(),
+            None => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+    fn connection(session_uuid: &str) -> (UserConnection, UnboundedReceiver<Message>) {
+        let (sender, receiver) = unbounded_channel();
+        let connection = UserConnection {
+            session_uuid: session_uuid.to_string(),
+            sender,
+        };
+        (connection, receiver)
+    }
+
+    #[tokio::test]
+    async fn test_publish_to_all_sessions() {
+        let hub = UserHub::new();
+        let (first, mut first_receiver) = connection("first");
+        let (second, mut second_receiver) = connection("second");
+
+        hub.subscribe("user", first).await;
+        hub.subscribe("user", second).await;
+
+        assert_eq!(hub.publish("user", Message::from("hello")).await, 2);
+        assert_eq!(first_receiver.recv().await, Some(Message::from("hello")));
+        assert_eq!(second_receiver.recv().await, Some(Message::from("hello")));
+        assert_eq!(hub.publish("other", Message::from("hello")).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_unsubscribe() {
+        let hub = UserHub::new();
+        let (first, _first_receiver) = connection("first");
+        let (second, mut second_receiver) = connection("second");
+
+        hub.subscribe("user", first).await;
+        hub.subscribe("user", second).await;
+        hub.unsubscribe("user", "first").await;
+
+        assert_eq!(hub.publish("user", Message::from("hello")).await, 1);
+        assert_eq!(second_receiver.recv().await, Some(Message::from("hello")));
+
+        hub.unsubscribe("user", "second").await;
+        assert_eq!(hub.publish("user", Message::from("hello")).await, 0);
+    }
+}
@@ -1 +1,3 @@
-
+pub mod print_dispatcher;
+pub mod storage_cleanup;
+pub mod storage_migration;
@@ -0,0 +1,342 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use crate::common::app_error::AppError;
+use crate::controllers::websockets::websocket_message::{
+    Payload, PrintJobMessage, PrintJobType, WebSocketMessage,
+};
+use crate::infra::agent_registry::AgentRequest;
+use crate::models::print_job::{PrintJobDbModel, PrintJobState};
+use crate::models::printer_status::PrinterStatus;
+use crate::services::print_job_service::{PrintJobService, PrintJobServiceImpl};
+use crate::services::print_queue_service::{PrintQueueService, PrintQueueServiceImpl};
+use crate::services::printer_service::{PrinterService, PrinterServiceImpl};
+use crate::services::printfile_service::{PrintFileService, PrintFileServiceImpl};
+use crate::AppState;
+
+/// Time an agent has to confirm or reject a print job after acknowledging it
+pub const PRINT_JOB_REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A printer that may be able to start the next queued print file
+#[derive(Debug, Clone, PartialEq)]
+pub struct DispatchRequest {
+    pub user_uuid: String,
+    pub printer_uuid: String,
+}
+
+/// Hands printers that became ready to the dispatcher task
+#[derive(Debug)]
+pub struct PrintDispatcher {
+    sender: UnboundedSender<DispatchRequest>,
+}
+
+impl PrintDispatcher {
+    pub fn new() -> (Self, UnboundedReceiver<DispatchRequest>) {
+        let (sender, receiver) = unbounded_channel();
+        (PrintDispatcher { sender }, receiver)
+    }
+
+    pub fn notify(&self, user_uuid: &str, printer_uuid: &str) {
+        let request = DispatchRequest {
+            user_uuid: user_uuid.to_string(),
+            printer_uuid: printer_uuid.to_string(),
+        };
+        if self.sender.send(request).is_err() {
+            warn!("Print dispatcher is not running");
+        }
+    }
+}
+
+/// Spawns the dispatcher task, requests are handled one at a time so a queue entry is only started once.
+/// Only the database work is serialized, the round-trip to the agent runs in its own task
+pub fn spawn(
+    state: Arc<AppState>,
+    mut receiver: UnboundedReceiver<DispatchRequest>,
+) -> JoinHandle<()> {
+    info!("print dispatcher started");
+    tokio::spawn(async move {
+        while let Some(request) = receiver.recv().await {
+            if let Err(err) = dispatch(&state, &request).await {
+                warn!(
+                    "Error dispatching queue of printer {}: {:?}",
+                    request.printer_uuid, err
+                );
+            }
+        }
+    })
+}
+
+/// Starts the next queued print file on the printer if it is available, its bed is cleared
+/// and it has no active print job
+async fn dispatch(
+    state: &Arc<AppState>,
+    request: &DispatchRequest,
+) -> Result<Option<PrintJobDbModel>, AppError> {
+    match state.printer_statuses.get(&request.printer_uuid).await {
+        Some(stored)
+            if stored.user_uuid == request.user_uuid
+                && stored.status.report.status == PrinterStatus::Available => {}
+        _ => return Ok(None),
+    }
+
+    let printer_service = PrinterServiceImpl::new(state.db_pool.clone());
+    let printer = printer_service
+        .get_by_uuid(&request.user_uuid, &request.printer_uuid)
+        .await?;
+    if !printer.bed_cleared {
+        return Ok(None);
+    }
+
+    let print_job_service = PrintJobServiceImpl::new(state.db_pool.clone());
+    if print_job_service
+        .get_active(&request.user_uuid, &request.printer_uuid)
+        .await?
+        .is_some()
+    {
+        return Ok(None);
+    }
+
+    let print_queue_service =
+        PrintQueueServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    let entry = match print_queue_service
+        .get_next(&request.user_uuid, &request.printer_uuid)
+        .await?
+    {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+
+    let result = create_print_job(
+        state,
+        &request.user_uuid,
+        &printer.agent_uuid,
+        &printer.uuid,
+        &entry.print_file_uuid,
+        PrintJobState::Queued,
+    )
+    .await;
+
+    match result {
+        Ok(job) => {
+            // the queued job keeps the printer busy, the entry is claimed before the agent
+            // is contacted so it is never started twice
+            print_queue_service
+                .delete(&request.user_uuid, &entry.uuid)
+                .await?;
+            info!(
+                "dispatched queued file {} to printer {} as print job {}",
+                entry.print_file_uuid, printer.uuid, job.uuid
+            );
+
+            // the next request is handled while the agent acknowledges and prepares the print
+            let send_state = state.clone();
+            let send_job = job.clone();
+            tokio::spawn(async move {
+                if let Err(err) = start_queued_print_job(&send_state, send_job).await {
+                    warn!("Queued print job was not started: {:?}", err);
+                }
+            });
+            Ok(Some(job))
+        }
+        // the file was deleted after it was queued, it can never be printed
+        Err(AppError::PrintFile { .. }) => {
+            print_queue_service
+                .delete(&request.user_uuid, &entry.uuid)
+                .await?;
+            Err(AppError::PrintJob {
+                message: format!(
+                    "Removed queue entry {}, its print file no longer exists",
+                    entry.uuid
+                ),
+                status: StatusCode::GONE,
+            })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Records a new print job and sends it to the agent, the job fails if the agent never acknowledges it.
+/// The caller checks the ownership of the agent and the printer, and awaits the reply of the agent
+/// with confirm_print_job
+pub async fn start_print_job(
+    state: &Arc<AppState>,
+    user_uuid: &str,
+    agent_uuid: &str,
+    printer_uuid: &str,
+    print_file_uuid: &str,
+) -> Result<(PrintJobDbModel, AgentRequest), AppError> {
+    let job = create_print_job(
+        state,
+        user_uuid,
+        agent_uuid,
+        printer_uuid,
+        print_file_uuid,
+        PrintJobState::Starting,
+    )
+    .await?;
+    let request = send_print_job(state, &job).await?;
+    Ok((job, request))
+}
+
+/// Records a new print job in the given state if the print file exists and the agent is online
+async fn create_print_job(
+    state: &Arc<AppState>,
+    user_uuid: &str,
+    agent_uuid: &str,
+    printer_uuid: &str,
+    print_file_uuid: &str,
+    job_state: PrintJobState,
+) -> Result<PrintJobDbModel, AppError> {
+    let printfile_service =
+        PrintFileServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+    printfile_service
+        .get_by_uuid(user_uuid, print_file_uuid)
+        .await?;
+
+    if !state.agent_registry.is_online(agent_uuid).await {
+        return Err(AppError::Agent {
+            message: "Agent is not connected".to_string(),
+            status: StatusCode::CONFLICT,
+        });
+    }
+
+    PrintJobServiceImpl::new(state.db_pool.clone())
+        .start(
+            user_uuid,
+            agent_uuid,
+            printer_uuid,
+            print_file_uuid,
+            job_state,
+        )
+        .await
+}
+
+/// Moves a job created from the print queue to starting and sends it to its agent,
+/// a job that was cancelled while it was queued is not sent
+async fn start_queued_print_job(
+    state: &Arc<AppState>,
+    job: PrintJobDbModel,
+) -> Result<(), AppError> {
+    let job = PrintJobServiceImpl::new(state.db_pool.clone())
+        .transition(job, PrintJobState::Starting, None)
+        .await?;
+    let request = send_print_job(state, &job).await?;
+    confirm_print_job(state, job, request).await
+}
+
+/// Sends the print job to its agent, the job fails if the agent never acknowledges it
+async fn send_print_job(
+    state: &Arc<AppState>,
+    job: &PrintJobDbModel,
+) -> Result<AgentRequest, AppError> {
+    let print_job = PrintJobMessage {
+        job_type: PrintJobType::Start,
+        agent_uuid: job.agent_uuid.to_string(),
+        print_file_uuid: job.print_file_uuid.to_string(),
+        printer_uuid: job.printer_uuid.to_string(),
+        print_job_uuid: Some(job.uuid.to_string()),
+    };
+
+    info!(
+        "forwarding print job {} of file {} to agent {}",
+        job.uuid, job.print_file_uuid, job.agent_uuid
+    );
+    let message = WebSocketMessage::PrintJob(Payload::Request(print_job));
+    match state.agent_registry.request(&job.agent_uuid, message).await {
+        Ok(request) => Ok(request),
+        Err(err) => {
+            PrintJobServiceImpl::new(state.db_pool.clone())
+                .transition(job.clone(), PrintJobState::Failed, None)
+                .await?;
+            Err(err)
+        }
+    }
+}
+
+/// Waits for the agent to confirm that it started the print job, the job fails if the agent
+/// rejects it, does not reply in time or disconnects
+pub async fn confirm_print_job(
+    state: &Arc<AppState>,
+    job: PrintJobDbModel,
+    request: AgentRequest,
+) -> Result<(), AppError> {
+    let result = match state
+        .agent_registry
+        .await_reply(request, PRINT_JOB_REPLY_TIMEOUT)
+        .await
+    {
+        Ok(reply) => print_job_reply_result(reply),
+        Err(err) => Err(err),
+    };
+
+    if let Err(err) = result {
+        // printer status reports may have moved the job along in the meantime
+        let print_job_service = PrintJobServiceImpl::new(state.db_pool.clone());
+        let job = print_job_service
+            .get_by_uuid(&job.user_uuid, &job.uuid)
+            .await?;
+        if job.state() == PrintJobState::Starting {
+            print_job_service
+                .transition(job, PrintJobState::Failed, None)
+                .await?;
+        }
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// Converts the reply of an agent to a print job into the result of the request
+pub fn print_job_reply_result(reply: WebSocketMessage) -> Result<(), AppError> {
+    match reply {
+        WebSocketMessage::PrintJob(Payload::Reply(response))
+        | WebSocketMessage::Error(response) => response.into_result(),
+        reply => Err(AppError::Agent {
+            message: format!("Unexpected reply to print job: {:?}", reply),
+            status: StatusCode::BAD_GATEWAY,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controllers::websockets::websocket_message::StatusResponse;
+
+    #[tokio::test]
+    async fn test_notify() {
+        let (dispatcher, mut receiver) = PrintDispatcher::new();
+        dispatcher.notify("user", "printer");
+
+        assert_eq!(
+            receiver.recv().await,
+            Some(DispatchRequest {
+                user_uuid: "user".to_string(),
+                printer_uuid: "printer".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_print_job_reply_result() {
+        let ok = WebSocketMessage::PrintJob(Payload::Reply(StatusResponse::ok()));
+        assert!(print_job_reply_result(ok).is_ok());
+
+        let rejected = WebSocketMessage::Error(StatusResponse {
+            status: "ERROR".to_string(),
+            message: Some("Printer is busy".to_string()),
+        });
+        assert_eq!(
+            print_job_reply_result(rejected).unwrap_err().to_string(),
+            "Printer is busy"
+        );
+
+        let unexpected = WebSocketMessage::Ack;
+        assert!(print_job_reply_result(unexpected).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+use crate::common::app_error::AppError;
+use crate::services::storage_deletion_service::{
+    StorageDeletionService, StorageDeletionServiceImpl,
+};
+use crate::AppState;
+
+/// Time between two runs of the storage cleanup
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Spawns the task that retries the deletions that failed when print files were deleted,
+/// so no file is left behind in the file storage
+pub fn spawn(state: Arc<AppState>) -> JoinHandle<()> {
+    info!("storage cleanup started");
+    tokio::spawn(async move {
+        let mut ticker = interval(CLEANUP_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = cleanup(&state).await {
+                warn!("Error cleaning up the file storage: {:?}", err);
+            }
+        }
+    })
+}
+
+/// Retries the queued deletions that are due, returns the number of removed files
+async fn cleanup(state: &Arc<AppState>) -> Result<usize, AppError> {
+    let storage_deletion_service =
+        StorageDeletionServiceImpl::new(state.db_pool.clone(), state.file_storages.clone());
+
+    let mut removed = 0;
+    for deletion in storage_deletion_service
+        .get_due(Utc::now().timestamp())
+        .await?
+    {
+        if storage_deletion_service.retry(&deletion).await? {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        info!("removed {} files from the file storage", removed);
+    }
+    Ok(removed)
+}
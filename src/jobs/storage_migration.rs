@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use futures_util::StreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::{MySql, Pool};
+use tracing::{error, info, warn};
+
+use crate::common::app_error::AppError;
+use crate::infra::filestorage::FileStorages;
+use crate::models::printfile::{storage_filename, PrintFileDbModel};
+use crate::services::printfile_service::{PrintFileService, PrintFileServiceImpl};
+use crate::services::printfile_thumbnail_service::{
+    PrintFileThumbnailService, PrintFileThumbnailServiceImpl,
+};
+use crate::services::storage_deletion_service::{
+    StorageDeletionService, StorageDeletionServiceImpl,
+};
+
+#[derive(Debug, Default)]
+pub struct MigrationSummary {
+    pub moved: usize,
+    pub failed: usize,
+}
+
+/// Moves the print files of one file storage with their thumbnails to another one.
+/// A file is switched to the target storage once its copy matches the checksum, the source
+/// is removed afterwards. Files that fail to move are counted and stay in the source storage
+pub async fn migrate(
+    pool: Arc<Pool<MySql>>,
+    file_storages: Arc<FileStorages>,
+    from: &str,
+    to: &str,
+) -> Result<MigrationSummary, AppError> {
+    if from == to {
+        return Err(AppError::PrintFile {
+            message: format!("The files are already stored in {}", to),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+    // both storages have to be configured before anything is moved
+    file_storages.get(from)?;
+    file_storages.get(to)?;
+
+    let printfile_service = PrintFileServiceImpl::new(pool.clone(), file_storages.clone());
+    let printfiles = printfile_service.get_by_storage_type(from).await?;
+    info!("moving {} files from {} to {}", printfiles.len(), from, to);
+
+    let mut summary = MigrationSummary::default();
+    for printfile in printfiles {
+        match move_printfile(&pool, &file_storages, &printfile, to).await {
+            Ok(_) => {
+                info!("moved {} to {}", printfile.path, to);
+                summary.moved += 1;
+            }
+            Err(e) => {
+                warn!("Error moving {} to {}: {:?}", printfile.path, to, e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Copies the print file and its thumbnails, records the new location and removes the source
+async fn move_printfile(
+    pool: &Arc<Pool<MySql>>,
+    file_storages: &Arc<FileStorages>,
+    printfile: &PrintFileDbModel,
+    to: &str,
+) -> Result<(), AppError> {
+    let source = file_storages.get(&printfile.file_storage_type)?;
+    let target = file_storages.get(to)?;
+    // names uploaded before they were checked fall back to the name of the stored file
+    let key = storage_filename(&printfile.uuid, &printfile.name)
+        .or_else(|| storage_filename(&printfile.uuid, filename(&printfile.path)))
+        .ok_or(AppError::InternalServer)?;
+
+    let mut hasher = Sha256::new();
+    let stream = source
+        .retrieve_stream(&printfile.path, None)
+        .await?
+        .map(|chunk| {
+            if let Ok(chunk) = &chunk {
+                hasher.update(chunk);
+            }
+            chunk
+        });
+    let path = target
+        .write_stream(&printfile.user_uuid, &key, Box::pin(stream))
+        .await?;
+    let mut written = vec![path.to_string()];
+
+    let checksum = format!("{:x}", hasher.finalize());
+    if checksum != printfile.checksum {
+        error!(
+            "checksum of {} doesn't match after the copy: {}",
+            printfile.path, checksum
+        );
+        remove(pool, file_storages, to, &written).await;
+        return Err(AppError::InternalServer);
+    }
+
+    let thumbnail_service = PrintFileThumbnailServiceImpl::new(pool.clone(), file_storages.clone());
+    let thumbnails = thumbnail_service.get_by_printfile(&printfile.uuid).await?;
+    for thumbnail in &thumbnails {
+        let copied = match source.retrieve_file(&thumbnail.path).await {
+            Ok(data) => {
+                target
+                    .write_file(&printfile.user_uuid, filename(&thumbnail.path), &data)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match copied {
+            Ok(thumbnail_path) => written.push(thumbnail_path),
+            Err(e) => {
+                remove(pool, file_storages, to, &written).await;
+                return Err(e);
+            }
+        }
+    }
+
+    PrintFileServiceImpl::new(pool.clone(), file_storages.clone())
+        .update_storage(&printfile.uuid, to, &path)
+        .await?;
+    for (thumbnail, thumbnail_path) in thumbnails.iter().zip(&written[1..]) {
+        thumbnail_service
+            .update_path(&thumbnail.uuid, thumbnail_path)
+            .await?;
+    }
+
+    let sources = thumbnails
+        .iter()
+        .map(|thumbnail| thumbnail.path.to_string())
+        .chain([printfile.path.to_string()])
+        .collect::<Vec<_>>();
+    remove(pool, file_storages, &printfile.file_storage_type, &sources).await;
+
+    Ok(())
+}
+
+/// Removes the files through the storage deletion queue, failed removals are retried later
+async fn remove(
+    pool: &Arc<Pool<MySql>>,
+    file_storages: &Arc<FileStorages>,
+    file_storage_type: &str,
+    paths: &[String],
+) {
+    let storage_deletion_service =
+        StorageDeletionServiceImpl::new(pool.clone(), file_storages.clone());
+    for path in paths {
+        if let Err(e) = storage_deletion_service
+            .delete_file(file_storage_type, path)
+            .await
+        {
+            warn!(
+                "Error removing {} from {}: {:?}",
+                path, file_storage_type, e
+            );
+        }
+    }
+}
+
+/// Last segment of the path, stored paths end with the filename
+fn filename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filename() {
+        assert_eq!(filename("user/part.gcode"), "part.gcode");
+        assert_eq!(filename("/srv/files/user/part.gcode"), "part.gcode");
+        assert_eq!(filename("part.gcode"), "part.gcode");
+    }
+}
@@ -4,9 +4,19 @@ use std::sync::Arc;
 
 use dotenvy::dotenv;
 use sqlx::{MySql, Pool};
-use tracing::info;
+use tracing::{error, info};
 
+use crate::infra::agent_registry::AgentRegistry;
 use crate::infra::database;
+use crate::infra::filestorage;
+use crate::infra::filestorage::FileStorages;
+use crate::infra::heartbeat::HeartbeatConfig;
+use crate::infra::printer_status_store::PrinterStatusStore;
+use crate::infra::user_hub::UserHub;
+use crate::jobs::print_dispatcher;
+use crate::jobs::print_dispatcher::PrintDispatcher;
+use crate::jobs::storage_cleanup;
+use crate::jobs::storage_migration;
 
 mod common;
 mod controllers;
@@ -20,6 +30,12 @@ mod services;
 #[derive(Clone)]
 pub struct AppState {
     pub db_pool: Arc<Pool<MySql>>,
+    pub file_storages: Arc<FileStorages>,
+    pub agent_registry: Arc<AgentRegistry>,
+    pub user_hub: Arc<UserHub>,
+    pub printer_statuses: Arc<PrinterStatusStore>,
+    pub print_dispatcher: Arc<PrintDispatcher>,
+    pub heartbeat: HeartbeatConfig,
 }
 
 /// Starts the Printerlynx Core Backend
@@ -30,7 +46,7 @@ pub async fn start() {
             panic!("Error loading .env file: {}", e)
         }
     }
-    
+
     tracing_subscriber::fmt().compact().with_target(true).init();
 
     info!("Starting Printerlynx Core...");
@@ -38,11 +54,23 @@ pub async fn start() {
     output_system_info();
 
     let db_pool = database::get_pool().await;
+    let (dispatcher, dispatch_requests) = PrintDispatcher::new();
 
     let state = Arc::new(AppState {
         db_pool: Arc::new(db_pool),
+        file_storages: Arc::new(FileStorages::from_env()),
+        agent_registry: Arc::new(AgentRegistry::new()),
+        user_hub: Arc::new(UserHub::new()),
+        printer_statuses: Arc::new(PrinterStatusStore::new()),
+        print_dispatcher: Arc::new(dispatcher),
+        heartbeat: HeartbeatConfig::from_env(),
     });
 
+    // starts queued print files when printers become available
+    print_dispatcher::spawn(state.clone(), dispatch_requests);
+    // retries the removal of stored files that failed when print files were deleted
+    storage_cleanup::spawn(state.clone());
+
     // init router and output addr information
     let app = router::api_v1::create(state).await;
     let port = 3000;
@@ -61,6 +89,33 @@ pub async fn start() {
         .expect("Failed to start Axum server")
 }
 
+/// Moves the print files and their thumbnails of one file storage to another,
+/// files that fail to move stay readable from their old file storage
+pub async fn migrate_storage(from: &str, to: &str) {
+    match dotenv() {
+        Ok(_) => {}
+        Err(e) => {
+            panic!("Error loading .env file: {}", e)
+        }
+    }
+
+    tracing_subscriber::fmt().compact().with_target(true).init();
+
+    info!("Migrating the file storage from {} to {}...", from, to);
+
+    let db_pool = Arc::new(database::get_pool().await);
+    let file_storages =
+        Arc::new(FileStorages::new(filestorage::build(to)).with(filestorage::build(from)));
+
+    match storage_migration::migrate(db_pool, file_storages, from, to).await {
+        Ok(summary) => info!(
+            "Migration finished, moved: {}, failed: {}",
+            summary.moved, summary.failed
+        ),
+        Err(e) => error!("Migration failed: {:?}", e),
+    }
+}
+
 pub fn output_system_info() {
     let operating_system = env::consts::OS;
     let architecture = env::consts::ARCH;
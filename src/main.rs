@@ -1,4 +1,17 @@
+use std::env;
+use std::process;
+
 #[tokio::main]
 async fn main() {
-    printerlynx_core_backend::start().await;
+    let args = env::args().collect::<Vec<_>>();
+    match args.get(1).map(String::as_str) {
+        Some("migrate-storage") => match (args.get(2), args.get(3)) {
+            (Some(from), Some(to)) => printerlynx_core_backend::migrate_storage(from, to).await,
+            _ => {
+                eprintln!("usage: {} migrate-storage <from> <to>", args[0]);
+                process::exit(2);
+            }
+        },
+        _ => printerlynx_core_backend::start().await,
+    }
 }
@@ -1,7 +1,15 @@
+use crate::common::app_error::AppError;
 use crate::models::view_model::ViewModel;
+use axum::http::StatusCode;
 use sea_query::Iden;
 use serde::{Deserialize, Serialize};
 
+/// Version of the agent websocket protocol spoken by this backend
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest agent protocol version the backend still accepts, version 1 agents don't acknowledge requests
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
 #[derive(Iden)]
 pub enum Agent {
     Table,
@@ -11,8 +19,14 @@ pub enum Agent {
     Description,
     Token,
     CreatedAt,
+    Version,
+    ProtocolVersion,
+    Os,
+    Arch,
+    Capabilities,
 }
 
+/// The handshake fields are reported by the agent when it authenticates, capabilities are stored as JSON
 #[derive(sqlx::FromRow, Debug)]
 pub struct AgentDbModel {
     pub uuid: String,
@@ -21,6 +35,58 @@ pub struct AgentDbModel {
     pub description: String,
     pub token: String,
     pub created_at: String,
+    pub version: Option<String>,
+    pub protocol_version: Option<i32>,
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    pub capabilities: Option<String>,
+}
+
+/// What an agent can do, the frontend hides features the agent doesn't support
+/// - adapters: Printer adapters the agent can talk to, e.g. SERIAL
+/// - webcam: Whether the agent can stream a webcam
+/// - max_printers: Number of printers the agent can run at the same time
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AgentCapabilities {
+    #[serde(default)]
+    pub adapters: Vec<String>,
+    #[serde(default)]
+    pub webcam: bool,
+    #[serde(default)]
+    pub max_printers: Option<u32>,
+}
+
+/// Details an agent reports about itself when it authenticates
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AgentHandshake {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<AgentCapabilities>,
+}
+
+impl AgentHandshake {
+    /// Refuses agents that speak a protocol older than MIN_PROTOCOL_VERSION,
+    /// agents that don't report a protocol version speak version 1
+    pub fn check_protocol(&self) -> Result<(), AppError> {
+        let protocol_version = self.protocol_version.unwrap_or(1);
+        if protocol_version < MIN_PROTOCOL_VERSION {
+            return Err(AppError::Agent {
+                message: format!(
+                    "Agent protocol version {} is no longer supported, the backend speaks version {} and requires {} or newer",
+                    protocol_version, PROTOCOL_VERSION, MIN_PROTOCOL_VERSION
+                ),
+                status: StatusCode::UPGRADE_REQUIRED,
+            });
+        }
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -29,13 +95,50 @@ pub struct AgentAddRequest {
     pub description: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AgentUpdateRequest {
+    pub name: String,
+    pub description: String,
+}
+
+/// The token is only included when it was just created or rotated, only its hash is stored
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AgentViewModel {
     pub uuid: String,
     pub name: String,
     pub description: String,
-    pub token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
     pub created_at: String,
+    pub version: Option<String>,
+    pub protocol_version: Option<i32>,
+    pub os: Option<String>,
+    pub arch: Option<String>,
+    pub capabilities: Option<AgentCapabilities>,
+}
+
+/// Live connection details of an agent, the address is only known while it is online
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct AgentConnectionInfo {
+    pub online: bool,
+    pub address: Option<String>,
+    pub last_seen: Option<String>,
+}
+
+/// Agent together with its live connection details
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AgentDetailViewModel {
+    #[serde(flatten)]
+    pub agent: AgentViewModel,
+    #[serde(flatten)]
+    pub connection: AgentConnectionInfo,
+}
+
+/// Plaintext token of an agent, returned once after rotating it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AgentTokenViewModel {
+    pub uuid: String,
+    pub token: String,
 }
 
 impl ViewModel for AgentDbModel {
@@ -46,8 +149,51 @@ impl ViewModel for AgentDbModel {
             uuid: self.uuid.to_string(),
             name: self.name.to_string(),
             description: self.description.to_string(),
-            token: self.token.to_string(),
+            token: None,
             created_at: self.created_at.to_string(),
+            version: self.version.clone(),
+            protocol_version: self.protocol_version,
+            os: self.os.clone(),
+            arch: self.arch.clone(),
+            capabilities: self
+                .capabilities
+                .as_deref()
+                .and_then(|capabilities| serde_json::from_str(capabilities).ok()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_protocol() {
+        let current = AgentHandshake {
+            protocol_version: Some(PROTOCOL_VERSION),
+            ..Default::default()
+        };
+        assert!(current.check_protocol().is_ok());
+
+        let outdated = AgentHandshake::default();
+        let err = outdated.check_protocol().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Agent protocol version 1 is no longer supported, the backend speaks version 2 and requires 2 or newer"
+        );
+    }
+
+    #[test]
+    fn test_capabilities_defaults() {
+        let capabilities: AgentCapabilities =
+            serde_json::from_str(r#"{"adapters":["SERIAL"]}"#).unwrap();
+        assert_eq!(
+            capabilities,
+            AgentCapabilities {
+                adapters: vec!["SERIAL".to_string()],
+                webcam: false,
+                max_printers: None,
+            }
+        );
+    }
+}
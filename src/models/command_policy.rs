@@ -0,0 +1,147 @@
+use std::fmt;
+use std::fmt::Display;
+
+use crate::common::gcode::command_word;
+use crate::models::view_model::ViewModel;
+use sea_query::Iden;
+use serde::{Deserialize, Serialize};
+
+/// Commands that are blocked for accounts that did not configure a policy:
+/// EEPROM writes/resets and firmware flashing
+pub const DEFAULT_DENIED_COMMANDS: [&str; 3] = ["M500", "M502", "M997"];
+
+#[derive(Iden)]
+pub enum CommandPolicy {
+    Table,
+    UserUuid,
+    Mode,
+    Commands,
+    UpdatedAt,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct CommandPolicyDbModel {
+    pub user_uuid: String,
+    pub mode: String,
+    pub commands: String,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommandPolicyViewModel {
+    pub mode: CommandPolicyMode,
+    pub commands: Vec<String>,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CommandPolicyUpdateRequest {
+    pub mode: CommandPolicyMode,
+    pub commands: Vec<String>,
+}
+
+/// - Allow: Only the listed commands may be sent to printers
+/// - Deny: All commands except the listed ones may be sent to printers
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandPolicyMode {
+    Allow,
+    Deny,
+}
+
+impl Display for CommandPolicyMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandPolicyMode::Allow => write!(f, "allow"),
+            CommandPolicyMode::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+impl CommandPolicyDbModel {
+    /// Policy used for accounts without a stored policy
+    pub fn default_for(user_uuid: &str) -> Self {
+        CommandPolicyDbModel {
+            user_uuid: user_uuid.to_string(),
+            mode: CommandPolicyMode::Deny.to_string(),
+            commands: DEFAULT_DENIED_COMMANDS.join(","),
+            updated_at: "".to_string(),
+        }
+    }
+
+    pub fn mode(&self) -> CommandPolicyMode {
+        match self.mode.as_str() {
+            "allow" => CommandPolicyMode::Allow,
+            _ => CommandPolicyMode::Deny,
+        }
+    }
+
+    pub fn command_list(&self) -> Vec<String> {
+        self.commands
+            .split(',')
+            .filter(|command| !command.is_empty())
+            .map(|command| command.to_string())
+            .collect()
+    }
+
+    /// Checks if the G-code line may be sent to a printer
+    pub fn is_allowed(&self, line: &str) -> bool {
+        let command = match command_word(line) {
+            Some(command) => command,
+            None => return false,
+        };
+
+        let listed = self.command_list().contains(&command);
+        match self.mode() {
+            CommandPolicyMode::Allow => listed,
+            CommandPolicyMode::Deny => !listed,
+        }
+    }
+}
+
+impl ViewModel for CommandPolicyDbModel {
+    type Model = CommandPolicyViewModel;
+
+    fn to_viewmodel(&self) -> Self::Model {
+        CommandPolicyViewModel {
+            mode: self.mode(),
+            commands: self.command_list(),
+            updated_at: self.updated_at.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy() {
+        let policy = CommandPolicyDbModel::default_for("user");
+        assert!(policy.is_allowed("G28"));
+        assert!(policy.is_allowed("G1 X10 F3000"));
+        assert!(!policy.is_allowed("M500"));
+        assert!(!policy.is_allowed("m502"));
+        assert!(!policy.is_allowed("N1 M997*12"));
+    }
+
+    #[test]
+    fn test_allow_policy() {
+        let policy = CommandPolicyDbModel {
+            user_uuid: "user".to_string(),
+            mode: "allow".to_string(),
+            commands: "G28,G1".to_string(),
+            updated_at: "".to_string(),
+        };
+        assert!(policy.is_allowed("G28"));
+        assert!(policy.is_allowed("G01 X5"));
+        assert!(!policy.is_allowed("M104 S200"));
+    }
+
+    #[test]
+    fn test_invalid_commands_are_not_allowed() {
+        let policy = CommandPolicyDbModel::default_for("user");
+        assert!(!policy.is_allowed(""));
+        assert!(!policy.is_allowed("G28\nM500"));
+    }
+}
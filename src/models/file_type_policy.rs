@@ -0,0 +1,117 @@
+use crate::common::app_error::AppError;
+use crate::models::printfile::FileType;
+use crate::models::view_model::ViewModel;
+use axum::http::StatusCode;
+use sea_query::Iden;
+use serde::{Deserialize, Serialize};
+
+/// File types that can be uploaded by accounts that did not configure a policy
+pub const DEFAULT_ALLOWED_FILE_TYPES: [FileType; 6] = [
+    FileType::Gcode,
+    FileType::Bgcode,
+    FileType::Stl,
+    FileType::Obj,
+    FileType::Amf,
+    FileType::ThreeMf,
+];
+
+#[derive(Iden)]
+pub enum FileTypePolicy {
+    Table,
+    UserUuid,
+    FileTypes,
+    UpdatedAt,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct FileTypePolicyDbModel {
+    pub user_uuid: String,
+    pub file_types: String,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileTypePolicyViewModel {
+    pub file_types: Vec<FileType>,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FileTypePolicyUpdateRequest {
+    pub file_types: Vec<FileType>,
+}
+
+impl FileTypePolicyDbModel {
+    /// Policy used for accounts without a stored policy
+    pub fn default_for(user_uuid: &str) -> Self {
+        FileTypePolicyDbModel {
+            user_uuid: user_uuid.to_string(),
+            file_types: DEFAULT_ALLOWED_FILE_TYPES
+                .map(|file_type| file_type.to_string())
+                .join(","),
+            updated_at: "".to_string(),
+        }
+    }
+
+    pub fn file_type_list(&self) -> Vec<FileType> {
+        self.file_types
+            .split(',')
+            .filter_map(|file_type| file_type.parse().ok())
+            .collect()
+    }
+
+    /// Checks if files of the type may be uploaded
+    pub fn is_allowed(&self, file_type: FileType) -> bool {
+        self.file_type_list().contains(&file_type)
+    }
+
+    /// Rejects files of types that may not be uploaded
+    pub fn check(&self, file_type: FileType) -> Result<(), AppError> {
+        if !self.is_allowed(file_type) {
+            return Err(AppError::PrintFile {
+                message: format!("Files of type {} are not allowed", file_type),
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl ViewModel for FileTypePolicyDbModel {
+    type Model = FileTypePolicyViewModel;
+
+    fn to_viewmodel(&self) -> Self::Model {
+        FileTypePolicyViewModel {
+            file_types: self.file_type_list(),
+            updated_at: self.updated_at.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy() {
+        let policy = FileTypePolicyDbModel::default_for("user");
+        assert!(policy.is_allowed(FileType::Gcode));
+        assert!(policy.is_allowed(FileType::ThreeMf));
+        assert!(!policy.is_allowed(FileType::Unknown));
+    }
+
+    #[test]
+    fn test_stored_policy() {
+        let policy = FileTypePolicyDbModel {
+            user_uuid: "user".to_string(),
+            file_types: "Gcode,Bgcode".to_string(),
+            updated_at: "".to_string(),
+        };
+        assert!(policy.is_allowed(FileType::Bgcode));
+        assert!(!policy.is_allowed(FileType::Stl));
+        assert_eq!(
+            policy.to_viewmodel().file_types,
+            vec![FileType::Gcode, FileType::Bgcode]
+        );
+    }
+}
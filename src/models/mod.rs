@@ -5,3 +5,21 @@ pub mod printfile;
 pub mod view_model;
 
 pub mod agent;
+
+pub mod command_policy;
+
+pub mod printer;
+
+pub mod printer_status;
+
+pub mod print_job;
+
+pub mod print_queue;
+
+pub mod printfile_metadata;
+
+pub mod printfile_thumbnail;
+
+pub mod file_type_policy;
+
+pub mod storage_deletion;
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use axum::http::StatusCode;
+
+use crate::common::app_error::AppError;
+use crate::models::printer_status::{PrinterStatus, PrinterStatusReport};
+use crate::models::view_model::ViewModel;
+use sea_query::Iden;
+use serde::{Deserialize, Serialize};
+
+#[derive(Iden)]
+pub enum PrintJob {
+    Table,
+    Uuid,
+    UserUuid,
+    AgentUuid,
+    PrinterUuid,
+    PrintFileUuid,
+    State,
+    Progress,
+    CreatedAt,
+    StartedAt,
+    FinishedAt,
+    UpdatedAt,
+}
+
+/// A print job, the user_uuid is the user that started the job
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct PrintJobDbModel {
+    pub uuid: String,
+    pub user_uuid: String,
+    pub agent_uuid: String,
+    pub printer_uuid: String,
+    pub print_file_uuid: String,
+    pub state: String,
+    pub progress: f64,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub updated_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrintJobViewModel {
+    pub uuid: String,
+    pub agent_uuid: String,
+    pub printer_uuid: String,
+    pub print_file_uuid: String,
+    pub state: String,
+    pub progress: f64,
+    pub created_at: String,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+    pub updated_at: String,
+}
+
+/// Filters of the print job history, from/to are unix timestamps matched against created_at
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PrintJobFilter {
+    pub agent_uuid: Option<String>,
+    pub printer_uuid: Option<String>,
+    pub print_file_uuid: Option<String>,
+    pub state: Option<PrintJobState>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// Lifecycle of a print job
+/// - Queued -> Starting -> Printing <-> Paused, jobs are queued while the print queue hands
+///   them to the agent, jobs started by the user start right away
+/// - Cancelled, Failed and Completed are final
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrintJobState {
+    Queued,
+    Starting,
+    Printing,
+    Paused,
+    Cancelled,
+    Failed,
+    Completed,
+}
+
+impl PrintJobState {
+    /// States of jobs that occupy their printer
+    pub const ACTIVE: [PrintJobState; 4] = [
+        PrintJobState::Queued,
+        PrintJobState::Starting,
+        PrintJobState::Printing,
+        PrintJobState::Paused,
+    ];
+
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            PrintJobState::Cancelled | PrintJobState::Failed | PrintJobState::Completed
+        )
+    }
+
+    pub fn can_transition_to(&self, next: PrintJobState) -> bool {
+        use PrintJobState::*;
+
+        matches!(
+            (self, next),
+            (Queued, Starting)
+                | (Queued, Cancelled)
+                | (Starting, Printing)
+                | (Starting, Cancelled)
+                | (Starting, Failed)
+                | (Printing, Paused)
+                | (Printing, Cancelled)
+                | (Printing, Failed)
+                | (Printing, Completed)
+                | (Paused, Printing)
+                | (Paused, Cancelled)
+                | (Paused, Failed)
+        )
+    }
+
+    /// Derives the next state of the active job of a printer from a status report of its agent.
+    /// Returns None if the report doesn't change the state of the job. Only a printer that went
+    /// idle while printing ends the job, an unavailable printer may just have lost its connection
+    pub fn next_for_report(
+        &self,
+        print_file_uuid: &str,
+        progress: f64,
+        report: &PrinterStatusReport,
+    ) -> Option<PrintJobState> {
+        let reported_job = report
+            .job
+            .as_ref()
+            .filter(|job| job.print_file_uuid == print_file_uuid);
+
+        let next = match (report.status, reported_job) {
+            (PrinterStatus::Busy, Some(_)) if *self == PrintJobState::Starting => {
+                PrintJobState::Printing
+            }
+            (PrinterStatus::Busy, _) | (PrinterStatus::Unavailable, _) => return None,
+            // the agent may not have picked up the job yet, a paused job is resumed or cancelled
+            // by the user
+            (PrinterStatus::Available, _) if *self != PrintJobState::Printing => return None,
+            (PrinterStatus::Available, Some(job)) if job.progress >= 1.0 => {
+                PrintJobState::Completed
+            }
+            (PrinterStatus::Available, None) if progress >= 1.0 => PrintJobState::Completed,
+            (PrinterStatus::Available, _) => PrintJobState::Failed,
+        };
+
+        match self.can_transition_to(next) {
+            true => Some(next),
+            false => None,
+        }
+    }
+}
+
+impl Display for PrintJobState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+impl FromStr for PrintJobState {
+    type Err = String;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state {
+            "queued" => Ok(PrintJobState::Queued),
+            "starting" => Ok(PrintJobState::Starting),
+            "printing" => Ok(PrintJobState::Printing),
+            "paused" => Ok(PrintJobState::Paused),
+            "cancelled" => Ok(PrintJobState::Cancelled),
+            "failed" => Ok(PrintJobState::Failed),
+            "completed" => Ok(PrintJobState::Completed),
+            _ => Err(format!("unknown print job state: {}", state)),
+        }
+    }
+}
+
+impl PrintJobDbModel {
+    pub fn state(&self) -> PrintJobState {
+        PrintJobState::from_str(&self.state).expect("Invalid print job state in database")
+    }
+
+    /// Rejects transitions the state machine doesn't allow, e.g. resuming a completed job
+    pub fn check_transition(&self, next: PrintJobState) -> Result<(), AppError> {
+        let state = self.state();
+        if !state.can_transition_to(next) {
+            return Err(AppError::PrintJob {
+                message: format!("Cannot change print job from {} to {}", state, next),
+                status: StatusCode::CONFLICT,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl ViewModel for PrintJobDbModel {
+    type Model = PrintJobViewModel;
+
+    fn to_viewmodel(&self) -> Self::Model {
+        PrintJobViewModel {
+            uuid: self.uuid.to_string(),
+            agent_uuid: self.agent_uuid.to_string(),
+            printer_uuid: self.printer_uuid.to_string(),
+            print_file_uuid: self.print_file_uuid.to_string(),
+            state: self.state.to_string(),
+            progress: self.progress,
+            created_at: self.created_at.to_string(),
+            started_at: self.started_at.clone(),
+            finished_at: self.finished_at.clone(),
+            updated_at: self.updated_at.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::printer_status::PrinterJob;
+
+    fn report(status: PrinterStatus, progress: Option<f64>) -> PrinterStatusReport {
+        PrinterStatusReport {
+            agent_uuid: "agent".to_string(),
+            printer_uuid: "printer".to_string(),
+            printer_identifier: "Demo".to_string(),
+            printer_adapter_identifier: None,
+            printer_adapter_interface: None,
+            status,
+            job: progress.map(|progress| PrinterJob {
+                print_file_uuid: "file".to_string(),
+                name: "File.gcode".to_string(),
+                size: 1024,
+                progress,
+            }),
+            state: None,
+        }
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        for state in [
+            PrintJobState::Queued,
+            PrintJobState::Starting,
+            PrintJobState::Printing,
+            PrintJobState::Paused,
+            PrintJobState::Cancelled,
+            PrintJobState::Failed,
+            PrintJobState::Completed,
+        ] {
+            assert_eq!(PrintJobState::from_str(&state.to_string()), Ok(state));
+        }
+    }
+
+    #[test]
+    fn test_transitions() {
+        use PrintJobState::*;
+
+        assert!(Queued.can_transition_to(Starting));
+        assert!(Starting.can_transition_to(Printing));
+        assert!(Printing.can_transition_to(Paused));
+        assert!(Paused.can_transition_to(Printing));
+        assert!(!Queued.can_transition_to(Completed));
+        assert!(!Starting.can_transition_to(Completed));
+        assert!(!Starting.can_transition_to(Paused));
+        assert!(!Completed.can_transition_to(Printing));
+        assert!(!Cancelled.can_transition_to(Cancelled));
+    }
+
+    #[test]
+    fn test_next_for_report() {
+        use PrintJobState::*;
+
+        let busy = report(PrinterStatus::Busy, Some(0.1));
+        assert_eq!(Starting.next_for_report("file", 0.0, &busy), Some(Printing));
+        // the agent doesn't know queued jobs yet
+        assert_eq!(Queued.next_for_report("file", 0.0, &busy), None);
+        assert_eq!(Printing.next_for_report("file", 0.1, &busy), None);
+
+        let finished = report(PrinterStatus::Available, Some(1.0));
+        assert_eq!(
+            Printing.next_for_report("file", 0.9, &finished),
+            Some(Completed)
+        );
+
+        let idle = report(PrinterStatus::Available, None);
+        assert_eq!(Starting.next_for_report("file", 0.0, &idle), None);
+        assert_eq!(
+            Printing.next_for_report("file", 1.0, &idle),
+            Some(Completed)
+        );
+        assert_eq!(Printing.next_for_report("file", 0.4, &idle), Some(Failed));
+
+        assert_eq!(Paused.next_for_report("file", 0.4, &idle), None);
+
+        // the connection to the printer may come back
+        let unavailable = report(PrinterStatus::Unavailable, None);
+        assert_eq!(Paused.next_for_report("file", 0.4, &unavailable), None);
+        assert_eq!(Printing.next_for_report("file", 0.4, &unavailable), None);
+        assert_eq!(Starting.next_for_report("file", 0.0, &unavailable), None);
+    }
+}
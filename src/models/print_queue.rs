@@ -0,0 +1,76 @@
+use crate::models::view_model::ViewModel;
+use sea_query::Iden;
+use serde::{Deserialize, Serialize};
+
+#[derive(Iden)]
+pub enum PrintQueue {
+    Table,
+    Uuid,
+    UserUuid,
+    PrintFileUuid,
+    PrinterUuids,
+    Position,
+    CreatedAt,
+}
+
+/// A queued print file, printer_uuids is a comma separated list of the printers that may print it
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct PrintQueueDbModel {
+    pub uuid: String,
+    pub user_uuid: String,
+    pub print_file_uuid: String,
+    pub printer_uuids: String,
+    pub position: i32,
+    pub created_at: String,
+}
+
+/// Request to queue a print file, the first of the printers that becomes available prints it
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrintQueueRequest {
+    pub print_file_uuid: String,
+    pub printer_uuids: Vec<String>,
+}
+
+/// Request to move a queued print file, position 0 is the front of the queue
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrintQueuePositionRequest {
+    pub position: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PrintQueueFilter {
+    pub printer_uuid: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrintQueueViewModel {
+    pub uuid: String,
+    pub print_file_uuid: String,
+    pub printer_uuids: Vec<String>,
+    pub position: i32,
+    pub created_at: String,
+}
+
+impl PrintQueueDbModel {
+    pub fn printer_uuids(&self) -> Vec<String> {
+        self.printer_uuids
+            .split(',')
+            .filter(|uuid| !uuid.is_empty())
+            .map(|uuid| uuid.to_string())
+            .collect()
+    }
+}
+
+impl ViewModel for PrintQueueDbModel {
+    type Model = PrintQueueViewModel;
+
+    fn to_viewmodel(&self) -> Self::Model {
+        PrintQueueViewModel {
+            uuid: self.uuid.to_string(),
+            print_file_uuid: self.print_file_uuid.to_string(),
+            printer_uuids: self.printer_uuids(),
+            position: self.position,
+            created_at: self.created_at.to_string(),
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::models::view_model::ViewModel;
+use sea_query::Iden;
+use serde::{Deserialize, Serialize};
+
+#[derive(Iden)]
+pub enum Printer {
+    Table,
+    Uuid,
+    UserUuid,
+    AgentUuid,
+    Identifier,
+    AdapterIdentifier,
+    AdapterInterface,
+    BedCleared,
+    CreatedAt,
+}
+
+#[derive(sqlx::FromRow, Debug, Clone)]
+pub struct PrinterDbModel {
+    pub uuid: String,
+    pub user_uuid: String,
+    pub agent_uuid: String,
+    pub identifier: String,
+    pub adapter_identifier: String,
+    pub adapter_interface: String,
+    pub bed_cleared: bool,
+    pub created_at: String,
+}
+
+/// Request to add or change a printer
+/// - identifier: Name of the printer, e.g. "Prusa MK4"
+/// - adapter_identifier: How the agent talks to the printer, e.g. "SERIAL"
+/// - adapter_interface: Where the agent finds the printer, e.g. "/dev/ttyUSB0"
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrinterRequest {
+    pub identifier: String,
+    pub adapter_identifier: String,
+    pub adapter_interface: String,
+}
+
+/// The bed_cleared flag is reset when a print finishes, queued jobs only start on a cleared bed
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PrinterViewModel {
+    pub uuid: String,
+    pub agent_uuid: String,
+    pub identifier: String,
+    pub adapter_identifier: String,
+    pub adapter_interface: String,
+    pub bed_cleared: bool,
+    pub created_at: String,
+}
+
+impl ViewModel for PrinterDbModel {
+    type Model = PrinterViewModel;
+
+    fn to_viewmodel(&self) -> Self::Model {
+        PrinterViewModel {
+            uuid: self.uuid.to_string(),
+            agent_uuid: self.agent_uuid.to_string(),
+            identifier: self.identifier.to_string(),
+            adapter_identifier: self.adapter_identifier.to_string(),
+            adapter_interface: self.adapter_interface.to_string(),
+            bed_cleared: self.bed_cleared,
+            created_at: self.created_at.to_string(),
+        }
+    }
+}
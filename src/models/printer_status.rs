@@ -0,0 +1,179 @@
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::common::app_error::AppError;
+
+/// Temperatures outside of this range are rejected as invalid readings
+const TEMPERATURE_RANGE: std::ops::RangeInclusive<f64> = -50.0..=500.0;
+
+/// Status of a printer as reported by its agent, see the printer_status websocket message
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrinterStatusReport {
+    pub agent_uuid: String,
+    pub printer_uuid: String,
+    pub printer_identifier: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub printer_adapter_identifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub printer_adapter_interface: Option<String>,
+    pub status: PrinterStatus,
+    #[serde(default)]
+    pub job: Option<PrinterJob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<PrinterState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PrinterStatus {
+    Available,
+    Unavailable,
+    Busy,
+}
+
+/// The job the printer is working on, progress ranges from 0.0 to 1.0
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrinterJob {
+    pub print_file_uuid: String,
+    pub name: String,
+    pub size: u64,
+    pub progress: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PrinterState {
+    pub temperature: PrinterTemperature,
+}
+
+/// Temperatures in degrees Celsius, tools the printer doesn't have are omitted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PrinterTemperature {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bed: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool0: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool1: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool2: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool3: Option<f64>,
+}
+
+/// Latest status of a printer kept by the backend
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PrinterStatusViewModel {
+    #[serde(flatten)]
+    pub report: PrinterStatusReport,
+    pub updated_at: String,
+}
+
+impl PrinterStatusReport {
+    /// Validates the values of the report, the ownership of the printer is checked by the caller
+    pub fn validate(&self) -> Result<(), AppError> {
+        let mut messages: Vec<String> = Vec::new();
+
+        if self.printer_uuid.is_empty() {
+            messages.push("Printer uuid cannot be empty".to_string());
+        }
+
+        if let Some(job) = &self.job {
+            if !(0.0..=1.0).contains(&job.progress) {
+                messages.push(format!(
+                    "Job progress {} is not between 0 and 1",
+                    job.progress
+                ));
+            }
+        }
+
+        if let Some(state) = &self.state {
+            let temperature = &state.temperature;
+            let readings = [
+                ("bed", temperature.bed),
+                ("tool0", temperature.tool0),
+                ("tool1", temperature.tool1),
+                ("tool2", temperature.tool2),
+                ("tool3", temperature.tool3),
+            ];
+            for (name, reading) in readings {
+                if let Some(reading) = reading {
+                    if !TEMPERATURE_RANGE.contains(&reading) {
+                        messages.push(format!(
+                            "Temperature {} of {} is out of range",
+                            reading, name
+                        ));
+                    }
+                }
+            }
+        }
+
+        if !messages.is_empty() {
+            return Err(AppError::Validation {
+                messages: messages.join(", "),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUSY_REPORT: &str = r#"{
+        "agent_uuid": "agent",
+        "printer_uuid": "printer",
+        "printer_identifier": "Demo",
+        "printer_adapter_identifier": "SERIAL",
+        "printer_adapter_interface": "/dev/ttyUSB0",
+        "status": "busy",
+        "job": {
+            "print_file_uuid": "file",
+            "name": "File.gcode",
+            "size": 4106612,
+            "progress": 0.5
+        },
+        "state": {
+            "temperature": { "bed": 60, "tool0": 215.5 }
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_busy_report() {
+        let report: PrinterStatusReport = serde_json::from_str(BUSY_REPORT).unwrap();
+        assert_eq!(report.status, PrinterStatus::Busy);
+        assert_eq!(report.job.as_ref().unwrap().progress, 0.5);
+        assert_eq!(report.state.as_ref().unwrap().temperature.bed, Some(60.0));
+        assert!(report.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse_unavailable_report() {
+        let body = r#"{"agent_uuid":"agent","printer_uuid":"printer","printer_identifier":"Demo","status":"unavailable"}"#;
+        let report: PrinterStatusReport = serde_json::from_str(body).unwrap();
+        assert_eq!(report.status, PrinterStatus::Unavailable);
+        assert!(report.job.is_none());
+        assert!(report.validate().is_ok());
+    }
+
+    #[test]
+    fn test_unknown_status_is_rejected() {
+        let body = r#"{"agent_uuid":"agent","printer_uuid":"printer","printer_identifier":"Demo","status":"exploded"}"#;
+        assert!(serde_json::from_str::<PrinterStatusReport>(body).is_err());
+    }
+
+    #[test]
+    fn test_validate_out_of_range_values() {
+        let mut report: PrinterStatusReport = serde_json::from_str(BUSY_REPORT).unwrap();
+        report.job.as_mut().unwrap().progress = 1.5;
+        report.state.as_mut().unwrap().temperature.tool1 = Some(900.0);
+
+        let err = report.validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Job progress 1.5 is not between 0 and 1, Temperature 900 of tool1 is out of range"
+        );
+    }
+}
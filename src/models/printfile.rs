@@ -1,6 +1,10 @@
 use std::fmt;
 use std::fmt::Display;
+use std::str::FromStr;
 
+use crate::common::gcode::command_word;
+use crate::common::mesh::{is_binary_stl, STL_HEADER_SIZE, STL_TRIANGLE_SIZE, THREEMF_MODEL_PATH};
+use crate::models::printfile_metadata::PrintFileMetadataViewModel;
 use crate::models::view_model::ViewModel;
 use sea_query::Iden;
 use serde::{Deserialize, Serialize};
@@ -25,7 +29,7 @@ pub struct PrintFileDbModel {
     pub user_uuid: String,
     pub name: String,
     pub path: String,
-    pub size: i32,
+    pub size: i64,
     pub checksum: String,
     pub file_type: String,
     pub file_storage_type: String,
@@ -36,22 +40,29 @@ pub struct PrintFileDbModel {
 pub struct PrintFileViewModel {
     pub uuid: String,
     pub name: String,
-    pub size: i32,
+    pub size: i64,
     pub checksum: String,
     pub file_type: String,
     pub file_storage_type: String,
     pub created_at: String,
+    /// Slicer metadata, only available for G-code files
+    pub metadata: Option<PrintFileMetadataViewModel>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// - Bgcode: Binary G-code of PrusaSlicer
+/// - ThreeMf: 3D Manufacturing Format, a zip of XML models
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum FileType {
     Gcode,
+    Bgcode,
     Stl,
     Obj,
     Amf,
+    ThreeMf,
     Unknown,
 }
 
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug)]
 pub enum FileStorageType {
     Local,
@@ -70,6 +81,198 @@ impl Display for FileType {
     }
 }
 
+impl FromStr for FileType {
+    type Err = ();
+
+    fn from_str(file_type: &str) -> Result<Self, Self::Err> {
+        match file_type {
+            "Gcode" => Ok(FileType::Gcode),
+            "Bgcode" => Ok(FileType::Bgcode),
+            "Stl" => Ok(FileType::Stl),
+            "Obj" => Ok(FileType::Obj),
+            "Amf" => Ok(FileType::Amf),
+            "ThreeMf" => Ok(FileType::ThreeMf),
+            "Unknown" => Ok(FileType::Unknown),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FileType {
+    /// Media type used when the file is downloaded
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            FileType::Gcode => "text/x-gcode",
+            FileType::Stl => "model/stl",
+            FileType::Obj => "model/obj",
+            FileType::Amf => "application/x-amf",
+            FileType::ThreeMf => "model/3mf",
+            FileType::Bgcode | FileType::Unknown => "application/octet-stream",
+        }
+    }
+}
+
+/// Largest upload, G-code of large prints runs to hundreds of MB and the limit leaves headroom
+/// above that. Uploads are streamed to the file storage, so the limit doesn't bound memory
+pub const MAX_UPLOAD_SIZE: usize = 1024 * 1024 * 1024;
+
+const ZIP_SIGNATURE: &[u8] = b"PK\x03\x04";
+const BGCODE_SIGNATURE: &[u8] = b"GCDE";
+/// Number of bytes looked at to detect text formats
+const SNIFF_SIZE: usize = 64 * 1024;
+
+/// Detects the type of a file that arrives in chunks, only the start of the file is kept.
+/// The extension only decides between formats that can't be told apart by their content
+/// (e.g. a zipped AMF)
+#[derive(Default)]
+pub struct FileSniffer {
+    head: Vec<u8>,
+    size: usize,
+    threemf_model: bool,
+    /// End of the previous chunk, the 3MF model path can span two chunks
+    tail: Vec<u8>,
+}
+
+impl FileSniffer {
+    pub fn new() -> Self {
+        FileSniffer::default()
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) {
+        let missing = SNIFF_SIZE.saturating_sub(self.head.len());
+        self.head
+            .extend_from_slice(&chunk[..chunk.len().min(missing)]);
+        self.size += chunk.len();
+
+        // zip entries store their names uncompressed
+        if !self.threemf_model && self.head.starts_with(ZIP_SIGNATURE) {
+            let needle = THREEMF_MODEL_PATH.as_bytes();
+            self.tail.extend_from_slice(chunk);
+            self.threemf_model = contains(&self.tail, needle);
+            let keep = self.tail.len().min(needle.len() - 1);
+            self.tail.drain(..self.tail.len() - keep);
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The type of the file once more data can't change it. None while the start of the file
+    /// is incomplete, a zip archive may still turn out to be a 3MF package or the file may still
+    /// end at the size its header announces for a binary STL. Files don't grow past `max_size`
+    pub fn settled_file_type(&self, filename: &str, max_size: usize) -> Option<FileType> {
+        let data = &self.head;
+        if data.len() < SNIFF_SIZE {
+            return None;
+        }
+        if data.starts_with(ZIP_SIGNATURE) {
+            return self.threemf_model.then_some(FileType::ThreeMf);
+        }
+        let triangles = u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize;
+        let stl_size = STL_HEADER_SIZE + triangles * STL_TRIANGLE_SIZE;
+        if !data.starts_with(BGCODE_SIGNATURE) && self.size <= stl_size && stl_size <= max_size {
+            return None;
+        }
+        Some(self.file_type(filename))
+    }
+
+    pub fn file_type(&self, filename: &str) -> FileType {
+        let data = &self.head;
+        let extension = filename
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase())
+            .unwrap_or_default();
+
+        if data.starts_with(BGCODE_SIGNATURE) {
+            return FileType::Bgcode;
+        }
+        if data.starts_with(ZIP_SIGNATURE) {
+            return if self.threemf_model {
+                FileType::ThreeMf
+            } else if extension == "amf" {
+                FileType::Amf
+            } else {
+                FileType::Unknown
+            };
+        }
+        if is_binary_stl(data, self.size) {
+            return FileType::Stl;
+        }
+
+        let text = match std::str::from_utf8(data) {
+            Ok(text) => text,
+            // the sniffed prefix may end in the middle of a character
+            Err(e) if e.error_len().is_none() => {
+                std::str::from_utf8(&data[..e.valid_up_to()]).unwrap_or_default()
+            }
+            Err(_) => return FileType::Unknown,
+        };
+        let trimmed = text.trim_start();
+
+        if trimmed.starts_with("solid") && text.contains("facet") {
+            FileType::Stl
+        } else if (trimmed.starts_with("<?xml") || trimmed.starts_with("<amf"))
+            && text.contains("<amf")
+        {
+            FileType::Amf
+        } else if is_obj(text) {
+            FileType::Obj
+        } else if is_gcode(text) {
+            FileType::Gcode
+        } else {
+            FileType::Unknown
+        }
+    }
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+fn content_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with(';'))
+}
+
+fn is_obj(text: &str) -> bool {
+    let mut vertices = false;
+    for line in content_lines(text) {
+        match line.split_whitespace().next() {
+            Some("v") => vertices = true,
+            Some("f") if vertices => return true,
+            Some("vt" | "vn" | "vp" | "o" | "g" | "s" | "usemtl" | "mtllib" | "l") => {}
+            _ => return false,
+        }
+    }
+    // large files may only contain vertices in the sniffed part
+    vertices
+}
+
+/// Most lines must be G-code commands, the rest can be firmware macros like PRINT_START
+fn is_gcode(text: &str) -> bool {
+    let (mut lines, mut commands) = (0, 0);
+    for line in content_lines(text).take(100) {
+        lines += 1;
+        if command_word(line).is_some() {
+            commands += 1;
+        }
+    }
+    commands > 0 && commands * 5 >= lines * 4
+}
+
+/// Name the print file is stored under in the files of the user, the uuid of the print file
+/// keeps uploads with the same name apart. Only the last path component of the name is used,
+/// None if that can't be stored safely, e.g. ".." or a name containing NUL
+pub fn storage_filename(print_file_uuid: &str, filename: &str) -> Option<String> {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    if name.is_empty() || name == "." || name == ".." || name.contains('\0') {
+        return None;
+    }
+    Some(format!("{}/{}", print_file_uuid, name))
+}
+
 impl ViewModel for PrintFileDbModel {
     type Model = PrintFileViewModel;
 
@@ -82,6 +285,141 @@ impl ViewModel for PrintFileDbModel {
             file_type: self.file_type.to_string(),
             file_storage_type: self.file_storage_type.to_string(),
             created_at: self.created_at.to_string(),
+            metadata: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::mesh::{STL_HEADER_SIZE, STL_TRIANGLE_SIZE};
+
+    fn detect(filename: &str, data: &[u8]) -> FileType {
+        let mut sniffer = FileSniffer::new();
+        sniffer.feed(data);
+        sniffer.file_type(filename)
+    }
+
+    fn binary_stl(triangles: u32) -> Vec<u8> {
+        let mut data = b"solid binary header".to_vec();
+        data.resize(80, 0);
+        data.extend_from_slice(&triangles.to_le_bytes());
+        data.resize(STL_HEADER_SIZE + triangles as usize * STL_TRIANGLE_SIZE, 0);
+        data
+    }
+
+    #[test]
+    fn test_detect_by_content() {
+        let gcode =
+            b"; generated by PrusaSlicer\nG28 ; home\nM104 S215\nPRINT_START\nG1 X1 E1\nG1 X2 E2\n";
+        let ascii_stl = b"solid cube\n  facet normal 0 0 1\n    outer loop\n";
+        let obj = b"# cube\no Cube\nv 0 0 0\nv 1 0 0\nv 1 1 0\nvn 0 0 1\nf 1 2 3\n";
+        let amf = b"<?xml version=\"1.0\"?>\n<amf unit=\"millimeter\">\n</amf>\n";
+        let threemf = b"PK\x03\x04\x14\x00\x00\x00[Content_Types].xmlPK\x03\x04 3D/3dmodel.model";
+
+        assert_eq!(detect("part.gcode", gcode), FileType::Gcode);
+        assert_eq!(detect("part.bgcode", b"GCDE\x01\x00"), FileType::Bgcode);
+        assert_eq!(detect("part.stl", ascii_stl), FileType::Stl);
+        assert_eq!(detect("part.stl", &binary_stl(2)), FileType::Stl);
+        assert_eq!(detect("part.obj", obj), FileType::Obj);
+        assert_eq!(detect("part.amf", amf), FileType::Amf);
+        assert_eq!(detect("part.3mf", threemf), FileType::ThreeMf);
+        assert_eq!(detect("part.amf", b"PK\x03\x04part.amf"), FileType::Amf);
+    }
+
+    #[test]
+    fn test_detect_ignores_wrong_extension() {
+        let ascii_stl = b"solid cube\n  facet normal 0 0 1\n";
+
+        assert_eq!(detect("part.gcode", ascii_stl), FileType::Stl);
+        assert_eq!(detect("part.stl", &binary_stl(2)[..100]), FileType::Unknown);
+        assert_eq!(detect("notes.gcode", b"hello world\n"), FileType::Unknown);
+        assert_eq!(
+            detect("image.gcode", &[0xff, 0xd8, 0xff, 0xe0]),
+            FileType::Unknown
+        );
+        assert_eq!(detect("archive.zip", b"PK\x03\x04data"), FileType::Unknown);
+        assert_eq!(detect("empty.gcode", b""), FileType::Unknown);
+    }
+
+    #[test]
+    fn test_settled_file_type() {
+        let max_size = MAX_UPLOAD_SIZE;
+        let mut gcode = b"; generated by PrusaSlicer\nG28\nG1 X1 E1\n".repeat(2000);
+        let mut sniffer = FileSniffer::new();
+        sniffer.feed(&gcode[..1000]);
+        assert_eq!(sniffer.settled_file_type("part.gcode", max_size), None);
+        sniffer.feed(&gcode[1000..]);
+        assert_eq!(
+            sniffer.settled_file_type("part.gcode", max_size),
+            Some(FileType::Gcode)
+        );
+
+        // the file may still end at the size announced by the triangle count of a binary STL
+        gcode[80..84].copy_from_slice(&4112u32.to_le_bytes());
+        let mut sniffer = FileSniffer::new();
+        sniffer.feed(&gcode);
+        assert_eq!(sniffer.settled_file_type("part.gcode", max_size), None);
+        assert_eq!(
+            sniffer.settled_file_type("part.gcode", gcode.len()),
+            Some(FileType::Gcode)
+        );
+        sniffer.feed(&gcode);
+        sniffer.feed(&gcode);
+        assert_eq!(
+            sniffer.settled_file_type("part.gcode", max_size),
+            Some(FileType::Gcode)
+        );
+
+        let mut zip = b"PK\x03\x04".to_vec();
+        zip.resize(SNIFF_SIZE, 0);
+        let mut sniffer = FileSniffer::new();
+        sniffer.feed(&zip);
+        assert_eq!(sniffer.settled_file_type("part.3mf", max_size), None);
+        sniffer.feed(THREEMF_MODEL_PATH.as_bytes());
+        assert_eq!(
+            sniffer.settled_file_type("part.3mf", max_size),
+            Some(FileType::ThreeMf)
+        );
+    }
+
+    #[test]
+    fn test_storage_filename() {
+        assert_eq!(
+            storage_filename("file", "part.gcode"),
+            Some("file/part.gcode".to_string())
+        );
+        assert_eq!(
+            storage_filename("file", "../../../../etc/cron.d/x"),
+            Some("file/x".to_string())
+        );
+        assert_eq!(
+            storage_filename("file", "..\\..\\part.gcode"),
+            Some("file/part.gcode".to_string())
+        );
+        assert_eq!(storage_filename("file", "parts/.."), None);
+        assert_eq!(storage_filename("file", "parts/"), None);
+        assert_eq!(storage_filename("file", "part\0.gcode"), None);
+    }
+
+    #[test]
+    fn test_sniffer_chunks() {
+        // the model path of the 3MF package spans two chunks and lies past the sniffed part
+        let mut threemf = b"PK\x03\x04".to_vec();
+        threemf.resize(SNIFF_SIZE + 100, 0);
+        threemf.extend_from_slice(b"3D/3dmodel.model");
+        let mut sniffer = FileSniffer::new();
+        for chunk in threemf.chunks(SNIFF_SIZE + 105) {
+            sniffer.feed(chunk);
+        }
+        assert_eq!(sniffer.file_type("part.3mf"), FileType::ThreeMf);
+
+        let stl = binary_stl(2000);
+        let mut sniffer = FileSniffer::new();
+        for chunk in stl.chunks(1000) {
+            sniffer.feed(chunk);
         }
+        assert_eq!(sniffer.file_type("part.stl"), FileType::Stl);
     }
 }
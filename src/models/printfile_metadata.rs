@@ -0,0 +1,249 @@
+use sea_query::Iden;
+use serde::{Deserialize, Serialize};
+
+use crate::common::gcode::{BoundingBox, GcodeMetadata};
+use crate::common::mesh::MeshAnalysis;
+use crate::models::view_model::ViewModel;
+
+#[derive(Iden)]
+pub enum PrintFileMetadata {
+    Table,
+    PrintFileUuid,
+    Slicer,
+    SlicerVersion,
+    EstimatedTime,
+    FilamentLength,
+    FilamentWeight,
+    LayerHeight,
+    LayerCount,
+    NozzleTemperature,
+    BedTemperature,
+    MinX,
+    MinY,
+    MinZ,
+    MaxX,
+    MaxY,
+    MaxZ,
+    TriangleCount,
+    Volume,
+    SurfaceArea,
+    Watertight,
+}
+
+/// Slicer metadata of a G-code file or mesh analysis of a model file,
+/// filament_length and filament_weight hold one comma separated value per extruder
+#[derive(sqlx::FromRow, Debug)]
+pub struct PrintFileMetadataDbModel {
+    pub print_file_uuid: String,
+    pub slicer: Option<String>,
+    pub slicer_version: Option<String>,
+    pub estimated_time: Option<i64>,
+    pub filament_length: String,
+    pub filament_weight: String,
+    pub layer_height: Option<f64>,
+    pub layer_count: Option<i32>,
+    pub nozzle_temperature: Option<f64>,
+    pub bed_temperature: Option<f64>,
+    pub min_x: Option<f64>,
+    pub min_y: Option<f64>,
+    pub min_z: Option<f64>,
+    pub max_x: Option<f64>,
+    pub max_y: Option<f64>,
+    pub max_z: Option<f64>,
+    pub triangle_count: Option<i64>,
+    pub volume: Option<f64>,
+    pub surface_area: Option<f64>,
+    pub watertight: Option<bool>,
+}
+
+/// - estimated_time: Estimated print time in seconds
+/// - filament_length, filament_weight: Used filament per extruder, in mm and g
+/// - bounding_box: Bounding box of the printed object in mm
+/// - volume, surface_area: Volume in mm³ and surface area in mm² of a model
+/// - watertight: The model is closed and consistently oriented, its volume can be trusted
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PrintFileMetadataViewModel {
+    pub slicer: Option<String>,
+    pub slicer_version: Option<String>,
+    pub estimated_time: Option<i64>,
+    pub filament_length: Vec<f64>,
+    pub filament_weight: Vec<f64>,
+    pub layer_height: Option<f64>,
+    pub layer_count: Option<i32>,
+    pub nozzle_temperature: Option<f64>,
+    pub bed_temperature: Option<f64>,
+    pub bounding_box: Option<BoundingBox>,
+    pub triangle_count: Option<i64>,
+    pub volume: Option<f64>,
+    pub surface_area: Option<f64>,
+    pub watertight: Option<bool>,
+}
+
+impl PrintFileMetadataDbModel {
+    fn empty(print_file_uuid: &str, bounding_box: Option<BoundingBox>) -> Self {
+        PrintFileMetadataDbModel {
+            print_file_uuid: print_file_uuid.to_string(),
+            slicer: None,
+            slicer_version: None,
+            estimated_time: None,
+            filament_length: "".to_string(),
+            filament_weight: "".to_string(),
+            layer_height: None,
+            layer_count: None,
+            nozzle_temperature: None,
+            bed_temperature: None,
+            min_x: bounding_box.map(|bounding_box| bounding_box.min_x),
+            min_y: bounding_box.map(|bounding_box| bounding_box.min_y),
+            min_z: bounding_box.map(|bounding_box| bounding_box.min_z),
+            max_x: bounding_box.map(|bounding_box| bounding_box.max_x),
+            max_y: bounding_box.map(|bounding_box| bounding_box.max_y),
+            max_z: bounding_box.map(|bounding_box| bounding_box.max_z),
+            triangle_count: None,
+            volume: None,
+            surface_area: None,
+            watertight: None,
+        }
+    }
+
+    pub fn from_gcode(print_file_uuid: &str, metadata: GcodeMetadata) -> Self {
+        PrintFileMetadataDbModel {
+            slicer: metadata.slicer,
+            slicer_version: metadata.slicer_version,
+            estimated_time: metadata.estimated_time.map(|time| time as i64),
+            filament_length: join_values(&metadata.filament_length),
+            filament_weight: join_values(&metadata.filament_weight),
+            layer_height: metadata.layer_height,
+            layer_count: metadata.layer_count.map(|count| count as i32),
+            nozzle_temperature: metadata.nozzle_temperature,
+            bed_temperature: metadata.bed_temperature,
+            ..PrintFileMetadataDbModel::empty(print_file_uuid, metadata.bounding_box)
+        }
+    }
+
+    pub fn from_mesh(print_file_uuid: &str, analysis: MeshAnalysis) -> Self {
+        PrintFileMetadataDbModel {
+            triangle_count: Some(analysis.triangle_count as i64),
+            volume: Some(analysis.volume),
+            surface_area: Some(analysis.surface_area),
+            watertight: Some(analysis.watertight),
+            ..PrintFileMetadataDbModel::empty(print_file_uuid, analysis.bounding_box)
+        }
+    }
+}
+
+fn join_values(values: &[f64]) -> String {
+    values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+fn split_values(values: &str) -> Vec<f64> {
+    values
+        .split(',')
+        .filter_map(|value| value.parse().ok())
+        .collect()
+}
+
+impl ViewModel for PrintFileMetadataDbModel {
+    type Model = PrintFileMetadataViewModel;
+
+    fn to_viewmodel(&self) -> Self::Model {
+        let bounding_box = match (
+            self.min_x, self.min_y, self.min_z, self.max_x, self.max_y, self.max_z,
+        ) {
+            (Some(min_x), Some(min_y), Some(min_z), Some(max_x), Some(max_y), Some(max_z)) => {
+                Some(BoundingBox {
+                    min_x,
+                    min_y,
+                    min_z,
+                    max_x,
+                    max_y,
+                    max_z,
+                })
+            }
+            _ => None,
+        };
+
+        PrintFileMetadataViewModel {
+            slicer: self.slicer.clone(),
+            slicer_version: self.slicer_version.clone(),
+            estimated_time: self.estimated_time,
+            filament_length: split_values(&self.filament_length),
+            filament_weight: split_values(&self.filament_weight),
+            layer_height: self.layer_height,
+            layer_count: self.layer_count,
+            nozzle_temperature: self.nozzle_temperature,
+            bed_temperature: self.bed_temperature,
+            bounding_box,
+            triangle_count: self.triangle_count,
+            volume: self.volume,
+            surface_area: self.surface_area,
+            watertight: self.watertight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let metadata = GcodeMetadata {
+            slicer: Some("PrusaSlicer".to_string()),
+            slicer_version: Some("2.6.0".to_string()),
+            estimated_time: Some(3723),
+            filament_length: vec![1234.56, 78.9],
+            filament_weight: vec![3.7, 0.24],
+            layer_height: Some(0.2),
+            layer_count: Some(42),
+            nozzle_temperature: Some(215.0),
+            bed_temperature: Some(60.0),
+            bounding_box: Some(BoundingBox {
+                min_x: 10.0,
+                min_y: 10.0,
+                min_z: 0.2,
+                max_x: 20.0,
+                max_y: 30.0,
+                max_z: 8.4,
+            }),
+        };
+
+        let viewmodel =
+            PrintFileMetadataDbModel::from_gcode("uuid", metadata.clone()).to_viewmodel();
+        assert_eq!(viewmodel.filament_length, metadata.filament_length);
+        assert_eq!(viewmodel.filament_weight, metadata.filament_weight);
+        assert_eq!(viewmodel.bounding_box, metadata.bounding_box);
+        assert_eq!(viewmodel.estimated_time, Some(3723));
+
+        let empty = PrintFileMetadataDbModel::from_gcode("uuid", GcodeMetadata::default());
+        assert_eq!(empty.to_viewmodel().filament_length, Vec::<f64>::new());
+        assert_eq!(empty.to_viewmodel().bounding_box, None);
+    }
+
+    #[test]
+    fn test_mesh_metadata() {
+        let analysis = MeshAnalysis {
+            triangle_count: 12,
+            bounding_box: Some(BoundingBox {
+                max_x: 10.0,
+                max_y: 10.0,
+                max_z: 10.0,
+                ..BoundingBox::default()
+            }),
+            volume: 1000.0,
+            surface_area: 600.0,
+            watertight: true,
+        };
+
+        let viewmodel =
+            PrintFileMetadataDbModel::from_mesh("uuid", analysis.clone()).to_viewmodel();
+        assert_eq!(viewmodel.triangle_count, Some(12));
+        assert_eq!(viewmodel.volume, Some(1000.0));
+        assert_eq!(viewmodel.watertight, Some(true));
+        assert_eq!(viewmodel.bounding_box, analysis.bounding_box);
+        assert_eq!(viewmodel.slicer, None);
+    }
+}
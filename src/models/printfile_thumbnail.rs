@@ -0,0 +1,96 @@
+use sea_query::Iden;
+use serde::{Deserialize, Serialize};
+
+use crate::common::gcode::ThumbnailFormat;
+
+#[derive(Iden)]
+pub enum PrintFileThumbnail {
+    Table,
+    Uuid,
+    PrintFileUuid,
+    Width,
+    Height,
+    Format,
+    Path,
+}
+
+#[derive(sqlx::FromRow, Debug)]
+pub struct PrintFileThumbnailDbModel {
+    pub uuid: String,
+    pub print_file_uuid: String,
+    pub width: i32,
+    pub height: i32,
+    pub format: String,
+    pub path: String,
+}
+
+/// - size: Requested size, either "WIDTHxHEIGHT" or a width
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ThumbnailQuery {
+    pub size: Option<String>,
+}
+
+impl PrintFileThumbnailDbModel {
+    pub fn format(&self) -> ThumbnailFormat {
+        self.format.parse().unwrap_or(ThumbnailFormat::Png)
+    }
+}
+
+/// Picks the thumbnail for the requested size: an exact match, otherwise the smallest one
+/// that is at least as wide, otherwise the largest one. Without a size the largest one is picked
+pub fn select_thumbnail<'a>(
+    thumbnails: &'a [PrintFileThumbnailDbModel],
+    size: Option<&str>,
+) -> Option<&'a PrintFileThumbnailDbModel> {
+    let largest = thumbnails
+        .iter()
+        .max_by_key(|thumbnail| thumbnail.width * thumbnail.height);
+    let (width, height) = match size {
+        Some(size) => match size.split_once('x') {
+            Some((width, height)) => (width.parse().ok()?, height.parse::<i32>().ok()),
+            None => (size.parse().ok()?, None),
+        },
+        None => return largest,
+    };
+
+    thumbnails
+        .iter()
+        .find(|thumbnail| thumbnail.width == width && Some(thumbnail.height) == height)
+        .or_else(|| {
+            thumbnails
+                .iter()
+                .filter(|thumbnail| thumbnail.width >= width)
+                .min_by_key(|thumbnail| thumbnail.width)
+        })
+        .or(largest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thumbnail(width: i32, height: i32) -> PrintFileThumbnailDbModel {
+        PrintFileThumbnailDbModel {
+            uuid: format!("{}x{}", width, height),
+            print_file_uuid: "file".to_string(),
+            width,
+            height,
+            format: "png".to_string(),
+            path: "path".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_select_thumbnail() {
+        let thumbnails = vec![thumbnail(16, 16), thumbnail(300, 300), thumbnail(300, 200)];
+        let selected = |size| select_thumbnail(&thumbnails, size).map(|t| t.uuid.as_str());
+
+        assert_eq!(selected(None), Some("300x300"));
+        assert_eq!(selected(Some("300x200")), Some("300x200"));
+        assert_eq!(selected(Some("16")), Some("16x16"));
+        assert_eq!(selected(Some("100")), Some("300x300"));
+        assert_eq!(selected(Some("1000x1000")), Some("300x300"));
+        assert_eq!(selected(Some("large")), None);
+        assert_eq!(select_thumbnail(&[], None).map(|t| t.width), None);
+    }
+}
@@ -0,0 +1,51 @@
+use sea_query::Iden;
+
+#[derive(Iden)]
+pub enum StorageDeletion {
+    Table,
+    Uuid,
+    Path,
+    FileStorageType,
+    Attempts,
+    LastError,
+    NextAttemptAt,
+    CreatedAt,
+}
+
+/// A file that could not be removed from the file storage, it's deleted again until it's gone
+/// - next_attempt_at: Unix timestamp of the next attempt
+#[derive(sqlx::FromRow, Debug)]
+pub struct StorageDeletionDbModel {
+    pub uuid: String,
+    pub path: String,
+    pub file_storage_type: String,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub next_attempt_at: i64,
+    pub created_at: String,
+}
+
+/// Delay of the first retry, doubled with every failed attempt
+const RETRY_DELAY: i64 = 60;
+/// Longest delay between two attempts
+const MAX_RETRY_DELAY: i64 = 6 * 60 * 60;
+
+/// Seconds until the next attempt after the given number of failed attempts
+pub fn retry_delay(attempts: i32) -> i64 {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    (RETRY_DELAY << exponent).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), 60);
+        assert_eq!(retry_delay(2), 120);
+        assert_eq!(retry_delay(5), 960);
+        assert_eq!(retry_delay(10), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(1000), MAX_RETRY_DELAY);
+    }
+}
@@ -9,8 +9,10 @@ use tower_http::trace;
 use tracing::Level;
 
 use crate::controllers::websockets::{agent_websocket, user_websocket};
-use crate::controllers::{account_controller, agent_controller};
-use crate::controllers::{auth_controller, printfile_controller};
+use crate::controllers::{
+    account_controller, agent_controller, job_controller, printer_controller,
+};
+use crate::controllers::{auth_controller, printfile_controller, queue_controller};
 use crate::AppState;
 
 pub async fn create(state: Arc<AppState>) -> Router {
@@ -27,6 +29,9 @@ pub async fn create(state: Arc<AppState>) -> Router {
     let account_endpoints = account_controller::init();
     let printfile_endpoints = printfile_controller::init();
     let agent_endpoints = agent_controller::init();
+    let printer_endpoints = printer_controller::init();
+    let job_endpoints = job_controller::init();
+    let queue_endpoints = queue_controller::init();
 
     Router::new()
         .route("/health", get(|| async { "OK" }))
@@ -34,9 +39,15 @@ pub async fn create(state: Arc<AppState>) -> Router {
         .nest("/api/v1", account_endpoints)
         .nest("/api/v1", printfile_endpoints)
         .nest("/api/v1", agent_endpoints)
+        .nest("/api/v1", printer_endpoints)
+        .nest("/api/v1", job_endpoints)
+        .nest("/api/v1", queue_endpoints)
         .layer(cors)
         .layer(trace_layer)
         .route("/ws", get(user_websocket::handler))
         .route("/agents/ws", get(agent_websocket::handler))
+        // paths of the websocket specification
+        .route("/api/v1/ws", get(user_websocket::handler))
+        .route("/api/v1/ws/agent", get(agent_websocket::handler))
         .with_state(state)
 }
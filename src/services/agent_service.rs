@@ -8,14 +8,41 @@ use sqlx::{Executor, FromRow, MySql, Pool};
 use tracing::error;
 use uuid::Uuid;
 
+use crate::common::agent_token::{generate_token, hash_token};
 use crate::common::app_error::AppError;
-use crate::models::agent::{Agent, AgentAddRequest, AgentDbModel};
+use crate::models::agent::{
+    Agent, AgentAddRequest, AgentDbModel, AgentHandshake, AgentUpdateRequest,
+};
+use crate::services::printer_service::{PrinterService, PrinterServiceImpl};
 
 #[async_trait]
 pub trait AgentService {
-    async fn add(&self, user_uuid: &str, agent: AgentAddRequest) -> Result<AgentDbModel, AppError>;
+    async fn add(
+        &self,
+        user_uuid: &str,
+        agent: AgentAddRequest,
+    ) -> Result<(AgentDbModel, String), AppError>;
+    async fn update(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        agent: AgentUpdateRequest,
+    ) -> Result<AgentDbModel, AppError>;
     async fn delete(&self, user_uuid: &str, file_uuid: &str) -> Result<bool, AppError>;
     async fn get_all(&self, user_uuid: &str) -> Result<Vec<AgentDbModel>, AppError>;
+    async fn get_by_uuid(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+    ) -> Result<AgentDbModel, AppError>;
+    async fn get_by_token(&self, token: &str) -> Result<AgentDbModel, AppError>;
+    async fn rotate_token(&self, user_uuid: &str, agent_uuid: &str) -> Result<String, AppError>;
+    async fn revoke_token(&self, user_uuid: &str, agent_uuid: &str) -> Result<bool, AppError>;
+    async fn update_handshake(
+        &self,
+        agent_uuid: &str,
+        handshake: &AgentHandshake,
+    ) -> Result<(), AppError>;
 }
 
 pub struct AgentServiceImpl {
@@ -28,30 +55,47 @@ impl AgentServiceImpl {
     }
 }
 
+const AGENT_SELECT_COLUMNS: [Agent; 11] = [
+    Agent::Uuid,
+    Agent::UserUuid,
+    Agent::Name,
+    Agent::Description,
+    Agent::Token,
+    Agent::CreatedAt,
+    Agent::Version,
+    Agent::ProtocolVersion,
+    Agent::Os,
+    Agent::Arch,
+    Agent::Capabilities,
+];
+
+/// Stored in place of the token hash of a revoked token, no token hashes to it
+const REVOKED_TOKEN: &str = "";
+
 #[async_trait]
 impl AgentService for AgentServiceImpl {
-    async fn add(&self, user_uuid: &str, agent: AgentAddRequest) -> Result<AgentDbModel, AppError> {
-        if agent.name.is_empty() {
-            return Err(AppError::Agent {
-                message: "Agent name cannot be empty".to_string(),
-                status: StatusCode::BAD_REQUEST,
-            });
-        }
-
-        if agent.name.len() < 3 {
-            return Err(AppError::Agent {
-                message: "Agent name must be at least 3 characters long".to_string(),
-                status: StatusCode::BAD_REQUEST,
-            });
-        }
+    /// Creates the agent, returns it together with its plaintext token. Only the hash of the token
+    /// is stored, the plaintext can't be retrieved afterwards
+    async fn add(
+        &self,
+        user_uuid: &str,
+        agent: AgentAddRequest,
+    ) -> Result<(AgentDbModel, String), AppError> {
+        validate_name(&agent.name)?;
 
+        let token = generate_token();
         let agent_model = AgentDbModel {
             uuid: Uuid::new_v4().to_string(),
             user_uuid: user_uuid.to_string(),
             name: agent.name,
             description: agent.description,
-            token: Uuid::new_v4().to_string(),
+            token: hash_token(&token),
             created_at: Utc::now().timestamp().to_string(),
+            version: None,
+            protocol_version: None,
+            os: None,
+            arch: None,
+            capabilities: None,
         };
 
         let sql = Query::insert()
@@ -78,7 +122,7 @@ impl AgentService for AgentServiceImpl {
         let mut conn = self.pool.acquire().await.unwrap();
 
         match conn.execute(&*sql).await {
-            Ok(_) => Ok(agent_model),
+            Ok(_) => Ok((agent_model, token)),
             Err(e) => {
                 error!("Error creating agents: {}", e);
                 Err(AppError::InternalServer)
@@ -86,7 +130,51 @@ impl AgentService for AgentServiceImpl {
         }
     }
 
+    /// Changes the name and description of the agent
+    async fn update(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        agent: AgentUpdateRequest,
+    ) -> Result<AgentDbModel, AppError> {
+        validate_name(&agent.name)?;
+        let mut agent_model = self.get_by_uuid(user_uuid, agent_uuid).await?;
+
+        let sql = Query::update()
+            .table(Agent::Table)
+            .values([
+                (Agent::Name, agent.name.to_string().into()),
+                (Agent::Description, agent.description.to_string().into()),
+            ])
+            .and_where(Expr::col(Agent::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Agent::Uuid).eq(agent_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => {
+                agent_model.name = agent.name;
+                agent_model.description = agent.description;
+                Ok(agent_model)
+            }
+            Err(e) => {
+                error!("Error updating agent: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Deletes the agent after its printers, a failure in between leaves the agent with fewer
+    /// printers rather than printers without an agent
     async fn delete(&self, user_uuid: &str, agent_uuid: &str) -> Result<bool, AppError> {
+        self.get_by_uuid(user_uuid, agent_uuid).await?;
+
+        let printer_service = PrinterServiceImpl::new(self.pool.clone());
+        printer_service
+            .delete_by_agent(user_uuid, agent_uuid)
+            .await?;
+
         let sql = Query::delete()
             .from_table(Agent::Table)
             .and_where(Expr::col(Agent::UserUuid).eq(user_uuid))
@@ -114,14 +202,7 @@ impl AgentService for AgentServiceImpl {
 
     async fn get_all(&self, user_uuid: &str) -> Result<Vec<AgentDbModel>, AppError> {
         let sql = Query::select()
-            .columns([
-                Agent::Uuid,
-                Agent::UserUuid,
-                Agent::Name,
-                Agent::Description,
-                Agent::Token,
-                Agent::CreatedAt,
-            ])
+            .columns(AGENT_SELECT_COLUMNS)
             .from(Agent::Table)
             .and_where(Expr::col(Agent::UserUuid).eq(user_uuid))
             .to_string(MysqlQueryBuilder);
@@ -143,4 +224,154 @@ impl AgentService for AgentServiceImpl {
 
         Ok(agents)
     }
+
+    async fn get_by_uuid(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+    ) -> Result<AgentDbModel, AppError> {
+        let sql = Query::select()
+            .columns(AGENT_SELECT_COLUMNS)
+            .from(Agent::Table)
+            .and_where(Expr::col(Agent::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Agent::Uuid).eq(agent_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let row = sqlx::query(&sql).fetch_optional(&*self.pool).await.unwrap();
+
+        if row.is_none() {
+            return Err(AppError::Agent {
+                message: "Agent not found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        let row = row.expect("Error unwrapping row");
+        let agent = AgentDbModel::from_row(&row).expect("Error converting row to AgentDbModel");
+
+        Ok(agent)
+    }
+
+    /// Retrieves the agent that owns the given token, used to authenticate agent websockets
+    async fn get_by_token(&self, token: &str) -> Result<AgentDbModel, AppError> {
+        let sql = Query::select()
+            .columns(AGENT_SELECT_COLUMNS)
+            .from(Agent::Table)
+            .and_where(Expr::col(Agent::Token).eq(hash_token(token)))
+            .to_string(MysqlQueryBuilder);
+
+        let row = match sqlx::query(&sql).fetch_optional(&*self.pool).await {
+            Ok(row) => row,
+            Err(e) => {
+                error!("Error retrieving agent by token: {}", e);
+                return Err(AppError::InternalServer);
+            }
+        };
+
+        match row {
+            Some(row) => {
+                Ok(AgentDbModel::from_row(&row).expect("Error converting row to AgentDbModel"))
+            }
+            None => Err(AppError::Agent {
+                message: "Invalid agent token".to_string(),
+                status: StatusCode::UNAUTHORIZED,
+            }),
+        }
+    }
+
+    /// Replaces the token of the agent, returns the new plaintext token. The old token stops working
+    async fn rotate_token(&self, user_uuid: &str, agent_uuid: &str) -> Result<String, AppError> {
+        let token = generate_token();
+        self.update_token(user_uuid, agent_uuid, &hash_token(&token))
+            .await?;
+        Ok(token)
+    }
+
+    /// Revokes the token of the agent, it can't authenticate until its token is rotated
+    async fn revoke_token(&self, user_uuid: &str, agent_uuid: &str) -> Result<bool, AppError> {
+        self.update_token(user_uuid, agent_uuid, REVOKED_TOKEN)
+            .await?;
+        Ok(true)
+    }
+
+    /// Stores what the agent reported about itself when it authenticated
+    async fn update_handshake(
+        &self,
+        agent_uuid: &str,
+        handshake: &AgentHandshake,
+    ) -> Result<(), AppError> {
+        let capabilities = handshake
+            .capabilities
+            .as_ref()
+            .map(|capabilities| serde_json::to_string(capabilities).unwrap());
+
+        let sql = Query::update()
+            .table(Agent::Table)
+            .values([
+                (Agent::Version, handshake.version.clone().into()),
+                (Agent::ProtocolVersion, handshake.protocol_version.into()),
+                (Agent::Os, handshake.os.clone().into()),
+                (Agent::Arch, handshake.arch.clone().into()),
+                (Agent::Capabilities, capabilities.into()),
+            ])
+            .and_where(Expr::col(Agent::Uuid).eq(agent_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error updating agent handshake: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), AppError> {
+    if name.is_empty() {
+        return Err(AppError::Agent {
+            message: "Agent name cannot be empty".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    if name.len() < 3 {
+        return Err(AppError::Agent {
+            message: "Agent name must be at least 3 characters long".to_string(),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    Ok(())
+}
+
+impl AgentServiceImpl {
+    async fn update_token(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        token_hash: &str,
+    ) -> Result<(), AppError> {
+        // also checks that the agent exists, an unchanged row is not reported as affected
+        self.get_by_uuid(user_uuid, agent_uuid).await?;
+
+        let sql = Query::update()
+            .table(Agent::Table)
+            .values([(Agent::Token, token_hash.into())])
+            .and_where(Expr::col(Agent::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Agent::Uuid).eq(agent_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error updating agent token: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
 }
@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_query::{Expr, MysqlQueryBuilder, OnConflict, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::error;
+
+use crate::common::app_error::AppError;
+use crate::common::gcode::command_word;
+use crate::models::command_policy::{
+    CommandPolicy, CommandPolicyDbModel, CommandPolicyUpdateRequest,
+};
+
+#[async_trait]
+pub trait CommandPolicyService {
+    async fn get(&self, user_uuid: &str) -> Result<CommandPolicyDbModel, AppError>;
+    async fn update(
+        &self,
+        user_uuid: &str,
+        policy: CommandPolicyUpdateRequest,
+    ) -> Result<CommandPolicyDbModel, AppError>;
+    async fn check(&self, user_uuid: &str, command: &str) -> Result<(), AppError>;
+}
+
+pub struct CommandPolicyServiceImpl {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl CommandPolicyServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        CommandPolicyServiceImpl { pool }
+    }
+}
+
+#[async_trait]
+impl CommandPolicyService for CommandPolicyServiceImpl {
+    /// Retrieves the command policy of the user, falls back to the default policy
+    async fn get(&self, user_uuid: &str) -> Result<CommandPolicyDbModel, AppError> {
+        let sql = Query::select()
+            .columns([
+                CommandPolicy::UserUuid,
+                CommandPolicy::Mode,
+                CommandPolicy::Commands,
+                CommandPolicy::UpdatedAt,
+            ])
+            .from(CommandPolicy::Table)
+            .and_where(Expr::col(CommandPolicy::UserUuid).eq(user_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let row = match sqlx::query(&sql).fetch_optional(&*self.pool).await {
+            Ok(row) => row,
+            Err(e) => {
+                error!("Error retrieving command policy: {}", e);
+                return Err(AppError::InternalServer);
+            }
+        };
+
+        match row {
+            Some(row) => Ok(CommandPolicyDbModel::from_row(&row)
+                .expect("Error converting row to CommandPolicyDbModel")),
+            None => Ok(CommandPolicyDbModel::default_for(user_uuid)),
+        }
+    }
+
+    /// Replaces the command policy of the user, commands are stored normalized (e.g. m0500 -> M500)
+    async fn update(
+        &self,
+        user_uuid: &str,
+        policy: CommandPolicyUpdateRequest,
+    ) -> Result<CommandPolicyDbModel, AppError> {
+        let mut commands: Vec<String> = Vec::new();
+        for command in &policy.commands {
+            match command_word(command) {
+                Some(command) if !commands.contains(&command) => commands.push(command),
+                Some(_) => {}
+                None => {
+                    return Err(AppError::Validation {
+                        messages: format!("Invalid G-code command: {}", command),
+                        status: StatusCode::BAD_REQUEST,
+                    })
+                }
+            }
+        }
+
+        let policy_model = CommandPolicyDbModel {
+            user_uuid: user_uuid.to_string(),
+            mode: policy.mode.to_string(),
+            commands: commands.join(","),
+            updated_at: Utc::now().timestamp().to_string(),
+        };
+
+        let sql = Query::insert()
+            .into_table(CommandPolicy::Table)
+            .columns([
+                CommandPolicy::UserUuid,
+                CommandPolicy::Mode,
+                CommandPolicy::Commands,
+                CommandPolicy::UpdatedAt,
+            ])
+            .values_panic([
+                policy_model.user_uuid.to_string().into(),
+                policy_model.mode.to_string().into(),
+                policy_model.commands.to_string().into(),
+                policy_model.updated_at.to_string().into(),
+            ])
+            .on_conflict(
+                OnConflict::column(CommandPolicy::UserUuid)
+                    .update_columns([
+                        CommandPolicy::Mode,
+                        CommandPolicy::Commands,
+                        CommandPolicy::UpdatedAt,
+                    ])
+                    .to_owned(),
+            )
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(policy_model),
+            Err(e) => {
+                error!("Error updating command policy: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Checks if the user is allowed to send the command to a printer
+    async fn check(&self, user_uuid: &str, command: &str) -> Result<(), AppError> {
+        if command_word(command).is_none() {
+            return Err(AppError::Validation {
+                messages: format!("Invalid G-code command: {}", command),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+
+        let policy = self.get(user_uuid).await?;
+        if !policy.is_allowed(command) {
+            return Err(AppError::Printer {
+                message: format!("Command {} is blocked by the command policy", command),
+                status: StatusCode::FORBIDDEN,
+            });
+        }
+
+        Ok(())
+    }
+}
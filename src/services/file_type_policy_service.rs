@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_query::{Expr, MysqlQueryBuilder, OnConflict, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::error;
+
+use crate::common::app_error::AppError;
+use crate::models::file_type_policy::{
+    FileTypePolicy, FileTypePolicyDbModel, FileTypePolicyUpdateRequest,
+};
+use crate::models::printfile::FileType;
+
+#[async_trait]
+pub trait FileTypePolicyService {
+    async fn get(&self, user_uuid: &str) -> Result<FileTypePolicyDbModel, AppError>;
+    async fn update(
+        &self,
+        user_uuid: &str,
+        policy: FileTypePolicyUpdateRequest,
+    ) -> Result<FileTypePolicyDbModel, AppError>;
+}
+
+pub struct FileTypePolicyServiceImpl {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl FileTypePolicyServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        FileTypePolicyServiceImpl { pool }
+    }
+}
+
+#[async_trait]
+impl FileTypePolicyService for FileTypePolicyServiceImpl {
+    /// Retrieves the file type policy of the user, falls back to the default policy
+    async fn get(&self, user_uuid: &str) -> Result<FileTypePolicyDbModel, AppError> {
+        let sql = Query::select()
+            .columns([
+                FileTypePolicy::UserUuid,
+                FileTypePolicy::FileTypes,
+                FileTypePolicy::UpdatedAt,
+            ])
+            .from(FileTypePolicy::Table)
+            .and_where(Expr::col(FileTypePolicy::UserUuid).eq(user_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let row = match sqlx::query(&sql).fetch_optional(&*self.pool).await {
+            Ok(row) => row,
+            Err(e) => {
+                error!("Error retrieving file type policy: {}", e);
+                return Err(AppError::InternalServer);
+            }
+        };
+
+        match row {
+            Some(row) => Ok(FileTypePolicyDbModel::from_row(&row)
+                .expect("Error converting row to FileTypePolicyDbModel")),
+            None => Ok(FileTypePolicyDbModel::default_for(user_uuid)),
+        }
+    }
+
+    /// Replaces the file types the user may upload
+    async fn update(
+        &self,
+        user_uuid: &str,
+        policy: FileTypePolicyUpdateRequest,
+    ) -> Result<FileTypePolicyDbModel, AppError> {
+        let mut file_types: Vec<String> = Vec::new();
+        for file_type in policy.file_types {
+            if file_type == FileType::Unknown {
+                return Err(AppError::Validation {
+                    messages: "Files of unknown type can't be allowed".to_string(),
+                    status: StatusCode::BAD_REQUEST,
+                });
+            }
+            if !file_types.contains(&file_type.to_string()) {
+                file_types.push(file_type.to_string());
+            }
+        }
+
+        let policy_model = FileTypePolicyDbModel {
+            user_uuid: user_uuid.to_string(),
+            file_types: file_types.join(","),
+            updated_at: Utc::now().timestamp().to_string(),
+        };
+
+        let sql = Query::insert()
+            .into_table(FileTypePolicy::Table)
+            .columns([
+                FileTypePolicy::UserUuid,
+                FileTypePolicy::FileTypes,
+                FileTypePolicy::UpdatedAt,
+            ])
+            .values_panic([
+                policy_model.user_uuid.to_string().into(),
+                policy_model.file_types.to_string().into(),
+                policy_model.updated_at.to_string().into(),
+            ])
+            .on_conflict(
+                OnConflict::column(FileTypePolicy::UserUuid)
+                    .update_columns([FileTypePolicy::FileTypes, FileTypePolicy::UpdatedAt])
+                    .to_owned(),
+            )
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(policy_model),
+            Err(e) => {
+                error!("Error updating file type policy: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
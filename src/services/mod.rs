@@ -1,4 +1,12 @@
 pub mod account_service;
 pub mod agent_service;
 pub mod auth_service;
+pub mod command_policy_service;
+pub mod file_type_policy_service;
+pub mod print_job_service;
+pub mod print_queue_service;
+pub mod printer_service;
+pub mod printfile_metadata_service;
 pub mod printfile_service;
+pub mod printfile_thumbnail_service;
+pub mod storage_deletion_service;
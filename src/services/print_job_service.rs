@@ -0,0 +1,451 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_query::{Alias, Expr, MysqlQueryBuilder, Order, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::common::app_error::AppError;
+use crate::models::print_job::{PrintJob, PrintJobDbModel, PrintJobFilter, PrintJobState};
+use crate::models::printer::Printer;
+use crate::models::printer_status::PrinterStatusReport;
+use crate::services::printer_service::{PrinterService, PrinterServiceImpl};
+
+#[async_trait]
+pub trait PrintJobService {
+    async fn start(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printer_uuid: &str,
+        print_file_uuid: &str,
+        state: PrintJobState,
+    ) -> Result<PrintJobDbModel, AppError>;
+    async fn transition(
+        &self,
+        job: PrintJobDbModel,
+        state: PrintJobState,
+        progress: Option<f64>,
+    ) -> Result<PrintJobDbModel, AppError>;
+    async fn apply_status(
+        &self,
+        user_uuid: &str,
+        report: &PrinterStatusReport,
+    ) -> Result<Option<PrintJobDbModel>, AppError>;
+    async fn get_active(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<Option<PrintJobDbModel>, AppError>;
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+        filter: PrintJobFilter,
+    ) -> Result<Vec<PrintJobDbModel>, AppError>;
+    async fn get_by_uuid(&self, user_uuid: &str, uuid: &str) -> Result<PrintJobDbModel, AppError>;
+}
+
+pub struct PrintJobServiceImpl {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl PrintJobServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        PrintJobServiceImpl { pool }
+    }
+}
+
+const PRINT_JOB_SELECT_COLUMNS: [PrintJob; 11] = [
+    PrintJob::Uuid,
+    PrintJob::UserUuid,
+    PrintJob::AgentUuid,
+    PrintJob::PrinterUuid,
+    PrintJob::PrintFileUuid,
+    PrintJob::State,
+    PrintJob::Progress,
+    PrintJob::CreatedAt,
+    PrintJob::StartedAt,
+    PrintJob::FinishedAt,
+    PrintJob::UpdatedAt,
+];
+
+#[async_trait]
+impl PrintJobService for PrintJobServiceImpl {
+    /// Records a new job in the queued or starting state, a printer only runs one job at a time.
+    /// The printer row stays locked until the job is inserted, so concurrent starts for the
+    /// same printer can't both see it idle
+    async fn start(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printer_uuid: &str,
+        print_file_uuid: &str,
+        state: PrintJobState,
+    ) -> Result<PrintJobDbModel, AppError> {
+        let mut tx = match self.pool.begin().await {
+            Ok(tx) => tx,
+            Err(e) => {
+                error!("Error starting print job transaction: {}", e);
+                return Err(AppError::InternalServer);
+            }
+        };
+
+        let lock_printer = Query::select()
+            .column(Printer::Uuid)
+            .from(Printer::Table)
+            .and_where(Expr::col(Printer::Uuid).eq(printer_uuid))
+            .lock_exclusive()
+            .to_string(MysqlQueryBuilder);
+        let active = match tx.execute(&*lock_printer).await {
+            Ok(_) => {
+                sqlx::query(&select_active_sql(user_uuid, printer_uuid))
+                    .fetch_optional(&mut *tx)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        match active {
+            Ok(None) => {}
+            Ok(Some(row)) => {
+                let active = PrintJobDbModel::from_row(&row)
+                    .expect("Error converting row to PrintJobDbModel");
+                return Err(AppError::PrintJob {
+                    message: format!("Printer is already running print job {}", active.uuid),
+                    status: StatusCode::CONFLICT,
+                });
+            }
+            Err(e) => {
+                error!("Error retrieving active print job: {}", e);
+                return Err(AppError::InternalServer);
+            }
+        }
+
+        let now = Utc::now().timestamp().to_string();
+        let job = PrintJobDbModel {
+            uuid: Uuid::new_v4().to_string(),
+            user_uuid: user_uuid.to_string(),
+            agent_uuid: agent_uuid.to_string(),
+            printer_uuid: printer_uuid.to_string(),
+            print_file_uuid: print_file_uuid.to_string(),
+            state: state.to_string(),
+            progress: 0.0,
+            created_at: now.to_string(),
+            started_at: None,
+            finished_at: None,
+            updated_at: now,
+        };
+
+        let sql = Query::insert()
+            .into_table(PrintJob::Table)
+            .columns(PRINT_JOB_SELECT_COLUMNS)
+            .values_panic([
+                job.uuid.to_string().into(),
+                job.user_uuid.to_string().into(),
+                job.agent_uuid.to_string().into(),
+                job.printer_uuid.to_string().into(),
+                job.print_file_uuid.to_string().into(),
+                job.state.to_string().into(),
+                job.progress.into(),
+                job.created_at.to_string().into(),
+                job.started_at.clone().into(),
+                job.finished_at.clone().into(),
+                job.updated_at.to_string().into(),
+            ])
+            .to_string(MysqlQueryBuilder)
+            .to_owned();
+
+        let inserted = match tx.execute(&*sql).await {
+            Ok(_) => tx.commit().await,
+            Err(e) => Err(e),
+        };
+        match inserted {
+            Ok(_) => Ok(job),
+            Err(e) => {
+                error!("Error inserting print job: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Moves the job to the given state, illegal transitions are rejected.
+    /// Sets started_at when the job starts printing and finished_at when it reaches a final state
+    async fn transition(
+        &self,
+        mut job: PrintJobDbModel,
+        state: PrintJobState,
+        progress: Option<f64>,
+    ) -> Result<PrintJobDbModel, AppError> {
+        job.check_transition(state)?;
+        let previous = job.state();
+
+        let now = Utc::now().timestamp().to_string();
+        job.state = state.to_string();
+        job.updated_at = now.to_string();
+        if let Some(progress) = progress {
+            job.progress = progress;
+        }
+        if state == PrintJobState::Printing && job.started_at.is_none() {
+            job.started_at = Some(now.to_string());
+        }
+        if state == PrintJobState::Completed {
+            job.progress = 1.0;
+        }
+        if state.is_final() {
+            job.finished_at = Some(now);
+        }
+
+        update_job(self.pool.clone(), &job, previous).await?;
+        info!("print job {} is now {}", job.uuid, job.state);
+
+        // whatever was printed has to be removed before the printer takes the next queued job
+        if state.is_final() && job.started_at.is_some() {
+            let printer_service = PrinterServiceImpl::new(self.pool.clone());
+            printer_service
+                .set_bed_cleared(&job.user_uuid, &job.printer_uuid, false)
+                .await?;
+        }
+
+        Ok(job)
+    }
+
+    /// Applies a status report of the agent to the active job of the printer, if any.
+    /// Returns the job if it was changed
+    async fn apply_status(
+        &self,
+        user_uuid: &str,
+        report: &PrinterStatusReport,
+    ) -> Result<Option<PrintJobDbModel>, AppError> {
+        let mut job = match self.get_active(user_uuid, &report.printer_uuid).await? {
+            Some(job) => job,
+            None => return Ok(None),
+        };
+
+        let progress = report
+            .job
+            .as_ref()
+            .filter(|reported| reported.print_file_uuid == job.print_file_uuid)
+            .map(|reported| reported.progress);
+
+        match job
+            .state()
+            .next_for_report(&job.print_file_uuid, job.progress, report)
+        {
+            Some(state) => Ok(Some(self.transition(job, state, progress).await?)),
+            None => match progress {
+                Some(progress) if progress != job.progress => {
+                    job.progress = progress;
+                    job.updated_at = Utc::now().timestamp().to_string();
+                    let state = job.state();
+                    update_job(self.pool.clone(), &job, state).await?;
+                    Ok(Some(job))
+                }
+                _ => Ok(None),
+            },
+        }
+    }
+
+    /// Retrieves the job that currently occupies the printer
+    async fn get_active(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<Option<PrintJobDbModel>, AppError> {
+        let sql = select_active_sql(user_uuid, printer_uuid);
+
+        match sqlx::query(&sql).fetch_optional(&*self.pool).await {
+            Ok(row) => Ok(row.map(|row| {
+                PrintJobDbModel::from_row(&row).expect("Error converting row to PrintJobDbModel")
+            })),
+            Err(e) => {
+                error!("Error retrieving active print job: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Retrieves the job history of the user, newest first
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+        filter: PrintJobFilter,
+    ) -> Result<Vec<PrintJobDbModel>, AppError> {
+        let sql = select_jobs_sql(user_uuid, filter);
+
+        let row = sqlx::query(&sql).fetch_all(&*self.pool).await.unwrap();
+
+        if row.is_empty() {
+            return Err(AppError::PrintJob {
+                message: "No print jobs found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        let mut jobs: Vec<PrintJobDbModel> = Vec::new();
+        for row in row {
+            let job =
+                PrintJobDbModel::from_row(&row).expect("Error converting row to PrintJobDbModel");
+            jobs.push(job);
+        }
+
+        Ok(jobs)
+    }
+
+    async fn get_by_uuid(&self, user_uuid: &str, uuid: &str) -> Result<PrintJobDbModel, AppError> {
+        let sql = Query::select()
+            .columns(PRINT_JOB_SELECT_COLUMNS)
+            .from(PrintJob::Table)
+            .and_where(Expr::col(PrintJob::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(PrintJob::Uuid).eq(uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let row = sqlx::query(&sql).fetch_optional(&*self.pool).await.unwrap();
+
+        match row {
+            Some(row) => {
+                Ok(PrintJobDbModel::from_row(&row)
+                    .expect("Error converting row to PrintJobDbModel"))
+            }
+            None => Err(AppError::PrintJob {
+                message: "Print job not found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            }),
+        }
+    }
+}
+
+fn select_active_sql(user_uuid: &str, printer_uuid: &str) -> String {
+    let active_states = PrintJobState::ACTIVE.map(|state| state.to_string());
+
+    Query::select()
+        .columns(PRINT_JOB_SELECT_COLUMNS)
+        .from(PrintJob::Table)
+        .and_where(Expr::col(PrintJob::UserUuid).eq(user_uuid))
+        .and_where(Expr::col(PrintJob::PrinterUuid).eq(printer_uuid))
+        .and_where(Expr::col(PrintJob::State).is_in(active_states))
+        .order_by(PrintJob::CreatedAt, Order::Desc)
+        .limit(1)
+        .to_string(MysqlQueryBuilder)
+}
+
+/// Builds the select of the job history, the query builder is not Send so it can't live across an await
+fn select_jobs_sql(user_uuid: &str, filter: PrintJobFilter) -> String {
+    let mut query = Query::select();
+    query
+        .columns(PRINT_JOB_SELECT_COLUMNS)
+        .from(PrintJob::Table)
+        .and_where(Expr::col(PrintJob::UserUuid).eq(user_uuid));
+
+    if let Some(agent_uuid) = filter.agent_uuid {
+        query.and_where(Expr::col(PrintJob::AgentUuid).eq(agent_uuid));
+    }
+    if let Some(printer_uuid) = filter.printer_uuid {
+        query.and_where(Expr::col(PrintJob::PrinterUuid).eq(printer_uuid));
+    }
+    if let Some(print_file_uuid) = filter.print_file_uuid {
+        query.and_where(Expr::col(PrintJob::PrintFileUuid).eq(print_file_uuid));
+    }
+    if let Some(state) = filter.state {
+        query.and_where(Expr::col(PrintJob::State).eq(state.to_string()));
+    }
+    // timestamps are stored as strings, compare them as numbers
+    let created_at = Expr::col(PrintJob::CreatedAt).cast_as(Alias::new("UNSIGNED"));
+    if let Some(from) = filter.from {
+        query.and_where(Expr::expr(created_at.clone()).gte(from));
+    }
+    if let Some(to) = filter.to {
+        query.and_where(Expr::expr(created_at).lte(to));
+    }
+
+    query
+        .order_by(PrintJob::CreatedAt, Order::Desc)
+        .to_string(MysqlQueryBuilder)
+}
+
+/// Writes the job if it is still in the state it was read in. Another request may have moved
+/// it in between, e.g. the user cancelled a queued job while the dispatcher starts it
+async fn update_job(
+    pool: Arc<Pool<MySql>>,
+    job: &PrintJobDbModel,
+    previous: PrintJobState,
+) -> Result<(), AppError> {
+    let sql = update_job_sql(job, previous);
+
+    let mut conn = pool.acquire().await.unwrap();
+
+    match conn.execute(&*sql).await {
+        Ok(result) if result.rows_affected() == 0 => Err(AppError::PrintJob {
+            message: format!("Print job {} is no longer {}", job.uuid, previous),
+            status: StatusCode::CONFLICT,
+        }),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Error updating print job: {}", e);
+            Err(AppError::InternalServer)
+        }
+    }
+}
+
+fn update_job_sql(job: &PrintJobDbModel, previous: PrintJobState) -> String {
+    Query::update()
+        .table(PrintJob::Table)
+        .values([
+            (PrintJob::State, job.state.to_string().into()),
+            (PrintJob::Progress, job.progress.into()),
+            (PrintJob::StartedAt, job.started_at.clone().into()),
+            (PrintJob::FinishedAt, job.finished_at.clone().into()),
+            (PrintJob::UpdatedAt, job.updated_at.to_string().into()),
+        ])
+        .and_where(Expr::col(PrintJob::Uuid).eq(job.uuid.to_string()))
+        .and_where(Expr::col(PrintJob::State).eq(previous.to_string()))
+        .to_string(MysqlQueryBuilder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_jobs_sql_filters() {
+        let sql = select_jobs_sql(
+            "user",
+            PrintJobFilter {
+                printer_uuid: Some("printer".to_string()),
+                state: Some(PrintJobState::Completed),
+                from: Some(1700000000),
+                ..Default::default()
+            },
+        );
+        assert!(sql.contains("`printer_uuid` = 'printer'"));
+        assert!(sql.contains("`state` = 'completed'"));
+        assert!(sql.contains("CAST(`created_at` AS UNSIGNED) >= 1700000000"));
+        assert!(!sql.contains("`agent_uuid` ="));
+    }
+
+    #[test]
+    fn test_update_job_sql_requires_previous_state() {
+        // the user cancelled the queued job before the dispatcher moves it to starting
+        let mut job = PrintJobDbModel {
+            uuid: "job".to_string(),
+            user_uuid: "user".to_string(),
+            agent_uuid: "agent".to_string(),
+            printer_uuid: "printer".to_string(),
+            print_file_uuid: "file".to_string(),
+            state: PrintJobState::Cancelled.to_string(),
+            progress: 0.0,
+            created_at: "1700000000".to_string(),
+            started_at: None,
+            finished_at: Some("1700000001".to_string()),
+            updated_at: "1700000001".to_string(),
+        };
+        assert!(job.check_transition(PrintJobState::Starting).is_err());
+
+        // the dispatcher read the job while it was still queued, its update matches no row
+        job.state = PrintJobState::Starting.to_string();
+        let sql = update_job_sql(&job, PrintJobState::Queued);
+        assert!(sql.contains("`state` = 'starting'"));
+        assert!(sql.contains("WHERE `uuid` = 'job' AND `state` = 'queued'"));
+    }
+}
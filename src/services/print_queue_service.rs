@@ -0,0 +1,388 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_query::{Expr, MysqlQueryBuilder, Order, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::common::app_error::AppError;
+use crate::infra::filestorage::FileStorages;
+use crate::models::print_queue::{
+    PrintQueue, PrintQueueDbModel, PrintQueueFilter, PrintQueueRequest,
+};
+use crate::services::printer_service::{PrinterService, PrinterServiceImpl};
+use crate::services::printfile_service::{PrintFileService, PrintFileServiceImpl};
+
+#[async_trait]
+pub trait PrintQueueService {
+    async fn add(
+        &self,
+        user_uuid: &str,
+        request: PrintQueueRequest,
+    ) -> Result<PrintQueueDbModel, AppError>;
+    async fn move_to(
+        &self,
+        user_uuid: &str,
+        uuid: &str,
+        position: u32,
+    ) -> Result<Vec<PrintQueueDbModel>, AppError>;
+    async fn delete(&self, user_uuid: &str, uuid: &str) -> Result<bool, AppError>;
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+        filter: PrintQueueFilter,
+    ) -> Result<Vec<PrintQueueDbModel>, AppError>;
+    async fn get_next(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<Option<PrintQueueDbModel>, AppError>;
+}
+
+pub struct PrintQueueServiceImpl {
+    pool: Arc<Pool<MySql>>,
+    file_storages: Arc<FileStorages>,
+}
+
+impl PrintQueueServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>, file_storages: Arc<FileStorages>) -> Self {
+        PrintQueueServiceImpl {
+            pool,
+            file_storages,
+        }
+    }
+}
+
+const PRINT_QUEUE_SELECT_COLUMNS: [PrintQueue; 6] = [
+    PrintQueue::Uuid,
+    PrintQueue::UserUuid,
+    PrintQueue::PrintFileUuid,
+    PrintQueue::PrinterUuids,
+    PrintQueue::Position,
+    PrintQueue::CreatedAt,
+];
+
+#[async_trait]
+impl PrintQueueService for PrintQueueServiceImpl {
+    /// Appends the print file to the end of the queue, the user has to own the file and all printers
+    async fn add(
+        &self,
+        user_uuid: &str,
+        request: PrintQueueRequest,
+    ) -> Result<PrintQueueDbModel, AppError> {
+        let mut printer_uuids: Vec<String> = Vec::new();
+        for printer_uuid in request.printer_uuids {
+            let printer_uuid = printer_uuid.trim().to_string();
+            if !printer_uuid.is_empty() && !printer_uuids.contains(&printer_uuid) {
+                printer_uuids.push(printer_uuid);
+            }
+        }
+        if printer_uuids.is_empty() {
+            return Err(AppError::Validation {
+                messages: "At least one printer is required".to_string(),
+                status: StatusCode::BAD_REQUEST,
+            });
+        }
+
+        let printfile_service =
+            PrintFileServiceImpl::new(self.pool.clone(), self.file_storages.clone());
+        printfile_service
+            .get_by_uuid(user_uuid, &request.print_file_uuid)
+            .await?;
+
+        let printer_service = PrinterServiceImpl::new(self.pool.clone());
+        for printer_uuid in &printer_uuids {
+            printer_service.get_by_uuid(user_uuid, printer_uuid).await?;
+        }
+
+        let sql = Query::select()
+            .expr(Expr::col(PrintQueue::Position).max())
+            .from(PrintQueue::Table)
+            .and_where(Expr::col(PrintQueue::UserUuid).eq(user_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let last_position: Option<i32> = match sqlx::query_scalar(&sql).fetch_one(&*self.pool).await
+        {
+            Ok(last_position) => last_position,
+            Err(e) => {
+                error!("Error retrieving print queue position: {}", e);
+                return Err(AppError::InternalServer);
+            }
+        };
+
+        let entry = PrintQueueDbModel {
+            uuid: Uuid::new_v4().to_string(),
+            user_uuid: user_uuid.to_string(),
+            print_file_uuid: request.print_file_uuid,
+            printer_uuids: printer_uuids.join(","),
+            position: last_position.map_or(0, |position| position + 1),
+            created_at: Utc::now().timestamp().to_string(),
+        };
+
+        let sql = Query::insert()
+            .into_table(PrintQueue::Table)
+            .columns(PRINT_QUEUE_SELECT_COLUMNS)
+            .values_panic([
+                entry.uuid.to_string().into(),
+                entry.user_uuid.to_string().into(),
+                entry.print_file_uuid.to_string().into(),
+                entry.printer_uuids.to_string().into(),
+                entry.position.into(),
+                entry.created_at.to_string().into(),
+            ])
+            .to_string(MysqlQueryBuilder)
+            .to_owned();
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(entry),
+            Err(e) => {
+                error!("Error inserting print queue entry: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Moves the entry to the given position, returns the reordered queue
+    async fn move_to(
+        &self,
+        user_uuid: &str,
+        uuid: &str,
+        position: u32,
+    ) -> Result<Vec<PrintQueueDbModel>, AppError> {
+        let entries = self.get_all(user_uuid, PrintQueueFilter::default()).await?;
+        let uuids = entries
+            .iter()
+            .map(|entry| entry.uuid.to_string())
+            .collect::<Vec<String>>();
+
+        let uuids = match reorder(uuids, uuid, position as usize) {
+            Some(uuids) => uuids,
+            None => {
+                return Err(AppError::PrintJob {
+                    message: "Queue entry not found".to_string(),
+                    status: StatusCode::NOT_FOUND,
+                })
+            }
+        };
+
+        let mut conn = self.pool.acquire().await.unwrap();
+        let mut reordered: Vec<PrintQueueDbModel> = Vec::new();
+        for (position, uuid) in uuids.iter().enumerate() {
+            let mut entry = entries
+                .iter()
+                .find(|entry| &entry.uuid == uuid)
+                .cloned()
+                .expect("Reordered queue entry is missing");
+
+            if entry.position != position as i32 {
+                entry.position = position as i32;
+                let sql = Query::update()
+                    .table(PrintQueue::Table)
+                    .values([(PrintQueue::Position, entry.position.into())])
+                    .and_where(Expr::col(PrintQueue::UserUuid).eq(user_uuid))
+                    .and_where(Expr::col(PrintQueue::Uuid).eq(uuid))
+                    .to_string(MysqlQueryBuilder);
+
+                if let Err(e) = conn.execute(&*sql).await {
+                    error!("Error updating print queue position: {}", e);
+                    return Err(AppError::InternalServer);
+                }
+            }
+            reordered.push(entry);
+        }
+
+        Ok(reordered)
+    }
+
+    async fn delete(&self, user_uuid: &str, uuid: &str) -> Result<bool, AppError> {
+        let sql = Query::delete()
+            .from_table(PrintQueue::Table)
+            .and_where(Expr::col(PrintQueue::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(PrintQueue::Uuid).eq(uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(res) => {
+                if (res.rows_affected() as i32) > 0 {
+                    return Ok(true);
+                }
+                Err(AppError::PrintJob {
+                    message: "Queue entry not found".to_string(),
+                    status: StatusCode::NOT_FOUND,
+                })
+            }
+            Err(e) => {
+                error!("Error deleting print queue entry: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Retrieves the queue of the user in order, optionally only the entries a printer may print
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+        filter: PrintQueueFilter,
+    ) -> Result<Vec<PrintQueueDbModel>, AppError> {
+        let sql = select_queue_sql(user_uuid, filter.printer_uuid.as_deref(), None);
+
+        let row = sqlx::query(&sql).fetch_all(&*self.pool).await.unwrap();
+
+        if row.is_empty() {
+            return Err(AppError::PrintJob {
+                message: "Print queue is empty".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        let mut entries: Vec<PrintQueueDbModel> = Vec::new();
+        for row in row {
+            let entry = PrintQueueDbModel::from_row(&row)
+                .expect("Error converting row to PrintQueueDbModel");
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    /// Retrieves the first entry of the queue the printer may print
+    async fn get_next(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<Option<PrintQueueDbModel>, AppError> {
+        let sql = select_queue_sql(user_uuid, Some(printer_uuid), Some(1));
+
+        match sqlx::query(&sql).fetch_optional(&*self.pool).await {
+            Ok(row) => Ok(row.map(|row| {
+                PrintQueueDbModel::from_row(&row)
+                    .expect("Error converting row to PrintQueueDbModel")
+            })),
+            Err(e) => {
+                error!("Error retrieving next print queue entry: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
+
+/// Removes the printer from the queued print files of the user, used when the printer is deleted.
+/// Entries that no other printer may print are deleted
+pub async fn remove_printer(
+    pool: &Pool<MySql>,
+    user_uuid: &str,
+    printer_uuid: &str,
+) -> Result<(), AppError> {
+    let sql = select_queue_sql(user_uuid, Some(printer_uuid), None);
+    let rows = match sqlx::query(&sql).fetch_all(pool).await {
+        Ok(rows) => rows,
+        Err(e) => {
+            error!("Error retrieving print queue of printer: {}", e);
+            return Err(AppError::InternalServer);
+        }
+    };
+
+    let mut conn = pool.acquire().await.unwrap();
+    for row in rows {
+        let entry =
+            PrintQueueDbModel::from_row(&row).expect("Error converting row to PrintQueueDbModel");
+        let printer_uuids = entry
+            .printer_uuids()
+            .into_iter()
+            .filter(|uuid| uuid != printer_uuid)
+            .collect::<Vec<String>>();
+
+        let sql = match printer_uuids.is_empty() {
+            true => Query::delete()
+                .from_table(PrintQueue::Table)
+                .and_where(Expr::col(PrintQueue::Uuid).eq(&entry.uuid))
+                .to_string(MysqlQueryBuilder),
+            false => Query::update()
+                .table(PrintQueue::Table)
+                .values([(PrintQueue::PrinterUuids, printer_uuids.join(",").into())])
+                .and_where(Expr::col(PrintQueue::Uuid).eq(&entry.uuid))
+                .to_string(MysqlQueryBuilder),
+        };
+        if let Err(e) = conn.execute(&*sql).await {
+            error!("Error removing printer from print queue entry: {}", e);
+            return Err(AppError::InternalServer);
+        }
+    }
+
+    Ok(())
+}
+
+fn select_queue_sql(user_uuid: &str, printer_uuid: Option<&str>, limit: Option<u64>) -> String {
+    let mut query = Query::select();
+    query
+        .columns(PRINT_QUEUE_SELECT_COLUMNS)
+        .from(PrintQueue::Table)
+        .and_where(Expr::col(PrintQueue::UserUuid).eq(user_uuid));
+
+    if let Some(printer_uuid) = printer_uuid {
+        query.and_where(Expr::cust_with_values(
+            "FIND_IN_SET(?, `printer_uuids`) > 0",
+            [printer_uuid],
+        ));
+    }
+    if let Some(limit) = limit {
+        query.limit(limit);
+    }
+
+    query
+        .order_by(PrintQueue::Position, Order::Asc)
+        .order_by(PrintQueue::CreatedAt, Order::Asc)
+        .to_string(MysqlQueryBuilder)
+}
+
+/// Moves the uuid to the position in the list, positions past the end move it to the back.
+/// Returns None if the uuid is not in the list
+fn reorder(mut uuids: Vec<String>, uuid: &str, position: usize) -> Option<Vec<String>> {
+    let index = uuids.iter().position(|queued| queued == uuid)?;
+    let uuid = uuids.remove(index);
+    let position = position.min(uuids.len());
+    uuids.insert(position, uuid);
+    Some(uuids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uuids(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_reorder() {
+        let queue = uuids(&["a", "b", "c", "d"]);
+        assert_eq!(
+            reorder(queue.clone(), "c", 0),
+            Some(uuids(&["c", "a", "b", "d"]))
+        );
+        assert_eq!(
+            reorder(queue.clone(), "a", 2),
+            Some(uuids(&["b", "c", "a", "d"]))
+        );
+        assert_eq!(
+            reorder(queue.clone(), "b", 99),
+            Some(uuids(&["a", "c", "d", "b"]))
+        );
+        assert_eq!(reorder(queue, "x", 0), None);
+    }
+
+    #[test]
+    fn test_select_queue_sql_for_printer() {
+        let sql = select_queue_sql("user", Some("printer"), Some(1));
+        assert!(sql.contains("FIND_IN_SET('printer', `printer_uuids`) > 0"));
+        assert!(sql.contains("ORDER BY `position` ASC"));
+        assert!(sql.ends_with("LIMIT 1"));
+    }
+}
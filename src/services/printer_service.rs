@@ -0,0 +1,540 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_query::{Expr, MysqlQueryBuilder, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::common::app_error::AppError;
+use crate::models::print_job::PrintJobState;
+use crate::models::printer::{Printer, PrinterDbModel, PrinterRequest};
+use crate::services::agent_service::{AgentService, AgentServiceImpl};
+use crate::services::print_job_service::{PrintJobService, PrintJobServiceImpl};
+use crate::services::print_queue_service::remove_printer;
+
+#[async_trait]
+pub trait PrinterService {
+    async fn add(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printer: PrinterRequest,
+    ) -> Result<PrinterDbModel, AppError>;
+    async fn update(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+        printer: PrinterRequest,
+    ) -> Result<PrinterDbModel, AppError>;
+    async fn delete(&self, user_uuid: &str, printer_uuid: &str) -> Result<bool, AppError>;
+    async fn delete_by_agent(&self, user_uuid: &str, agent_uuid: &str) -> Result<(), AppError>;
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+    ) -> Result<Vec<PrinterDbModel>, AppError>;
+    async fn get_by_uuid(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<PrinterDbModel, AppError>;
+    async fn get_by_agent(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<PrinterDbModel, AppError>;
+    async fn report(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printers: Vec<PrinterRequest>,
+    ) -> Result<Vec<PrinterDbModel>, AppError>;
+    async fn set_bed_cleared(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+        bed_cleared: bool,
+    ) -> Result<PrinterDbModel, AppError>;
+}
+
+pub struct PrinterServiceImpl {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl PrinterServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        PrinterServiceImpl { pool }
+    }
+}
+
+const PRINTER_SELECT_COLUMNS: [Printer; 8] = [
+    Printer::Uuid,
+    Printer::UserUuid,
+    Printer::AgentUuid,
+    Printer::Identifier,
+    Printer::AdapterIdentifier,
+    Printer::AdapterInterface,
+    Printer::BedCleared,
+    Printer::CreatedAt,
+];
+
+#[async_trait]
+impl PrinterService for PrinterServiceImpl {
+    async fn add(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printer: PrinterRequest,
+    ) -> Result<PrinterDbModel, AppError> {
+        let printer = validate_printer(printer)?;
+
+        let agent_service = AgentServiceImpl::new(self.pool.clone());
+        agent_service.get_by_uuid(user_uuid, agent_uuid).await?;
+
+        insert_printer(self.pool.clone(), user_uuid, agent_uuid, printer).await
+    }
+
+    async fn update(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+        printer: PrinterRequest,
+    ) -> Result<PrinterDbModel, AppError> {
+        let printer = validate_printer(printer)?;
+        let mut printer_model = self.get_by_uuid(user_uuid, printer_uuid).await?;
+
+        let sql = Query::update()
+            .table(Printer::Table)
+            .values([
+                (Printer::Identifier, printer.identifier.to_string().into()),
+                (
+                    Printer::AdapterIdentifier,
+                    printer.adapter_identifier.to_string().into(),
+                ),
+                (
+                    Printer::AdapterInterface,
+                    printer.adapter_interface.to_string().into(),
+                ),
+            ])
+            .and_where(Expr::col(Printer::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Printer::Uuid).eq(printer_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => {
+                printer_model.identifier = printer.identifier;
+                printer_model.adapter_identifier = printer.adapter_identifier;
+                printer_model.adapter_interface = printer.adapter_interface;
+                Ok(printer_model)
+            }
+            Err(e) if is_unique_violation(&e) => Err(adapter_taken()),
+            Err(e) => {
+                error!("Error updating printer: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Deletes the printer and removes it from the print queue,
+    /// a printer with an active print job can't be deleted
+    async fn delete(&self, user_uuid: &str, printer_uuid: &str) -> Result<bool, AppError> {
+        let print_job_service = PrintJobServiceImpl::new(self.pool.clone());
+        if let Some(active) = print_job_service
+            .get_active(user_uuid, printer_uuid)
+            .await?
+        {
+            return Err(AppError::Printer {
+                message: format!("Printer is running print job {}", active.uuid),
+                status: StatusCode::CONFLICT,
+            });
+        }
+
+        let sql = Query::delete()
+            .from_table(Printer::Table)
+            .and_where(Expr::col(Printer::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Printer::Uuid).eq(printer_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(res) => {
+                if (res.rows_affected() as i32) > 0 {
+                    remove_printer(&self.pool, user_uuid, printer_uuid).await?;
+                    return Ok(true);
+                }
+                Err(AppError::Printer {
+                    message: "Printer not found".to_string(),
+                    status: StatusCode::NOT_FOUND,
+                })
+            }
+            Err(e) => {
+                error!("Error deleting printer: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Deletes all printers of an agent, used when the agent itself is deleted.
+    /// Their active print jobs are cancelled and they are removed from the print queue
+    async fn delete_by_agent(&self, user_uuid: &str, agent_uuid: &str) -> Result<(), AppError> {
+        let printers = match self.get_all(user_uuid, agent_uuid).await {
+            Ok(printers) => printers,
+            Err(AppError::Printer { .. }) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let print_job_service = PrintJobServiceImpl::new(self.pool.clone());
+        for printer in &printers {
+            if let Some(active) = print_job_service
+                .get_active(user_uuid, &printer.uuid)
+                .await?
+            {
+                print_job_service
+                    .transition(active, PrintJobState::Cancelled, None)
+                    .await?;
+            }
+            remove_printer(&self.pool, user_uuid, &printer.uuid).await?;
+        }
+
+        let sql = Query::delete()
+            .from_table(Printer::Table)
+            .and_where(Expr::col(Printer::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Printer::AgentUuid).eq(agent_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error deleting printers of agent: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+    ) -> Result<Vec<PrinterDbModel>, AppError> {
+        let sql = Query::select()
+            .columns(PRINTER_SELECT_COLUMNS)
+            .from(Printer::Table)
+            .and_where(Expr::col(Printer::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Printer::AgentUuid).eq(agent_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let row = sqlx::query(&sql).fetch_all(&*self.pool).await.unwrap();
+
+        if row.is_empty() {
+            return Err(AppError::Printer {
+                message: "No printers found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        let mut printers: Vec<PrinterDbModel> = Vec::new();
+        for row in row {
+            let printer =
+                PrinterDbModel::from_row(&row).expect("Error converting row to PrinterDbModel");
+            printers.push(printer);
+        }
+
+        Ok(printers)
+    }
+
+    async fn get_by_uuid(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<PrinterDbModel, AppError> {
+        let sql = Query::select()
+            .columns(PRINTER_SELECT_COLUMNS)
+            .from(Printer::Table)
+            .and_where(Expr::col(Printer::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Printer::Uuid).eq(printer_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let row = sqlx::query(&sql).fetch_optional(&*self.pool).await.unwrap();
+
+        if row.is_none() {
+            return Err(AppError::Printer {
+                message: "Printer not found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        let row = row.expect("Error unwrapping row");
+        let printer =
+            PrinterDbModel::from_row(&row).expect("Error converting row to PrinterDbModel");
+
+        Ok(printer)
+    }
+
+    /// Retrieves the printer, it has to be connected to the given agent
+    async fn get_by_agent(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printer_uuid: &str,
+    ) -> Result<PrinterDbModel, AppError> {
+        let printer = self.get_by_uuid(user_uuid, printer_uuid).await?;
+
+        if printer.agent_uuid != agent_uuid {
+            return Err(AppError::Printer {
+                message: "Printer not found".to_string(),
+                status: StatusCode::NOT_FOUND,
+            });
+        }
+
+        Ok(printer)
+    }
+
+    /// Stores the printers discovered by an agent, printers are matched on their adapter.
+    /// Returns the stored printers, including the ones that already existed
+    async fn report(
+        &self,
+        user_uuid: &str,
+        agent_uuid: &str,
+        printers: Vec<PrinterRequest>,
+    ) -> Result<Vec<PrinterDbModel>, AppError> {
+        let existing = match self.get_all(user_uuid, agent_uuid).await {
+            Ok(existing) => existing,
+            Err(AppError::Printer { .. }) => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut reported: Vec<PrinterDbModel> = Vec::new();
+        for printer in validate_reported_printers(printers)? {
+            let known = existing.iter().find(|known| {
+                known.adapter_identifier == printer.adapter_identifier
+                    && known.adapter_interface == printer.adapter_interface
+            });
+
+            match known {
+                Some(known) => reported.push(known.clone()),
+                None => {
+                    let adapter_identifier = printer.adapter_identifier.to_string();
+                    let adapter_interface = printer.adapter_interface.to_string();
+                    match insert_printer(self.pool.clone(), user_uuid, agent_uuid, printer).await {
+                        Ok(printer) => {
+                            info!(
+                                "agent {} discovered printer {} ({})",
+                                agent_uuid, printer.identifier, printer.uuid
+                            );
+                            reported.push(printer);
+                        }
+                        // a concurrent discovery stored the printer first
+                        Err(AppError::Printer {
+                            status: StatusCode::CONFLICT,
+                            ..
+                        }) => {
+                            let stored = self.get_all(user_uuid, agent_uuid).await?;
+                            reported.extend(stored.into_iter().find(|known| {
+                                known.adapter_identifier == adapter_identifier
+                                    && known.adapter_interface == adapter_interface
+                            }));
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+
+        Ok(reported)
+    }
+
+    /// Marks the bed of the printer as cleared or occupied by a finished print
+    async fn set_bed_cleared(
+        &self,
+        user_uuid: &str,
+        printer_uuid: &str,
+        bed_cleared: bool,
+    ) -> Result<PrinterDbModel, AppError> {
+        let mut printer = self.get_by_uuid(user_uuid, printer_uuid).await?;
+
+        let sql = Query::update()
+            .table(Printer::Table)
+            .values([(Printer::BedCleared, bed_cleared.into())])
+            .and_where(Expr::col(Printer::UserUuid).eq(user_uuid))
+            .and_where(Expr::col(Printer::Uuid).eq(printer_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => {
+                printer.bed_cleared = bed_cleared;
+                Ok(printer)
+            }
+            Err(e) => {
+                error!("Error updating bed of printer: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
+
+/// Validates the printer request and normalizes the adapter identifier, e.g. serial -> SERIAL
+fn validate_printer(printer: PrinterRequest) -> Result<PrinterRequest, AppError> {
+    let mut messages: Vec<&str> = Vec::new();
+
+    if printer.identifier.trim().is_empty() {
+        messages.push("Printer identifier cannot be empty");
+    }
+    if printer.adapter_identifier.trim().is_empty() {
+        messages.push("Printer adapter identifier cannot be empty");
+    }
+    if printer.adapter_interface.trim().is_empty() {
+        messages.push("Printer adapter interface cannot be empty");
+    }
+
+    if !messages.is_empty() {
+        return Err(AppError::Validation {
+            messages: messages.join(", "),
+            status: StatusCode::BAD_REQUEST,
+        });
+    }
+
+    Ok(PrinterRequest {
+        identifier: printer.identifier.trim().to_string(),
+        adapter_identifier: printer.adapter_identifier.trim().to_uppercase(),
+        adapter_interface: printer.adapter_interface.trim().to_string(),
+    })
+}
+
+/// Validates the printers of a discovery, a printer listed twice on the same adapter is stored once
+fn validate_reported_printers(
+    printers: Vec<PrinterRequest>,
+) -> Result<Vec<PrinterRequest>, AppError> {
+    let mut validated: Vec<PrinterRequest> = Vec::new();
+    for printer in printers {
+        let printer = validate_printer(printer)?;
+        let duplicate = validated.iter().any(|known| {
+            known.adapter_identifier == printer.adapter_identifier
+                && known.adapter_interface == printer.adapter_interface
+        });
+        if !duplicate {
+            validated.push(printer);
+        }
+    }
+    Ok(validated)
+}
+
+/// Printers are unique per agent and adapter
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+fn adapter_taken() -> AppError {
+    AppError::Printer {
+        message: "A printer is already connected to this adapter".to_string(),
+        status: StatusCode::CONFLICT,
+    }
+}
+
+async fn insert_printer(
+    pool: Arc<Pool<MySql>>,
+    user_uuid: &str,
+    agent_uuid: &str,
+    printer: PrinterRequest,
+) -> Result<PrinterDbModel, AppError> {
+    let printer_model = PrinterDbModel {
+        uuid: Uuid::new_v4().to_string(),
+        user_uuid: user_uuid.to_string(),
+        agent_uuid: agent_uuid.to_string(),
+        identifier: printer.identifier,
+        adapter_identifier: printer.adapter_identifier,
+        adapter_interface: printer.adapter_interface,
+        bed_cleared: true,
+        created_at: Utc::now().timestamp().to_string(),
+    };
+
+    let sql = Query::insert()
+        .into_table(Printer::Table)
+        .columns(PRINTER_SELECT_COLUMNS)
+        .values_panic([
+            printer_model.uuid.to_string().into(),
+            printer_model.user_uuid.to_string().into(),
+            printer_model.agent_uuid.to_string().into(),
+            printer_model.identifier.to_string().into(),
+            printer_model.adapter_identifier.to_string().into(),
+            printer_model.adapter_interface.to_string().into(),
+            printer_model.bed_cleared.into(),
+            printer_model.created_at.to_string().into(),
+        ])
+        .to_string(MysqlQueryBuilder)
+        .to_owned();
+
+    let mut conn = pool.acquire().await.unwrap();
+
+    match conn.execute(&*sql).await {
+        Ok(_) => Ok(printer_model),
+        Err(e) if is_unique_violation(&e) => Err(adapter_taken()),
+        Err(e) => {
+            error!("Error inserting printer: {}", e);
+            Err(AppError::InternalServer)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_printer() {
+        let printer = validate_printer(PrinterRequest {
+            identifier: " Prusa MK4 ".to_string(),
+            adapter_identifier: "serial".to_string(),
+            adapter_interface: "/dev/ttyUSB0".to_string(),
+        })
+        .unwrap();
+        assert_eq!(printer.identifier, "Prusa MK4");
+        assert_eq!(printer.adapter_identifier, "SERIAL");
+    }
+
+    #[test]
+    fn test_validate_reported_printers_duplicates() {
+        let printer =
+            |identifier: &str, adapter_identifier: &str, adapter_interface: &str| PrinterRequest {
+                identifier: identifier.to_string(),
+                adapter_identifier: adapter_identifier.to_string(),
+                adapter_interface: adapter_interface.to_string(),
+            };
+        let printers = validate_reported_printers(vec![
+            printer("Prusa MK4", "SERIAL", "/dev/ttyUSB0"),
+            printer("Prusa MK4 (again)", "serial", "/dev/ttyUSB0"),
+            printer("Ender 3", "SERIAL", "/dev/ttyUSB1"),
+        ])
+        .unwrap();
+
+        let identifiers = printers
+            .iter()
+            .map(|printer| printer.identifier.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(identifiers, ["Prusa MK4", "Ender 3"]);
+    }
+
+    #[test]
+    fn test_validate_printer_empty_fields() {
+        let result = validate_printer(PrinterRequest {
+            identifier: "".to_string(),
+            adapter_identifier: "SERIAL".to_string(),
+            adapter_interface: " ".to_string(),
+        });
+        let err = result.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Printer identifier cannot be empty, Printer adapter interface cannot be empty"
+        );
+    }
+}
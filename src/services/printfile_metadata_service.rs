@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::async_trait;
+use sea_query::{Expr, MysqlQueryBuilder, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::error;
+
+use crate::common::app_error::AppError;
+use crate::models::printfile::PrintFile;
+use crate::models::printfile_metadata::{PrintFileMetadata, PrintFileMetadataDbModel};
+
+#[async_trait]
+pub trait PrintFileMetadataService {
+    async fn add(&self, metadata: &PrintFileMetadataDbModel) -> Result<(), AppError>;
+    async fn get_by_printfile(
+        &self,
+        print_file_uuid: &str,
+    ) -> Result<Option<PrintFileMetadataDbModel>, AppError>;
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+    ) -> Result<HashMap<String, PrintFileMetadataDbModel>, AppError>;
+    async fn delete(&self, print_file_uuid: &str) -> Result<(), AppError>;
+}
+
+pub struct PrintFileMetadataServiceImpl {
+    pool: Arc<Pool<MySql>>,
+}
+
+impl PrintFileMetadataServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
+        PrintFileMetadataServiceImpl { pool }
+    }
+}
+
+const METADATA_COLUMNS: [PrintFileMetadata; 20] = [
+    PrintFileMetadata::PrintFileUuid,
+    PrintFileMetadata::Slicer,
+    PrintFileMetadata::SlicerVersion,
+    PrintFileMetadata::EstimatedTime,
+    PrintFileMetadata::FilamentLength,
+    PrintFileMetadata::FilamentWeight,
+    PrintFileMetadata::LayerHeight,
+    PrintFileMetadata::LayerCount,
+    PrintFileMetadata::NozzleTemperature,
+    PrintFileMetadata::BedTemperature,
+    PrintFileMetadata::MinX,
+    PrintFileMetadata::MinY,
+    PrintFileMetadata::MinZ,
+    PrintFileMetadata::MaxX,
+    PrintFileMetadata::MaxY,
+    PrintFileMetadata::MaxZ,
+    PrintFileMetadata::TriangleCount,
+    PrintFileMetadata::Volume,
+    PrintFileMetadata::SurfaceArea,
+    PrintFileMetadata::Watertight,
+];
+
+#[async_trait]
+impl PrintFileMetadataService for PrintFileMetadataServiceImpl {
+    async fn add(&self, metadata: &PrintFileMetadataDbModel) -> Result<(), AppError> {
+        let sql = Query::insert()
+            .into_table(PrintFileMetadata::Table)
+            .columns(METADATA_COLUMNS)
+            .values_panic([
+                metadata.print_file_uuid.to_string().into(),
+                metadata.slicer.clone().into(),
+                metadata.slicer_version.clone().into(),
+                metadata.estimated_time.into(),
+                metadata.filament_length.to_string().into(),
+                metadata.filament_weight.to_string().into(),
+                metadata.layer_height.into(),
+                metadata.layer_count.into(),
+                metadata.nozzle_temperature.into(),
+                metadata.bed_temperature.into(),
+                metadata.min_x.into(),
+                metadata.min_y.into(),
+                metadata.min_z.into(),
+                metadata.max_x.into(),
+                metadata.max_y.into(),
+                metadata.max_z.into(),
+                metadata.triangle_count.into(),
+                metadata.volume.into(),
+                metadata.surface_area.into(),
+                metadata.watertight.into(),
+            ])
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error inserting printfile metadata: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    async fn get_by_printfile(
+        &self,
+        print_file_uuid: &str,
+    ) -> Result<Option<PrintFileMetadataDbModel>, AppError> {
+        let sql = Query::select()
+            .columns(METADATA_COLUMNS)
+            .from(PrintFileMetadata::Table)
+            .and_where(Expr::col(PrintFileMetadata::PrintFileUuid).eq(print_file_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        match sqlx::query(&sql).fetch_optional(&*self.pool).await {
+            Ok(row) => Ok(row.map(|row| {
+                PrintFileMetadataDbModel::from_row(&row)
+                    .expect("Error converting row to PrintFileMetadataDbModel")
+            })),
+            Err(e) => {
+                error!("Error retrieving printfile metadata: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Retrieves the metadata of all print files of the user, keyed by print file uuid
+    async fn get_all(
+        &self,
+        user_uuid: &str,
+    ) -> Result<HashMap<String, PrintFileMetadataDbModel>, AppError> {
+        let sql = Query::select()
+            .columns(METADATA_COLUMNS.map(|column| (PrintFileMetadata::Table, column)))
+            .from(PrintFileMetadata::Table)
+            .inner_join(
+                PrintFile::Table,
+                Expr::col((PrintFile::Table, PrintFile::Uuid))
+                    .equals((PrintFileMetadata::Table, PrintFileMetadata::PrintFileUuid)),
+            )
+            .and_where(Expr::col((PrintFile::Table, PrintFile::UserUuid)).eq(user_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let rows = match sqlx::query(&sql).fetch_all(&*self.pool).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Error retrieving printfile metadata: {}", e);
+                return Err(AppError::InternalServer);
+            }
+        };
+
+        let mut metadata = HashMap::new();
+        for row in rows {
+            let row = PrintFileMetadataDbModel::from_row(&row)
+                .expect("Error converting row to PrintFileMetadataDbModel");
+            metadata.insert(row.print_file_uuid.to_string(), row);
+        }
+
+        Ok(metadata)
+    }
+
+    async fn delete(&self, print_file_uuid: &str) -> Result<(), AppError> {
+        let sql = Query::delete()
+            .from_table(PrintFileMetadata::Table)
+            .and_where(Expr::col(PrintFileMetadata::PrintFileUuid).eq(print_file_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error deleting printfile metadata: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
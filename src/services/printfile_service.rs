@@ -1,19 +1,46 @@
-use std::env;
+use std::io;
 use std::sync::Arc;
 
 use axum::async_trait;
+use axum::extract::multipart::Field;
 use axum::extract::Multipart;
 use axum::http::StatusCode;
 use chrono::Utc;
+use futures_util::StreamExt;
 use sea_query::{Expr, MysqlQueryBuilder, Query};
-use sha2::Digest;
+use sha2::{Digest, Sha256};
 use sqlx::{Executor, FromRow, MySql, Pool};
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::common::app_error::AppError;
-use crate::infra::filestorage::{retrieve_file, store_file};
-use crate::models::printfile::{FileType, PrintFile, PrintFileDbModel};
+use crate::common::download::ByteRange;
+use crate::common::gcode::{GcodeMetadata, GcodeScanner, GcodeThumbnail};
+use crate::common::mesh::{parse_3mf_model, Mesh, MeshAnalysis, MeshReader, THREEMF_MODEL_PATH};
+use crate::common::zip::{
+    central_directory, data_offset, decompress, find_entry, parse_central_directory,
+    END_SEARCH_SIZE, LOCAL_HEADER_SIZE,
+};
+use crate::infra::filestorage::FileStorages;
+use crate::infra::strategies::file_storage_strategy::{ByteStream, FileStorageStrategy};
+use crate::models::file_type_policy::FileTypePolicyDbModel;
+use crate::models::printfile::{
+    storage_filename, FileSniffer, FileType, PrintFile, PrintFileDbModel, MAX_UPLOAD_SIZE,
+};
+use crate::models::printfile_metadata::PrintFileMetadataDbModel;
+use crate::services::file_type_policy_service::{FileTypePolicyService, FileTypePolicyServiceImpl};
+use crate::services::printfile_metadata_service::{
+    PrintFileMetadataService, PrintFileMetadataServiceImpl,
+};
+use crate::services::printfile_thumbnail_service::{
+    PrintFileThumbnailService, PrintFileThumbnailServiceImpl,
+};
+use crate::services::storage_deletion_service::{
+    StorageDeletionService, StorageDeletionServiceImpl,
+};
+
+/// Bytes of a mesh file handed to the mesh reader at once
+const MESH_BATCH_SIZE: usize = 1024 * 1024;
 
 #[async_trait]
 pub trait PrintFileService {
@@ -29,16 +56,41 @@ pub trait PrintFileService {
         user_uuid: &str,
         file_uuid: &str,
     ) -> Result<PrintFileDbModel, AppError>;
-    async fn download(&self, user_uuid: &str, file_uuid: &str) -> Result<Vec<u8>, AppError>;
+    async fn download_stream(
+        &self,
+        printfile: &PrintFileDbModel,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream<'static>, AppError>;
+    async fn get_by_storage_type(
+        &self,
+        file_storage_type: &str,
+    ) -> Result<Vec<PrintFileDbModel>, AppError>;
+    async fn update_storage(
+        &self,
+        file_uuid: &str,
+        file_storage_type: &str,
+        path: &str,
+    ) -> Result<(), AppError>;
 }
 
 pub struct PrintFileServiceImpl {
     pool: Arc<Pool<MySql>>,
+    file_storages: Arc<FileStorages>,
 }
 
 impl PrintFileServiceImpl {
-    pub fn new(pool: Arc<Pool<MySql>>) -> Self {
-        PrintFileServiceImpl { pool }
+    pub fn new(pool: Arc<Pool<MySql>>, file_storages: Arc<FileStorages>) -> Self {
+        PrintFileServiceImpl {
+            pool,
+            file_storages,
+        }
+    }
+
+    /// Removes a stored upload that no print file row points to
+    async fn discard(&self, file_storage_type: &str, filepath: &str) -> Result<(), AppError> {
+        StorageDeletionServiceImpl::new(self.pool.clone(), self.file_storages.clone())
+            .delete_file(file_storage_type, filepath)
+            .await
     }
 }
 
@@ -56,34 +108,121 @@ const PRINTFILE_SELECT_COLUMNS: [PrintFile; 9] = [
 
 #[async_trait]
 impl PrintFileService for PrintFileServiceImpl {
+    /// Streams the files of the multipart body to the file storage, the type, checksum,
+    /// size and metadata are collected while the chunks pass through
     async fn upload(
         &self,
         user_uuid: &str,
         mut multipart_file: Multipart,
     ) -> Result<PrintFileDbModel, AppError> {
+        let file_storage = self.file_storages.current();
+        let policy = FileTypePolicyServiceImpl::new(self.pool.clone())
+            .get(user_uuid)
+            .await?;
         let mut printfile = None;
-        while let Some(field) = multipart_file.next_field().await.unwrap() {
-            let filename = &field.file_name().unwrap().to_string();
-            let data = &field.bytes().await.unwrap();
-            let filesize = data.len() as i32;
-            let filepath = store_file(user_uuid, filename, data).await?;
-            let sha256 = format!("{:x}", sha2::Sha256::digest(data));
+        while let Some(field) = next_field(&mut multipart_file).await? {
+            let filename = &match field.file_name() {
+                Some(filename) => filename.to_string(),
+                None => {
+                    return Err(AppError::Validation {
+                        messages: "File name is missing".to_string(),
+                        status: StatusCode::BAD_REQUEST,
+                    })
+                }
+            };
+            // every upload gets its own key, so an upload with the name of an existing file
+            // can't overwrite it and discarding the upload only removes what it wrote.
+            // The name as uploaded is only recorded with the print file
+            let uuid = Uuid::new_v4().to_string();
+            let key = match storage_filename(&uuid, filename) {
+                Some(key) => key,
+                None => {
+                    return Err(AppError::Validation {
+                        messages: "File name is invalid".to_string(),
+                        status: StatusCode::BAD_REQUEST,
+                    })
+                }
+            };
+
+            let mut inspector = UploadInspector::new(&policy, filename);
+            let stream = field.map(|chunk| {
+                let chunk = chunk.map_err(io::Error::other)?;
+                inspector.feed(&chunk)?;
+                Ok(chunk)
+            });
+            let written = file_storage
+                .write_stream(user_uuid, &key, Box::pin(stream))
+                .await;
+            // the storage removes what it wrote when the stream is aborted
+            if let Some(e) = inspector.rejected.take() {
+                return Err(e);
+            }
+            let filepath = written?;
+            let upload = inspector.finish();
+
+            // small files are only recognized once the stream has ended
+            if let Err(e) = policy.check(upload.file_type) {
+                self.discard(file_storage.storage_type(), &filepath).await?;
+                return Err(e);
+            }
 
             info!(
                 "success, filepath: {}, sha256 checksum: {}, size: {}",
-                filepath, sha256, filesize
+                filepath, upload.sha256, upload.size
             );
-            printfile = Some(
-                insert_printfile(
+            let inserted = match insert_printfile(
+                self.pool.clone(),
+                &uuid,
+                user_uuid,
+                filename,
+                &filepath,
+                &upload,
+                file_storage.storage_type(),
+            )
+            .await
+            {
+                Ok(inserted) => inserted,
+                Err(e) => {
+                    self.discard(file_storage.storage_type(), &filepath).await?;
+                    return Err(e);
+                }
+            };
+
+            // metadata and thumbnails are extras, the print file is usable without them
+            let metadata_service = PrintFileMetadataServiceImpl::new(self.pool.clone());
+            let mesh = analyze_mesh(&*file_storage, &filepath, upload.file_type, upload.size).await;
+            if let Some(mesh) = mesh {
+                let metadata = PrintFileMetadataDbModel::from_mesh(&inserted.uuid, mesh);
+                if metadata_service.add(&metadata).await.is_err() {
+                    warn!(
+                        "print file {} is stored without mesh metadata",
+                        inserted.uuid
+                    );
+                }
+            }
+            if let Some((metadata, thumbnails)) = upload.gcode {
+                let metadata = PrintFileMetadataDbModel::from_gcode(&inserted.uuid, metadata);
+                if metadata_service.add(&metadata).await.is_err() {
+                    warn!(
+                        "print file {} is stored without G-code metadata",
+                        inserted.uuid
+                    );
+                }
+
+                let thumbnail_service = PrintFileThumbnailServiceImpl::new(
                     self.pool.clone(),
-                    user_uuid,
-                    filename,
-                    &filepath,
-                    filesize,
-                    &sha256,
-                )
-                .await?,
-            );
+                    self.file_storages.clone(),
+                );
+                for thumbnail in thumbnails {
+                    let added = thumbnail_service
+                        .add(user_uuid, &inserted.uuid, thumbnail)
+                        .await;
+                    if added.is_err() {
+                        warn!("print file {} is stored without a thumbnail", inserted.uuid);
+                    }
+                }
+            }
+            printfile = Some(inserted);
         }
 
         match printfile {
@@ -92,7 +231,12 @@ impl PrintFileService for PrintFileServiceImpl {
         }
     }
 
+    /// Deletes the print file with its metadata and thumbnails, the stored files are removed
+    /// after the rows so a failed removal can't leave a row without its file behind.
+    /// A stored file that another print file still points to is kept
     async fn delete(&self, user_uuid: &str, file_uuid: &str) -> Result<bool, AppError> {
+        let printfile = self.get_by_uuid(user_uuid, file_uuid).await?;
+
         let sql = Query::delete()
             .from_table(PrintFile::Table)
             .and_where(Expr::col(PrintFile::UserUuid).eq(user_uuid))
@@ -100,13 +244,22 @@ impl PrintFileService for PrintFileServiceImpl {
             .to_string(MysqlQueryBuilder);
 
         let mut conn = self.pool.acquire().await.unwrap();
-        match conn.execute(&*sql).await {
-            Ok(_) => Ok(true),
-            Err(e) => {
-                error!("Error deleting printfile: {}", e);
-                Err(AppError::InternalServer)
-            }
+        if let Err(e) = conn.execute(&*sql).await {
+            error!("Error deleting printfile: {}", e);
+            return Err(AppError::InternalServer);
         }
+
+        PrintFileMetadataServiceImpl::new(self.pool.clone())
+            .delete(&printfile.uuid)
+            .await?;
+        PrintFileThumbnailServiceImpl::new(self.pool.clone(), self.file_storages.clone())
+            .delete(&printfile.uuid, &printfile.file_storage_type)
+            .await?;
+        StorageDeletionServiceImpl::new(self.pool.clone(), self.file_storages.clone())
+            .delete_file(&printfile.file_storage_type, &printfile.path)
+            .await?;
+
+        Ok(true)
     }
 
     async fn get_all(&self, user_uuid: &str) -> Result<Vec<PrintFileDbModel>, AppError> {
@@ -163,31 +316,283 @@ impl PrintFileService for PrintFileServiceImpl {
         Ok(printfile)
     }
 
-    async fn download(&self, user_uuid: &str, file_uuid: &str) -> Result<Vec<u8>, AppError> {
-        let printfile = self.get_by_uuid(user_uuid, file_uuid).await?;
-        Ok(retrieve_file(&printfile.path).await?)
+    /// Streams the file, or the range of it, from the file storage it was written to
+    /// without reading it into memory
+    async fn download_stream(
+        &self,
+        printfile: &PrintFileDbModel,
+        range: Option<ByteRange>,
+    ) -> Result<ByteStream<'static>, AppError> {
+        self.file_storages
+            .get(&printfile.file_storage_type)?
+            .retrieve_stream(&printfile.path, range)
+            .await
+    }
+
+    /// Print files of all users stored in the file storage
+    async fn get_by_storage_type(
+        &self,
+        file_storage_type: &str,
+    ) -> Result<Vec<PrintFileDbModel>, AppError> {
+        let sql = Query::select()
+            .columns(PRINTFILE_SELECT_COLUMNS)
+            .from(PrintFile::Table)
+            .and_where(Expr::col(PrintFile::FileStorageType).eq(file_storage_type))
+            .to_string(MysqlQueryBuilder);
+
+        match sqlx::query(&sql).fetch_all(&*self.pool).await {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| {
+                    PrintFileDbModel::from_row(row)
+                        .expect("Error converting row to PrintFileDbModel")
+                })
+                .collect()),
+            Err(e) => {
+                error!("Error retrieving printfiles: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Records that the file was moved to another file storage
+    async fn update_storage(
+        &self,
+        file_uuid: &str,
+        file_storage_type: &str,
+        path: &str,
+    ) -> Result<(), AppError> {
+        let sql = Query::update()
+            .table(PrintFile::Table)
+            .values([
+                (PrintFile::FileStorageType, file_storage_type.into()),
+                (PrintFile::Path, path.into()),
+            ])
+            .and_where(Expr::col(PrintFile::Uuid).eq(file_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error updating printfile: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
+
+/// Reads the next part of the multipart body, a truncated or malformed body is rejected
+async fn next_field(multipart_file: &mut Multipart) -> Result<Option<Field<'_>>, AppError> {
+    multipart_file
+        .next_field()
+        .await
+        .map_err(|e| AppError::Validation {
+            messages: format!("Invalid multipart body: {}", e),
+            status: StatusCode::BAD_REQUEST,
+        })
+}
+
+/// Inspects the chunks of an upload while they are streamed to the file storage.
+/// Nothing but the start of the file is kept, meshes are analyzed once the file is stored.
+/// The stream is aborted as soon as the file turns out to be of a type the policy rejects
+struct UploadInspector<'a> {
+    policy: &'a FileTypePolicyDbModel,
+    filename: &'a str,
+    checked: bool,
+    rejected: Option<AppError>,
+    hasher: Sha256,
+    sniffer: FileSniffer,
+    gcode: GcodeScanner,
+}
+
+/// What was learned about an uploaded file
+struct InspectedUpload {
+    file_type: FileType,
+    size: usize,
+    sha256: String,
+    gcode: Option<(GcodeMetadata, Vec<GcodeThumbnail>)>,
+}
+
+impl<'a> UploadInspector<'a> {
+    fn new(policy: &'a FileTypePolicyDbModel, filename: &'a str) -> Self {
+        UploadInspector {
+            policy,
+            filename,
+            checked: false,
+            rejected: None,
+            hasher: Sha256::new(),
+            sniffer: FileSniffer::new(),
+            gcode: GcodeScanner::new(),
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.hasher.update(chunk);
+        self.sniffer.feed(chunk);
+        self.gcode.feed(chunk);
+
+        if !self.checked {
+            if let Some(file_type) = self
+                .sniffer
+                .settled_file_type(self.filename, MAX_UPLOAD_SIZE)
+            {
+                self.checked = true;
+                if let Err(e) = self.policy.check(file_type) {
+                    self.rejected = Some(e);
+                    return Err(io::Error::other("file type is not allowed"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> InspectedUpload {
+        let file_type = self.sniffer.file_type(self.filename);
+
+        InspectedUpload {
+            file_type,
+            size: self.sniffer.size(),
+            sha256: format!("{:x}", self.hasher.finalize()),
+            gcode: (file_type == FileType::Gcode).then(|| self.gcode.finish()),
+        }
+    }
+}
+
+/// Analyzes the mesh of a stored model file. STL and OBJ files are streamed through the
+/// mesh reader, of 3MF packages only the central directory and the model entry are read.
+/// Parsing and analyzing run on the blocking threads. Files that can't be analyzed get no
+/// mesh metadata
+async fn analyze_mesh(
+    file_storage: &dyn FileStorageStrategy,
+    filepath: &str,
+    file_type: FileType,
+    size: usize,
+) -> Option<MeshAnalysis> {
+    let mesh = match file_type {
+        FileType::Stl => read_mesh(file_storage, filepath, MeshReader::stl(size)).await,
+        FileType::Obj => read_mesh(file_storage, filepath, MeshReader::obj()).await,
+        FileType::ThreeMf => read_3mf_mesh(file_storage, filepath, size).await,
+        _ => None,
+    };
+    blocking(move || mesh.map(|mesh| mesh.analyze())).await?
+}
+
+/// Runs CPU heavy mesh work off the async workers, so other requests and the websockets
+/// aren't stalled while a large mesh is processed
+async fn blocking<T: Send + 'static>(work: impl FnOnce() -> T + Send + 'static) -> Option<T> {
+    match tokio::task::spawn_blocking(work).await {
+        Ok(result) => Some(result),
+        Err(e) => {
+            error!("Error in the mesh analysis: {}", e);
+            None
+        }
+    }
+}
+
+async fn read_mesh(
+    file_storage: &dyn FileStorageStrategy,
+    filepath: &str,
+    mut reader: MeshReader,
+) -> Option<Mesh> {
+    let mut stream = match file_storage.retrieve_stream(filepath, None).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!("Error reading {} for the mesh analysis: {}", filepath, e);
+            return None;
+        }
+    };
+    // chunks are collected into batches, so the reader isn't moved to a blocking thread per chunk
+    let mut batch = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => batch.extend_from_slice(&chunk),
+            Err(e) => {
+                warn!("Error reading {} for the mesh analysis: {}", filepath, e);
+                return None;
+            }
+        }
+        if batch.len() >= MESH_BATCH_SIZE {
+            reader = feed_mesh(reader, std::mem::take(&mut batch)).await?;
+        }
+    }
+    reader = feed_mesh(reader, batch).await?;
+    blocking(move || reader.finish()).await?
+}
+
+async fn feed_mesh(mut reader: MeshReader, data: Vec<u8>) -> Option<MeshReader> {
+    blocking(move || {
+        reader.feed(&data);
+        reader
+    })
+    .await
+}
+
+/// Reads the model of a 3MF package with range reads, the package isn't read as a whole
+async fn read_3mf_mesh(
+    file_storage: &dyn FileStorageStrategy,
+    filepath: &str,
+    size: usize,
+) -> Option<Mesh> {
+    let read =
+        |offset: usize, length: usize| read_part(file_storage, filepath, size, offset, length);
+
+    let end_start = size.saturating_sub(END_SEARCH_SIZE);
+    let directory = central_directory(&read(end_start, size - end_start).await?)?;
+    let entries =
+        parse_central_directory(&directory, &read(directory.offset, directory.size).await?)?;
+    let entry = find_entry(&entries, THREEMF_MODEL_PATH)?;
+    let local_header = read(entry.local_header_offset(), LOCAL_HEADER_SIZE).await?;
+    let data = read(data_offset(entry, &local_header)?, entry.compressed_size()).await?;
+    blocking(move || {
+        let entry = find_entry(&entries, THREEMF_MODEL_PATH)?;
+        parse_3mf_model(&decompress(entry, &data)?)
+    })
+    .await?
+}
+
+/// Reads length bytes from the offset, None if they aren't inside the file or can't be read
+async fn read_part(
+    file_storage: &dyn FileStorageStrategy,
+    filepath: &str,
+    size: usize,
+    offset: usize,
+    length: usize,
+) -> Option<Vec<u8>> {
+    let end = offset
+        .checked_add(length)
+        .filter(|end| length > 0 && *end <= size)?;
+    let range = ByteRange {
+        start: offset as u64,
+        end: end as u64 - 1,
+    };
+    match file_storage.retrieve_range(filepath, range).await {
+        Ok(data) => Some(data),
+        Err(e) => {
+            warn!("Error reading {} for the mesh analysis: {}", filepath, e);
+            None
+        }
     }
 }
 
 //TODO: ask for overwrite
 async fn insert_printfile(
     pool: Arc<Pool<MySql>>,
+    uuid: &str,
     user_uuid: &str,
     filename: &str,
     filepath: &str,
-    filesize: i32,
-    sha256: &str,
+    upload: &InspectedUpload,
+    file_storage_type: &str,
 ) -> Result<PrintFileDbModel, AppError> {
-    let file_storage_type = env::var("FILESTORAGE_TYPE").expect("FILESTORAGE_TYPE must be set!");
-
     let printfile_model = PrintFileDbModel {
-        uuid: Uuid::new_v4().to_string(),
+        uuid: uuid.to_string(),
         user_uuid: user_uuid.to_string(),
         name: filename.to_string(),
         path: filepath.to_string(),
-        size: filesize.to_owned(),
-        checksum: sha256.to_string(),
-        file_type: FileType::Gcode.to_string(),
+        size: upload.size as i64,
+        checksum: upload.sha256.to_string(),
+        file_type: upload.file_type.to_string(),
         file_storage_type: file_storage_type.to_string(),
         created_at: Utc::now().timestamp().to_string(),
     };
@@ -229,3 +634,55 @@ async fn insert_printfile(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::zip::stored_archive;
+    use crate::infra::strategies::memory_file_strategy::MemoryFileStrategy;
+
+    /// Tetrahedron with the corners (0, 0, 0), (10, 0, 0), (0, 10, 0) and (0, 0, 10)
+    const TETRAHEDRON_OBJ: &str =
+        "v 0 0 0\nv 10 0 0\nv 0 10 0\nv 0 0 10\nf 1 3 2\nf 1 2 4\nf 1 4 3\nf 2 3 4\n";
+    const TETRAHEDRON_3MF_MODEL: &str = r#"<model><resources><object id="1"><mesh>
+        <vertices><vertex x="0" y="0" z="0"/><vertex x="10" y="0" z="0"/>
+        <vertex x="0" y="10" z="0"/><vertex x="0" y="0" z="10"/></vertices>
+        <triangles><triangle v1="0" v2="2" v3="1"/><triangle v1="0" v2="1" v3="3"/>
+        <triangle v1="0" v2="3" v3="2"/><triangle v1="1" v2="2" v3="3"/></triangles>
+        </mesh></object></resources></model>"#;
+
+    #[tokio::test]
+    async fn test_analyze_mesh() {
+        let storage = MemoryFileStrategy::new();
+
+        let obj = TETRAHEDRON_OBJ.as_bytes();
+        let path = storage.write_file("user", "part.obj", obj).await.unwrap();
+        let analysis = analyze_mesh(&storage, &path, FileType::Obj, obj.len())
+            .await
+            .unwrap();
+        assert_eq!(analysis.triangle_count, 4);
+        assert!((analysis.volume - 1000.0 / 6.0).abs() < 1e-9);
+        assert!(analysis.watertight);
+
+        // only the end, the central directory and the model entry of the package are read
+        let threemf = stored_archive(THREEMF_MODEL_PATH, TETRAHEDRON_3MF_MODEL.as_bytes());
+        let path = storage
+            .write_file("user", "part.3mf", &threemf)
+            .await
+            .unwrap();
+        let analysis = analyze_mesh(&storage, &path, FileType::ThreeMf, threemf.len())
+            .await
+            .unwrap();
+        assert_eq!(analysis.triangle_count, 4);
+        assert!(analysis.watertight);
+
+        assert_eq!(
+            analyze_mesh(&storage, &path, FileType::Gcode, threemf.len()).await,
+            None
+        );
+        assert_eq!(
+            analyze_mesh(&storage, "user/missing.stl", FileType::Stl, 84).await,
+            None
+        );
+    }
+}
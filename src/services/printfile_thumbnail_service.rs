@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use sea_query::{Expr, MysqlQueryBuilder, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::common::app_error::AppError;
+use crate::common::gcode::GcodeThumbnail;
+use crate::infra::filestorage::FileStorages;
+use crate::models::printfile_thumbnail::{PrintFileThumbnail, PrintFileThumbnailDbModel};
+use crate::services::storage_deletion_service::{
+    StorageDeletionService, StorageDeletionServiceImpl,
+};
+
+#[async_trait]
+pub trait PrintFileThumbnailService {
+    async fn add(
+        &self,
+        user_uuid: &str,
+        print_file_uuid: &str,
+        thumbnail: GcodeThumbnail,
+    ) -> Result<PrintFileThumbnailDbModel, AppError>;
+    async fn get_by_printfile(
+        &self,
+        print_file_uuid: &str,
+    ) -> Result<Vec<PrintFileThumbnailDbModel>, AppError>;
+    async fn download(
+        &self,
+        file_storage_type: &str,
+        thumbnail: &PrintFileThumbnailDbModel,
+    ) -> Result<Vec<u8>, AppError>;
+    async fn update_path(&self, uuid: &str, path: &str) -> Result<(), AppError>;
+    async fn delete(&self, print_file_uuid: &str, file_storage_type: &str) -> Result<(), AppError>;
+}
+
+pub struct PrintFileThumbnailServiceImpl {
+    pool: Arc<Pool<MySql>>,
+    file_storages: Arc<FileStorages>,
+}
+
+impl PrintFileThumbnailServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>, file_storages: Arc<FileStorages>) -> Self {
+        PrintFileThumbnailServiceImpl {
+            pool,
+            file_storages,
+        }
+    }
+}
+
+const THUMBNAIL_COLUMNS: [PrintFileThumbnail; 6] = [
+    PrintFileThumbnail::Uuid,
+    PrintFileThumbnail::PrintFileUuid,
+    PrintFileThumbnail::Width,
+    PrintFileThumbnail::Height,
+    PrintFileThumbnail::Format,
+    PrintFileThumbnail::Path,
+];
+
+#[async_trait]
+impl PrintFileThumbnailService for PrintFileThumbnailServiceImpl {
+    /// Stores the image next to the print file in the current file storage and records it
+    async fn add(
+        &self,
+        user_uuid: &str,
+        print_file_uuid: &str,
+        thumbnail: GcodeThumbnail,
+    ) -> Result<PrintFileThumbnailDbModel, AppError> {
+        let filename = format!(
+            "{}_thumbnail_{}x{}.{}",
+            print_file_uuid,
+            thumbnail.width,
+            thumbnail.height,
+            thumbnail.format.extension()
+        );
+        let file_storage = self.file_storages.current();
+        let path = file_storage
+            .write_file(user_uuid, &filename, &thumbnail.data)
+            .await?;
+        info!("stored thumbnail {}", path);
+
+        let thumbnail_model = PrintFileThumbnailDbModel {
+            uuid: Uuid::new_v4().to_string(),
+            print_file_uuid: print_file_uuid.to_string(),
+            width: thumbnail.width as i32,
+            height: thumbnail.height as i32,
+            format: thumbnail.format.extension().to_string(),
+            path,
+        };
+
+        let sql = Query::insert()
+            .into_table(PrintFileThumbnail::Table)
+            .columns(THUMBNAIL_COLUMNS)
+            .values_panic([
+                thumbnail_model.uuid.to_string().into(),
+                thumbnail_model.print_file_uuid.to_string().into(),
+                thumbnail_model.width.into(),
+                thumbnail_model.height.into(),
+                thumbnail_model.format.to_string().into(),
+                thumbnail_model.path.to_string().into(),
+            ])
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(thumbnail_model),
+            Err(e) => {
+                error!("Error inserting printfile thumbnail: {}", e);
+                // nothing points to the image without the row
+                let _ = file_storage.delete_file(&thumbnail_model.path).await;
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    async fn get_by_printfile(
+        &self,
+        print_file_uuid: &str,
+    ) -> Result<Vec<PrintFileThumbnailDbModel>, AppError> {
+        let sql = Query::select()
+            .columns(THUMBNAIL_COLUMNS)
+            .from(PrintFileThumbnail::Table)
+            .and_where(Expr::col(PrintFileThumbnail::PrintFileUuid).eq(print_file_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        match sqlx::query(&sql).fetch_all(&*self.pool).await {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| {
+                    PrintFileThumbnailDbModel::from_row(row)
+                        .expect("Error converting row to PrintFileThumbnailDbModel")
+                })
+                .collect()),
+            Err(e) => {
+                error!("Error retrieving printfile thumbnails: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Reads the image from the file storage of its print file
+    async fn download(
+        &self,
+        file_storage_type: &str,
+        thumbnail: &PrintFileThumbnailDbModel,
+    ) -> Result<Vec<u8>, AppError> {
+        self.file_storages
+            .get(file_storage_type)?
+            .retrieve_file(&thumbnail.path)
+            .await
+    }
+
+    async fn update_path(&self, uuid: &str, path: &str) -> Result<(), AppError> {
+        let sql = Query::update()
+            .table(PrintFileThumbnail::Table)
+            .values([(PrintFileThumbnail::Path, path.into())])
+            .and_where(Expr::col(PrintFileThumbnail::Uuid).eq(uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+        match conn.execute(&*sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error updating printfile thumbnail: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Deletes the thumbnails of the print file and their images
+    async fn delete(&self, print_file_uuid: &str, file_storage_type: &str) -> Result<(), AppError> {
+        let thumbnails = self.get_by_printfile(print_file_uuid).await?;
+
+        let sql = Query::delete()
+            .from_table(PrintFileThumbnail::Table)
+            .and_where(Expr::col(PrintFileThumbnail::PrintFileUuid).eq(print_file_uuid))
+            .to_string(MysqlQueryBuilder);
+
+        let mut conn = self.pool.acquire().await.unwrap();
+        if let Err(e) = conn.execute(&*sql).await {
+            error!("Error deleting printfile thumbnails: {}", e);
+            return Err(AppError::InternalServer);
+        }
+
+        let storage_deletion_service =
+            StorageDeletionServiceImpl::new(self.pool.clone(), self.file_storages.clone());
+        for thumbnail in thumbnails {
+            storage_deletion_service
+                .delete_file(file_storage_type, &thumbnail.path)
+                .await?;
+        }
+        Ok(())
+    }
+}
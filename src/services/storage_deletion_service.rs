@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+use sea_query::{Expr, MysqlQueryBuilder, Order, Query};
+use sqlx::{Executor, FromRow, MySql, Pool};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::common::app_error::AppError;
+use crate::infra::filestorage::FileStorages;
+use crate::models::printfile::PrintFile;
+use crate::models::storage_deletion::{retry_delay, StorageDeletion, StorageDeletionDbModel};
+
+/// Number of queued deletions retried per run
+const RETRY_BATCH_SIZE: u64 = 100;
+
+#[async_trait]
+pub trait StorageDeletionService {
+    async fn delete_file(&self, file_storage_type: &str, path: &str) -> Result<(), AppError>;
+    async fn get_due(&self, now: i64) -> Result<Vec<StorageDeletionDbModel>, AppError>;
+    async fn retry(&self, deletion: &StorageDeletionDbModel) -> Result<bool, AppError>;
+}
+
+pub struct StorageDeletionServiceImpl {
+    pool: Arc<Pool<MySql>>,
+    file_storages: Arc<FileStorages>,
+}
+
+impl StorageDeletionServiceImpl {
+    pub fn new(pool: Arc<Pool<MySql>>, file_storages: Arc<FileStorages>) -> Self {
+        StorageDeletionServiceImpl {
+            pool,
+            file_storages,
+        }
+    }
+
+    /// Deletes the file from the file storage, a file that is already gone counts as deleted
+    async fn try_delete(&self, file_storage_type: &str, path: &str) -> Result<(), AppError> {
+        let file_storage = self.file_storages.get(file_storage_type)?;
+        match file_storage.delete_file(path).await {
+            Err(AppError::PrintFile {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }) => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Whether a print file still points to the file. Print files uploaded before every upload
+    /// got its own key can share one, the file is kept until the last of them is deleted
+    async fn is_referenced(&self, file_storage_type: &str, path: &str) -> Result<bool, AppError> {
+        let sql = Query::select()
+            .column(PrintFile::Uuid)
+            .from(PrintFile::Table)
+            .and_where(Expr::col(PrintFile::FileStorageType).eq(file_storage_type))
+            .and_where(Expr::col(PrintFile::Path).eq(path))
+            .limit(1)
+            .to_string(MysqlQueryBuilder);
+
+        match sqlx::query(&sql).fetch_optional(&*self.pool).await {
+            Ok(row) => Ok(row.is_some()),
+            Err(e) => {
+                error!("Error checking the print files of {}: {}", path, e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    async fn execute(&self, sql: &str, action: &str) -> Result<(), AppError> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        match conn.execute(sql).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Error {} storage deletion: {}", action, e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+}
+
+const STORAGE_DELETION_COLUMNS: [StorageDeletion; 7] = [
+    StorageDeletion::Uuid,
+    StorageDeletion::Path,
+    StorageDeletion::FileStorageType,
+    StorageDeletion::Attempts,
+    StorageDeletion::LastError,
+    StorageDeletion::NextAttemptAt,
+    StorageDeletion::CreatedAt,
+];
+
+#[async_trait]
+impl StorageDeletionService for StorageDeletionServiceImpl {
+    /// Deletes the file from the file storage, if that fails the deletion is queued for a retry.
+    /// Files a print file still points to are kept
+    async fn delete_file(&self, file_storage_type: &str, path: &str) -> Result<(), AppError> {
+        if self.is_referenced(file_storage_type, path).await? {
+            info!("keeping {}, another print file points to it", path);
+            return Ok(());
+        }
+        let failure = match self.try_delete(file_storage_type, path).await {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        warn!("queueing deletion of {}: {}", path, failure);
+
+        let now = Utc::now().timestamp();
+        let deletion = StorageDeletionDbModel {
+            uuid: Uuid::new_v4().to_string(),
+            path: path.to_string(),
+            file_storage_type: file_storage_type.to_string(),
+            attempts: 1,
+            last_error: Some(failure.to_string()),
+            next_attempt_at: now + retry_delay(1),
+            created_at: now.to_string(),
+        };
+
+        let sql = Query::insert()
+            .into_table(StorageDeletion::Table)
+            .columns(STORAGE_DELETION_COLUMNS)
+            .values_panic([
+                deletion.uuid.to_string().into(),
+                deletion.path.to_string().into(),
+                deletion.file_storage_type.to_string().into(),
+                deletion.attempts.into(),
+                deletion.last_error.clone().into(),
+                deletion.next_attempt_at.into(),
+                deletion.created_at.to_string().into(),
+            ])
+            .to_string(MysqlQueryBuilder);
+
+        self.execute(&sql, "inserting").await
+    }
+
+    /// Queued deletions whose next attempt is due, oldest first
+    async fn get_due(&self, now: i64) -> Result<Vec<StorageDeletionDbModel>, AppError> {
+        let sql = Query::select()
+            .columns(STORAGE_DELETION_COLUMNS)
+            .from(StorageDeletion::Table)
+            .and_where(Expr::col(StorageDeletion::NextAttemptAt).lte(now))
+            .order_by(StorageDeletion::NextAttemptAt, Order::Asc)
+            .limit(RETRY_BATCH_SIZE)
+            .to_string(MysqlQueryBuilder);
+
+        match sqlx::query(&sql).fetch_all(&*self.pool).await {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| {
+                    StorageDeletionDbModel::from_row(row)
+                        .expect("Error converting row to StorageDeletionDbModel")
+                })
+                .collect()),
+            Err(e) => {
+                error!("Error retrieving storage deletions: {}", e);
+                Err(AppError::InternalServer)
+            }
+        }
+    }
+
+    /// Deletes the file again, the queued deletion is removed once the file is gone or a print
+    /// file points to it again, otherwise the next attempt is scheduled. Returns whether the
+    /// file is gone
+    async fn retry(&self, deletion: &StorageDeletionDbModel) -> Result<bool, AppError> {
+        let dequeue = Query::delete()
+            .from_table(StorageDeletion::Table)
+            .and_where(Expr::col(StorageDeletion::Uuid).eq(&deletion.uuid))
+            .to_string(MysqlQueryBuilder);
+        if self
+            .is_referenced(&deletion.file_storage_type, &deletion.path)
+            .await?
+        {
+            info!(
+                "dropping the deletion of {}, a print file points to it",
+                deletion.path
+            );
+            self.execute(&dequeue, "deleting").await?;
+            return Ok(false);
+        }
+
+        let failure = match self
+            .try_delete(&deletion.file_storage_type, &deletion.path)
+            .await
+        {
+            Ok(_) => {
+                info!(
+                    "deleted {} after {} failed attempts",
+                    deletion.path, deletion.attempts
+                );
+                self.execute(&dequeue, "deleting").await?;
+                return Ok(true);
+            }
+            Err(e) => e,
+        };
+
+        let attempts = deletion.attempts + 1;
+        warn!(
+            "attempt {} to delete {} failed: {}",
+            attempts, deletion.path, failure
+        );
+        let sql = Query::update()
+            .table(StorageDeletion::Table)
+            .values([
+                (StorageDeletion::Attempts, attempts.into()),
+                (StorageDeletion::LastError, failure.to_string().into()),
+                (
+                    StorageDeletion::NextAttemptAt,
+                    (Utc::now().timestamp() + retry_delay(attempts)).into(),
+                ),
+            ])
+            .and_where(Expr::col(StorageDeletion::Uuid).eq(&deletion.uuid))
+            .to_string(MysqlQueryBuilder);
+        self.execute(&sql, "updating").await?;
+
+        Ok(false)
+    }
+}